@@ -1,3 +1,4 @@
 pub mod db;
 pub mod models;
-pub mod utils;
\ No newline at end of file
+pub mod utils;
+pub mod migrations;
\ No newline at end of file
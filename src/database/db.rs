@@ -8,7 +8,7 @@ pub struct Credentials {
     pub database: String,
 }
 
-pub async fn connect_to_database<'a>(hostname : &'a str, credentials : Credentials) -> Result<Surreal<Client>, surrealdb::Error>{
+pub async fn connect_to_database(hostname : &str, credentials : Credentials) -> Result<Surreal<Client>, surrealdb::Error>{
     let db = Surreal::new::<Wss>(hostname).await?;
 
     db.signin(Namespace {
@@ -0,0 +1,243 @@
+use surrealdb::engine::remote::ws::Client;
+use surrealdb::{Error, Surreal};
+
+use crate::database::models::stock_reservation::hold_until;
+use crate::money::minor_unit_exponent;
+
+/// Turns amounts stored as plain numbers into `Money` objects in `currency`,
+/// the one every price was kept in before. Numbers are rounded to the nearest
+/// minor unit, which only drops the float noise of the old `f32` prices.
+/// Percentage coupons get their percent in basis points.
+///
+/// Safe to run on every start, converted records are left alone.
+pub async fn money_amounts(currency: &str, db: &Surreal<Client>) -> Result<(), Error> {
+    let query_str = r#"
+    BEGIN TRANSACTION;
+
+    // Products. Fields are FLEXIBLE while converting, or the new objects would come out empty
+    DEFINE FIELD OVERWRITE price ON TABLE Product FLEXIBLE TYPE number | object PERMISSIONS FULL;
+    UPDATE Product SET price = { amount: <int> math::round(price * $scale), currency: $currency }
+        WHERE type::is::number(price);
+    DEFINE FIELD OVERWRITE price ON TABLE Product TYPE object PERMISSIONS FULL;
+    DEFINE FIELD OVERWRITE price.amount ON TABLE Product TYPE int ASSERT $value >= 0 PERMISSIONS FULL;
+    DEFINE FIELD OVERWRITE price.currency ON TABLE Product TYPE string PERMISSIONS FULL;
+
+    // Orders, `subtotal` tells converted ones apart. Nested fields are kept by a FLEXIBLE `lines`
+    DEFINE FIELD OVERWRITE lines ON TABLE Order FLEXIBLE TYPE array<object> PERMISSIONS FULL;
+    DEFINE FIELD OVERWRITE lines.*.unit_price ON TABLE Order TYPE number | object PERMISSIONS FULL;
+    DEFINE FIELD OVERWRITE lines.*.line_total ON TABLE Order TYPE number | object PERMISSIONS FULL;
+    DEFINE FIELD OVERWRITE subtotal ON TABLE Order FLEXIBLE TYPE number | object PERMISSIONS FULL;
+    DEFINE FIELD OVERWRITE discount ON TABLE Order FLEXIBLE TYPE number | object PERMISSIONS FULL;
+    DEFINE FIELD OVERWRITE total ON TABLE Order FLEXIBLE TYPE number | object PERMISSIONS FULL;
+    UPDATE Order SET
+        lines = (SELECT *,
+                { amount: <int> math::round(unit_price * $scale), currency: $currency } AS unit_price,
+                { amount: <int> math::round(line_total * $scale), currency: $currency } AS line_total
+            FROM $this.lines),
+        coupon = IF coupon = NONE { NONE } ELSE { {
+            code: coupon.code,
+            kind: coupon.kind,
+            free_shipping: coupon.free_shipping,
+            discount: { amount: <int> math::round(coupon.discount * $scale), currency: $currency },
+            lines: (SELECT *, { amount: <int> math::round(amount * $scale), currency: $currency } AS amount
+                FROM $this.coupon.lines)
+        } },
+        subtotal = { amount: <int> math::round(subtotal * $scale), currency: $currency },
+        discount = { amount: <int> math::round(discount * $scale), currency: $currency },
+        total = { amount: <int> math::round(total * $scale), currency: $currency }
+        WHERE type::is::number(subtotal);
+    DEFINE FIELD OVERWRITE lines ON TABLE Order TYPE array<object> PERMISSIONS FULL;
+    DEFINE FIELD OVERWRITE lines.*.unit_price ON TABLE Order TYPE object PERMISSIONS FULL;
+    DEFINE FIELD OVERWRITE lines.*.unit_price.amount ON TABLE Order TYPE int PERMISSIONS FULL;
+    DEFINE FIELD OVERWRITE lines.*.unit_price.currency ON TABLE Order TYPE string PERMISSIONS FULL;
+    DEFINE FIELD OVERWRITE lines.*.line_total ON TABLE Order TYPE object PERMISSIONS FULL;
+    DEFINE FIELD OVERWRITE lines.*.line_total.amount ON TABLE Order TYPE int PERMISSIONS FULL;
+    DEFINE FIELD OVERWRITE lines.*.line_total.currency ON TABLE Order TYPE string PERMISSIONS FULL;
+    DEFINE FIELD OVERWRITE subtotal ON TABLE Order TYPE object PERMISSIONS FULL;
+    DEFINE FIELD OVERWRITE subtotal.amount ON TABLE Order TYPE int PERMISSIONS FULL;
+    DEFINE FIELD OVERWRITE subtotal.currency ON TABLE Order TYPE string PERMISSIONS FULL;
+    DEFINE FIELD OVERWRITE discount ON TABLE Order TYPE object PERMISSIONS FULL;
+    DEFINE FIELD OVERWRITE discount.amount ON TABLE Order TYPE int PERMISSIONS FULL;
+    DEFINE FIELD OVERWRITE discount.currency ON TABLE Order TYPE string PERMISSIONS FULL;
+    DEFINE FIELD OVERWRITE total ON TABLE Order TYPE object PERMISSIONS FULL;
+    DEFINE FIELD OVERWRITE total.amount ON TABLE Order TYPE int PERMISSIONS FULL;
+    DEFINE FIELD OVERWRITE total.currency ON TABLE Order TYPE string PERMISSIONS FULL;
+
+    // Payments kept their currency next to the amount
+    DEFINE FIELD OVERWRITE amount ON TABLE Payment FLEXIBLE TYPE number | object PERMISSIONS FULL;
+    UPDATE Payment SET amount = { amount: <int> math::round(amount * $scale), currency: currency ?? $currency }
+        WHERE type::is::number(amount);
+    DEFINE FIELD OVERWRITE amount ON TABLE Payment TYPE object READONLY;
+    DEFINE FIELD OVERWRITE amount.amount ON TABLE Payment TYPE int READONLY;
+    DEFINE FIELD OVERWRITE amount.currency ON TABLE Payment TYPE string READONLY;
+    DEFINE FIELD OVERWRITE currency ON TABLE Payment TYPE option<string> READONLY;
+
+    // Coupons, a fixed coupon's `value` becomes its `amount_off` and a percentage coupon's its `percent_bps`
+    DEFINE FIELD OVERWRITE min_cart_value ON TABLE Coupon FLEXIBLE TYPE option<number | object> PERMISSIONS FULL;
+    UPDATE Coupon SET amount_off = { amount: <int> math::round(value * $scale), currency: $currency }, value = 0
+        WHERE kind = 'fixed' AND type::is::number(value) AND value > 0;
+    UPDATE Coupon SET percent_bps = <int> math::round(value * 100), value = 0
+        WHERE kind = 'percentage' AND percent_bps = NONE AND value > 0;
+    UPDATE Coupon SET min_cart_value = IF min_cart_value > 0 {
+            { amount: <int> math::round(min_cart_value * $scale), currency: $currency }
+        } ELSE { NONE }
+        WHERE type::is::number(min_cart_value);
+    DEFINE FIELD OVERWRITE min_cart_value ON TABLE Coupon TYPE option<object> PERMISSIONS FULL;
+    DEFINE FIELD OVERWRITE min_cart_value.amount ON TABLE Coupon TYPE int PERMISSIONS FULL;
+    DEFINE FIELD OVERWRITE min_cart_value.currency ON TABLE Coupon TYPE string PERMISSIONS FULL;
+
+    COMMIT TRANSACTION;"#;
+
+    let resp = match db.query(query_str)
+        .bind(("scale", 10i64.pow(minor_unit_exponent(currency))))
+        .bind(("currency", currency.to_string()))
+        .await {
+        Ok(response) => response.check(),
+        Err(e) => Err(e)
+    };
+
+    match resp {
+        Ok(_) => {
+            println!("Money amounts migrated...");
+            Ok(())
+        },
+        Err(e) => {
+            println!("Money migration DB Error : {:?}",e);
+
+            Err(e)
+        }
+    }
+}
+
+/// Orders awaiting payment used to take their stock at checkout. Their stock
+/// is given back and held for `hold_secs` instead, like new orders do, so an
+/// abandoned one frees it.
+///
+/// Runs once, `migration:stock_reservations` marks it done.
+pub async fn stock_reservations(hold_secs: usize, db: &Surreal<Client>) -> Result<(), Error> {
+    let query_str = r#"
+    BEGIN TRANSACTION;
+
+    DEFINE TABLE IF NOT EXISTS migration SCHEMALESS;
+    IF !record::exists(migration:stock_reservations) {
+        FOR $order IN (SELECT id, lines FROM Order WHERE status = 'pending_payment') {
+            FOR $line IN $order.lines {
+                UPDATE $line.product SET stock_qty += $line.quantity;
+                CREATE stock_reservation CONTENT { product: $line.product, order: $order.id, quantity: $line.quantity, expires_at: $hold_until };
+            };
+        };
+        CREATE migration:stock_reservations SET at = time::now();
+    };
+
+    COMMIT TRANSACTION;"#;
+
+    let resp = match db.query(query_str)
+        .bind(("hold_until", hold_until(hold_secs)))
+        .await {
+        Ok(response) => response.check(),
+        Err(e) => Err(e)
+    };
+
+    match resp {
+        Ok(_) => {
+            println!("Stock reservations migrated...");
+            Ok(())
+        },
+        Err(e) => {
+            println!("Stock reservation migration DB Error : {:?}",e);
+
+            Err(e)
+        }
+    }
+}
+
+/// Stock used to be a bare number. Every product gets an opening `correction`
+/// movement for the stock it has, so its movements add up to it from here on.
+///
+/// Runs once, `migration:inventory_ledger` marks it done.
+pub async fn inventory_ledger(db: &Surreal<Client>) -> Result<(), Error> {
+    let query_str = r#"
+    BEGIN TRANSACTION;
+
+    DEFINE TABLE IF NOT EXISTS migration SCHEMALESS;
+    IF !record::exists(migration:inventory_ledger) {
+        FOR $product IN (SELECT id, sku, stock_qty FROM Product WHERE stock_qty != 0) {
+            CREATE inventory_movement CONTENT {
+                product: $product.id, sku: $product.sku, kind: 'correction', quantity: $product.stock_qty,
+                stock_after: $product.stock_qty, reason: 'Opening balance'
+            };
+        };
+        CREATE migration:inventory_ledger SET at = time::now();
+    };
+
+    COMMIT TRANSACTION;"#;
+
+    let resp = match db.query(query_str).await {
+        Ok(response) => response.check(),
+        Err(e) => Err(e)
+    };
+
+    match resp {
+        Ok(_) => {
+            println!("Inventory ledger opened...");
+            Ok(())
+        },
+        Err(e) => {
+            println!("Inventory ledger migration DB Error : {:?}",e);
+
+            Err(e)
+        }
+    }
+}
+
+/// Moves the single stock count of every product into a `MAIN` warehouse,
+/// created unless warehouses already exist, and ships earlier orders, holds
+/// and movements from it.
+///
+/// Runs once, `migration:warehouses` marks it done.
+pub async fn warehouses(db: &Surreal<Client>) -> Result<(), Error> {
+    let query_str = r#"
+    BEGIN TRANSACTION;
+
+    DEFINE TABLE IF NOT EXISTS migration SCHEMALESS;
+    IF !record::exists(migration:warehouses) {
+        IF array::len(SELECT id FROM Warehouse) = 0 {
+            CREATE Warehouse:MAIN CONTENT { code: 'MAIN', name: 'Main warehouse', active: true };
+        };
+        LET $main = (SELECT id, code FROM Warehouse ORDER BY code LIMIT 1)[0].id;
+
+        FOR $product IN (SELECT id, stock_qty FROM Product WHERE stock_qty > 0) {
+            UPSERT type::thing('warehouse_stock', [$main, $product.id]) CONTENT { warehouse: $main, product: $product.id, stock_qty: $product.stock_qty };
+        };
+        FOR $order IN (SELECT id, lines FROM Order WHERE allocations = NONE OR array::len(allocations) = 0) {
+            UPDATE $order.id SET allocations = (SELECT product, $main AS warehouse, quantity FROM $order.lines);
+        };
+        UPDATE stock_reservation SET warehouse = $main WHERE warehouse = NONE;
+
+        // Movements are read only, so the field is opened up just for this
+        DEFINE FIELD OVERWRITE warehouse ON TABLE inventory_movement TYPE option<record<Warehouse>>;
+        UPDATE inventory_movement SET warehouse = $main WHERE warehouse = NONE;
+        DEFINE FIELD OVERWRITE warehouse ON TABLE inventory_movement TYPE option<record<Warehouse>> READONLY;
+
+        CREATE migration:warehouses SET at = time::now();
+    };
+
+    COMMIT TRANSACTION;"#;
+
+    let resp = match db.query(query_str).await {
+        Ok(response) => response.check(),
+        Err(e) => Err(e)
+    };
+
+    match resp {
+        Ok(_) => {
+            println!("Stock moved into warehouses...");
+            Ok(())
+        },
+        Err(e) => {
+            println!("Warehouse migration DB Error : {:?}",e);
+
+            Err(e)
+        }
+    }
+}
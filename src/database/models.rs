@@ -1,7 +1,36 @@
 pub mod product;
+pub mod product_group;
 pub mod user;
 pub mod session_token;
+pub mod role;
+pub mod cart;
+pub mod order;
+pub mod order_event;
+pub mod payment;
+pub mod idempotency_key;
+pub mod coupon;
+pub mod exchange_rate;
+pub mod stock_reservation;
+pub mod inventory_movement;
+pub mod stock_subscription;
+pub mod notification;
+pub mod warehouse;
+pub mod address;
 
 pub use super::utils::DatabaseIO;
 pub use product::Product;
-pub use user::User;
\ No newline at end of file
+pub use product_group::ProductGroup;
+pub use user::User;
+pub use role::Role;
+pub use cart::Cart;
+pub use order::{Order, OrderStatus};
+pub use order_event::OrderEvent;
+pub use payment::Payment;
+pub use coupon::Coupon;
+pub use exchange_rate::ExchangeRate;
+pub use stock_reservation::StockReservation;
+pub use inventory_movement::InventoryMovement;
+pub use stock_subscription::StockSubscription;
+pub use notification::Notification;
+pub use warehouse::Warehouse;
+pub use address::Address;
\ No newline at end of file
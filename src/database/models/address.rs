@@ -0,0 +1,221 @@
+use rocket::serde::{Deserialize, Serialize};
+use surrealdb::engine::remote::ws::Client;
+use surrealdb::{Error, RecordId, Surreal};
+use surrealdb::Error::Api;
+use surrealdb::error::Api::Query;
+use surrealdb::sql::Datetime;
+
+use super::super::models::{DatabaseIO};
+use super::order::OrderAddress;
+use super::warehouse::is_valid_pincode;
+
+/// A delivery or billing address in a customer's address book. Orders copy
+/// the one chosen, so it may be edited or deleted afterwards.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Address {
+    #[serde(default)]
+    pub id: Option<RecordId>,
+    #[serde(default)]
+    pub user: Option<RecordId>,     // set from the session, never taken from the body
+    pub name: String,
+    pub phone: String,
+    pub line1: String,
+    #[serde(default)]
+    pub line2: Option<String>,
+    pub city: String,
+    pub state: String,
+    pub pincode: String,
+    #[serde(default = "default_country")]
+    pub country: String,            // ISO 3166 alpha-2 code
+    #[serde(default)]
+    pub default_shipping: bool,
+    #[serde(default)]
+    pub default_billing: bool,
+    #[serde(default)]
+    pub created_at: Option<Datetime>
+}
+
+fn default_country() -> String {
+    "IN".to_string()
+}
+
+impl DatabaseIO for Address {
+    type Model = Address;
+
+    fn table_name() -> &'static str {
+        "Address"
+    }
+
+    async fn init(db: &Surreal<Client>) -> Result<(), Error> {
+        let query_str = r#"
+        // Customers only ever reach their own addresses, the routes check this as well
+        DEFINE TABLE IF NOT EXISTS Address SCHEMAFULL
+            PERMISSIONS FOR select, create, update, delete WHERE user = $auth.id;
+        DEFINE FIELD IF NOT EXISTS user ON TABLE Address TYPE record<User> READONLY PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS name ON TABLE Address TYPE string PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS phone ON TABLE Address TYPE string PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS line1 ON TABLE Address TYPE string PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS line2 ON TABLE Address TYPE option<string> PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS city ON TABLE Address TYPE string PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS state ON TABLE Address TYPE string PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS pincode ON TABLE Address TYPE string PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS country ON TABLE Address TYPE string DEFAULT 'IN' PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS default_shipping ON TABLE Address TYPE bool DEFAULT false PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS default_billing ON TABLE Address TYPE bool DEFAULT false PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS created_at ON TABLE Address TYPE datetime DEFAULT time::now() READONLY PERMISSIONS FULL;
+
+        DEFINE INDEX IF NOT EXISTS addressUserIndex ON TABLE Address FIELDS user;
+
+        // Takes the default flags $address has off the other addresses of its user
+        DEFINE FUNCTION OVERWRITE fn::settle_default_addresses($address: record<Address>) {
+            IF $address.default_shipping {
+                UPDATE Address SET default_shipping = false WHERE user = $address.user AND id != $address;
+            };
+            IF $address.default_billing {
+                UPDATE Address SET default_billing = false WHERE user = $address.user AND id != $address;
+            };
+            RETURN SELECT * FROM ONLY $address;
+        };"#;
+
+        let resp = db.query(query_str).await;
+
+        match resp {
+            Ok(_) => {
+                println!("Address Table Initialized...");
+                Ok(())
+            },
+            Err(e) => {
+                println!("Addresses DB Error : {:?}",e);
+
+                Err(e)
+            }
+        }
+    }
+
+    async fn get_all(db: &Surreal<Client>) -> Vec<Self::Model> {
+        let query = db.query("SELECT * FROM Address").await;
+        let mut response = query.ok().unwrap();
+        response.take(0).
+            ok().unwrap()
+    }
+
+    /// Writes the address. A user's first address becomes both defaults, and
+    /// making one the default takes the flag off their others in the same
+    /// transaction, so there is only ever one of each.
+    async fn save(self, db: &Surreal<Client>) -> Result<Self::Model, Error> {
+        let (query, failure) = match self.id.clone() {
+            None => (db.query(r#"
+                BEGIN TRANSACTION;
+                LET $first = array::len(SELECT id FROM Address WHERE user = $address.user) = 0;
+                LET $saved = CREATE ONLY Address CONTENT $address;
+                IF $first {
+                    UPDATE $saved.id SET default_shipping = true, default_billing = true;
+                };
+                RETURN fn::settle_default_addresses($saved.id);
+                COMMIT TRANSACTION;"#), "Failed to create address"),
+            Some(id) => (db.query(r#"
+                BEGIN TRANSACTION;
+                UPDATE $id CONTENT $address;
+                RETURN fn::settle_default_addresses($id);
+                COMMIT TRANSACTION;"#).bind(("id", id)), "Failed to update address")
+        };
+        let mut response = query.bind(("address", self)).await?;
+
+        let errors = response.take_errors();
+        if let Some(e) = errors.into_values().find(|e| !e.to_string().contains("failed transaction")) {
+            return Err(e);
+        }
+        let address: Option<Address> = response.take(0)?;
+        address.ok_or(Api(Query(failure.to_string())))
+    }
+}
+
+impl Address {
+    /// Checks the fields a customer sends, returning every problem found.
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errors = Vec::new();
+
+        for (field, value) in [("name", &self.name), ("line1", &self.line1), ("city", &self.city), ("state", &self.state)] {
+            if value.trim().is_empty() {
+                errors.push(format!("{} must not be empty", field));
+            }
+        }
+        let digits = self.phone.strip_prefix('+').unwrap_or(&self.phone);
+        if !(10..=15).contains(&digits.len()) || !digits.chars().all(|c| c.is_ascii_digit()) {
+            errors.push("phone must be 10 to 15 digits, optionally after a +".to_string());
+        }
+        if self.country.len() != 2 || !self.country.chars().all(|c| c.is_ascii_uppercase()) {
+            errors.push("country must be a two letter code like IN".to_string());
+        }
+        if self.country == "IN" && !is_valid_pincode(&self.pincode) {
+            errors.push("pincode must be six digits".to_string());
+        } else if self.pincode.trim().is_empty() {
+            errors.push("pincode must not be empty".to_string());
+        }
+
+        if errors.is_empty() { Ok(()) } else { Err(errors) }
+    }
+
+    /// Addresses of `user`, the default shipping one first.
+    pub async fn for_user(user: &RecordId, db: &Surreal<Client>) -> Result<Vec<Address>, Error> {
+        let mut response = db.query("SELECT * FROM Address WHERE user = $user ORDER BY default_shipping DESC, created_at ASC")
+            .bind(("user", user.clone()))
+            .await?;
+        response.take(0)
+    }
+
+    /// `key` is the part of the address id after `Address:`.
+    pub async fn find_by_key(key: &str, db: &Surreal<Client>) -> Result<Option<Address>, Error> {
+        db.select(("Address", key)).await
+    }
+
+    pub async fn default_shipping(user: &RecordId, db: &Surreal<Client>) -> Result<Option<Address>, Error> {
+        let mut response = db.query("SELECT * FROM Address WHERE user = $user AND default_shipping = true LIMIT 1")
+            .bind(("user", user.clone()))
+            .await?;
+        let mut addresses: Vec<Address> = response.take(0)?;
+        Ok(addresses.pop())
+    }
+
+    /// Deletes the address. Its default flags move to the user's most recent
+    /// other address in the same transaction, so they keep a default.
+    pub async fn delete(self, db: &Surreal<Client>) -> Result<Option<Address>, Error> {
+        let Some(id) = self.id else {
+            return Ok(None);
+        };
+        let mut response = db.query(r#"
+            BEGIN TRANSACTION;
+            LET $deleted = (DELETE $id RETURN BEFORE)[0];
+            LET $next = (SELECT id, created_at FROM Address WHERE user = $deleted.user ORDER BY created_at DESC LIMIT 1)[0].id;
+            IF $next != NONE AND $deleted.default_shipping {
+                UPDATE $next SET default_shipping = true;
+            };
+            IF $next != NONE AND $deleted.default_billing {
+                UPDATE $next SET default_billing = true;
+            };
+            RETURN $deleted;
+            COMMIT TRANSACTION;"#)
+            .bind(("id", id))
+            .await?;
+
+        let errors = response.take_errors();
+        if let Some(e) = errors.into_values().find(|e| !e.to_string().contains("failed transaction")) {
+            return Err(e);
+        }
+        response.take(0)
+    }
+
+    /// What an order keeps of the address.
+    pub fn snapshot(&self) -> OrderAddress {
+        OrderAddress {
+            name: self.name.clone(),
+            phone: self.phone.clone(),
+            line1: self.line1.clone(),
+            line2: self.line2.clone(),
+            city: self.city.clone(),
+            state: self.state.clone(),
+            pincode: self.pincode.clone(),
+            country: self.country.clone()
+        }
+    }
+}
@@ -0,0 +1,331 @@
+use chrono::{Duration, Utc};
+use rocket::serde::{Deserialize, Serialize};
+use surrealdb::engine::remote::ws::Client;
+use surrealdb::{Error, RecordId, Surreal};
+use surrealdb::Error::Api;
+use surrealdb::error::Api::Query;
+use surrealdb::sql::Datetime;
+
+use super::super::models::{DatabaseIO};
+use super::Product;
+use super::product::PRODUCT_FIELDS;
+use super::coupon::{AppliedCoupon, Coupon};
+use crate::money::Money;
+
+/// Most units of one product a cart holds.
+pub const MAX_LINE_QUANTITY: u32 = 100;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CartItem {
+    pub product: RecordId,
+    pub quantity: u32
+}
+
+/// Items a user intends to buy. Prices are never stored here, they are
+/// looked up from `Product` whenever the cart is shown.
+///
+/// Guest carts have no `user`, they are found by the hash of an opaque cart
+/// token instead and expire after `expires_at`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Cart {
+    #[serde(default)]
+    pub id: Option<RecordId>,
+    #[serde(default)]
+    pub user: Option<RecordId>,
+    #[serde(default)]
+    pub guest_token_hash: Option<String>,
+    #[serde(default)]
+    pub items: Vec<CartItem>,
+    #[serde(default)]
+    pub coupon_code: Option<String>,
+    #[serde(default)]
+    pub updated_at: Option<Datetime>,
+    #[serde(default)]
+    pub expires_at: Option<Datetime>
+}
+
+impl DatabaseIO for Cart {
+    type Model = Cart;
+
+    fn table_name() -> &'static str {
+        "Cart"
+    }
+
+    async fn init(db: &Surreal<Client>) -> Result<(), Error> {
+        let query_str = r#"
+        DEFINE TABLE IF NOT EXISTS Cart SCHEMAFULL;
+        DEFINE FIELD IF NOT EXISTS user ON TABLE Cart TYPE record<User> PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS items ON TABLE Cart TYPE array<object> DEFAULT [] PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS items.*.product ON TABLE Cart TYPE record<Product> PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS items.*.quantity ON TABLE Cart TYPE int ASSERT $value > 0 PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS updated_at ON TABLE Cart TYPE datetime VALUE time::now() PERMISSIONS FULL;
+
+        // Guest carts
+        DEFINE FIELD OVERWRITE user ON TABLE Cart TYPE option<record<User>> PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS guest_token_hash ON TABLE Cart TYPE option<string> PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS expires_at ON TABLE Cart TYPE option<datetime> PERMISSIONS FULL;
+
+        DEFINE FIELD IF NOT EXISTS coupon_code ON TABLE Cart TYPE option<string> PERMISSIONS FULL;
+
+        // Unique indexes skip NONE, so any number of guest carts have no user and user carts no token
+        DEFINE INDEX IF NOT EXISTS cartUserIndex ON TABLE Cart FIELDS user UNIQUE;
+        DEFINE INDEX IF NOT EXISTS cartGuestIndex ON TABLE Cart FIELDS guest_token_hash UNIQUE;
+
+        // There is no TTL in SurrealDB, lookups skip expired guest carts and Cart::sweep drops them
+        DELETE Cart WHERE expires_at != NONE AND expires_at < time::now();"#;
+
+        let resp = db.query(query_str).await;
+
+        match resp {
+            Ok(_) => {
+                println!("Cart Table Initialized...");
+                Ok(())
+            },
+            Err(e) => {
+                println!("Carts DB Error : {:?}",e);
+
+                Err(e)
+            }
+        }
+    }
+
+    async fn get_all(db: &Surreal<Client>) -> Vec<Self::Model> {
+        let query = db.query("SELECT * FROM Cart").await;
+        let mut response = query.ok().unwrap();
+        response.take(0).
+            ok().unwrap()
+    }
+
+    async fn save(self, db: &Surreal<Client>) -> Result<Self::Model, Error> {
+        match self.id.clone() {
+            None => {
+                let cart : Option<Cart> = db.create("Cart").content(self.clone()).await?;
+                cart.ok_or(Api(Query("Failed to create cart".to_string())))
+            }
+            Some(id) => {
+                let cart :Option<Cart> = db.update(id).content(self.clone()).await?;
+                cart.ok_or(Api(Query("Failed to update cart".to_string())))
+            }
+        }
+    }
+}
+
+/// A cart item priced with the current `Product` data.
+#[derive(Debug, Serialize)]
+pub struct CartLine {
+    pub sku: String,
+    pub slug: String,
+    pub title: String,
+    pub img: String,
+    pub color: String,
+    pub size: String,
+    pub unit_price: Money,
+    pub quantity: u32,
+    pub line_total: Money,
+    pub available_qty: u32,
+    pub insufficient_stock: bool
+}
+
+/// A priced cart. A coupon that no longer applies, e.g. after items were
+/// removed, stays on the cart and `coupon_error` tells why it is not counted.
+#[derive(Debug, Serialize)]
+pub struct PricedCart {
+    pub lines: Vec<CartLine>,
+    pub item_count: u32,
+    pub subtotal: Money,
+    pub coupon: Option<AppliedCoupon>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub coupon_error: Option<String>,
+    pub discount: Money,
+    pub total: Money
+}
+
+impl Cart {
+    /// The user's cart, or a new empty (unsaved) one.
+    pub async fn for_user(user: &RecordId, db: &Surreal<Client>) -> Result<Cart, Error> {
+        let mut response = db.query("SELECT * FROM Cart WHERE user = $user LIMIT 1")
+            .bind(("user", user.clone()))
+            .await?;
+        let mut carts: Vec<Cart> = response.take(0)?;
+        Ok(carts.pop().unwrap_or_else(|| Cart {
+            user: Some(user.clone()),
+            ..Cart::guest()
+        }))
+    }
+
+    /// A new empty (unsaved) guest cart, it gets a token with `issue_guest_token`.
+    pub fn guest() -> Cart {
+        Cart {
+            id: None,
+            user: None,
+            guest_token_hash: None,
+            items: Vec::new(),
+            coupon_code: None,
+            updated_at: None,
+            expires_at: None
+        }
+    }
+
+    /// The live guest cart whose token hashes to `token_hash`.
+    pub async fn find_by_guest_hash(token_hash: &str, db: &Surreal<Client>) -> Result<Option<Cart>, Error> {
+        let mut response = db.query(r#"
+            SELECT * FROM Cart
+            WHERE guest_token_hash = $token_hash AND expires_at > time::now()
+            LIMIT 1"#)
+            .bind(("token_hash", token_hash.to_string()))
+            .await?;
+        let mut carts: Vec<Cart> = response.take(0)?;
+        Ok(carts.pop())
+    }
+
+    pub fn is_guest(&self) -> bool {
+        self.user.is_none()
+    }
+
+    /// Pushes the expiry of a guest cart `ttl_secs` into the future.
+    pub fn extend_expiry(&mut self, ttl_secs: usize) {
+        if self.is_guest() {
+            self.expires_at = Some(Datetime::from(Utc::now() + Duration::seconds(ttl_secs as i64)));
+        }
+    }
+
+    pub async fn delete(self, db: &Surreal<Client>) -> Result<Option<Cart>, Error> {
+        match self.id {
+            Some(id) => db.delete(id).await,
+            None => Ok(None)
+        }
+    }
+
+    /// Deletes guest carts that have run out, returning how many there were.
+    pub async fn delete_expired(db: &Surreal<Client>) -> Result<usize, Error> {
+        let mut response = db.query("DELETE Cart WHERE expires_at != NONE AND expires_at < time::now() RETURN BEFORE").await?;
+        let deleted: Vec<Cart> = response.take(0)?;
+        Ok(deleted.len())
+    }
+
+    /// Deletes expired guest carts every `every` for as long as the app runs.
+    pub async fn sweep(db: Surreal<Client>, every: std::time::Duration) {
+        let mut interval = rocket::tokio::time::interval(every);
+        loop {
+            interval.tick().await;
+            match Self::delete_expired(&db).await {
+                Ok(0) => {},
+                Ok(deleted) => println!("Deleted {} expired guest carts...", deleted),
+                Err(e) => println!("Guest cart sweep DB Error : {:?}", e)
+            }
+        }
+    }
+
+    /// Moves the items of the guest cart `token_hash` into the cart of `user`
+    /// and deletes the guest cart. Quantities are added up, but never beyond
+    /// what is in stock. Returns `None` when there is no such guest cart.
+    pub async fn merge_guest(token_hash: &str, user: &RecordId, db: &Surreal<Client>) -> Result<Option<Cart>, Error> {
+        let Some(guest) = Self::find_by_guest_hash(token_hash, db).await? else {
+            return Ok(None);
+        };
+
+        let mut cart = Self::for_user(user, db).await?;
+        for (item, product) in guest.products(db).await? {
+            let quantity = merged_quantity(cart.quantity_of(&item.product), item.quantity, product.available_qty);
+            cart.set_quantity(&item.product, quantity);
+        }
+        if cart.coupon_code.is_none() {
+            cart.coupon_code = guest.coupon_code.clone();
+        }
+
+        let cart = cart.save(db).await?;
+        guest.delete(db).await?;
+        Ok(Some(cart))
+    }
+
+    pub fn quantity_of(&self, product: &RecordId) -> u32 {
+        self.items.iter()
+            .find(|item| &item.product == product)
+            .map_or(0, |item| item.quantity)
+    }
+
+    /// Sets the quantity of a product, removing it when `quantity` is 0.
+    pub fn set_quantity(&mut self, product: &RecordId, quantity: u32) {
+        match self.items.iter_mut().find(|item| &item.product == product) {
+            Some(item) if quantity > 0 => item.quantity = quantity,
+            Some(_) => self.items.retain(|item| &item.product != product),
+            None if quantity > 0 => self.items.push(CartItem { product: product.clone(), quantity }),
+            None => {}
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.items.clear();
+        self.coupon_code = None;
+    }
+
+    /// Current products behind the cart items, in cart order. Items whose
+    /// product was deleted are left out.
+    pub async fn products(&self, db: &Surreal<Client>) -> Result<Vec<(CartItem, Product)>, Error> {
+        let ids: Vec<RecordId> = self.items.iter().map(|item| item.product.clone()).collect();
+        let mut response = db.query(format!("SELECT {} FROM $ids", PRODUCT_FIELDS))
+            .bind(("ids", ids))
+            .await?;
+        let products: Vec<Product> = response.take(0)?;
+
+        Ok(self.items.iter()
+            .filter_map(|item| products.iter()
+                .find(|product| product.id.as_ref() == Some(&item.product))
+                .map(|product| (item.clone(), product.clone())))
+            .collect())
+    }
+
+    /// Prices the cart with current product prices, flagging lines that ask
+    /// for more than is in stock, and applies its coupon. Totals are in
+    /// `currency`, the one products are priced in.
+    pub async fn priced(&self, currency: &str, db: &Surreal<Client>) -> Result<PricedCart, Error> {
+        let items = self.products(db).await?;
+
+        let (coupon, coupon_error) = match &self.coupon_code {
+            Some(code) => match Coupon::check(code, &items, self.user.as_ref(), db).await? {
+                Ok((_, applied)) => (Some(applied), None),
+                Err(rejection) => (None, Some(rejection.message()))
+            },
+            None => (None, None)
+        };
+
+        let mut lines = Vec::new();
+        for (item, product) in items {
+            lines.push(CartLine {
+                sku: product.sku,
+                slug: product.slug,
+                title: product.title,
+                img: product.img,
+                color: product.color,
+                size: product.size,
+                line_total: product.price.times(item.quantity),
+                unit_price: product.price,
+                quantity: item.quantity,
+                available_qty: product.available_qty,
+                insufficient_stock: item.quantity > product.available_qty
+            });
+        }
+
+        let discount = coupon.as_ref().map_or_else(|| Money::zero(currency), |coupon| coupon.discount.clone());
+        let subtotal = Money::sum(currency, lines.iter().map(|line| &line.line_total))
+            .ok_or(Api(Query("Cart amounts are in different currencies".to_string())))?;
+        let total = subtotal.checked_sub(&discount)
+            .ok_or(Api(Query("Cart amounts are in different currencies".to_string())))?;
+        Ok(PricedCart {
+            item_count: lines.iter().map(|line| line.quantity).sum(),
+            subtotal,
+            coupon,
+            coupon_error,
+            discount,
+            total,
+            lines
+        })
+    }
+}
+
+/// Quantity of a product after merging a guest cart into a user cart. The
+/// sum is capped at `stock` and `MAX_LINE_QUANTITY`, but what the user
+/// already had is never reduced.
+pub fn merged_quantity(user_qty: u32, guest_qty: u32, stock: u32) -> u32 {
+    user_qty.saturating_add(guest_qty).min(stock).min(MAX_LINE_QUANTITY).max(user_qty)
+}
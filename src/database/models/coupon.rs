@@ -0,0 +1,387 @@
+use chrono::{DateTime, Utc};
+use rocket::serde::{Deserialize, Serialize};
+use surrealdb::engine::remote::ws::Client;
+use surrealdb::{Error, RecordId, Surreal};
+use surrealdb::Error::Api;
+use surrealdb::error::Api::Query;
+use surrealdb::sql::Datetime;
+
+use super::super::models::{DatabaseIO};
+use super::Product;
+use super::cart::CartItem;
+use crate::money::Money;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CouponKind {
+    Percentage,     // `percent_bps` hundredths of a percent off the eligible items
+    Fixed,          // `amount_off` off the eligible items
+    FreeShipping,
+    BuyXGetY        // for every `buy_qty` eligible items `get_qty` more are free, cheapest first
+}
+
+/// A promo code. Without `categories` and `products` it applies to the whole
+/// cart, otherwise only to items matching either of them.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Coupon {
+    #[serde(default)]
+    pub id: Option<RecordId>,
+    pub code: String,
+    pub kind: CouponKind,
+    #[serde(default)]
+    pub percent_bps: Option<u32>,   // 1250 is 12.5%
+    #[serde(default)]
+    pub amount_off: Option<Money>,
+    #[serde(default)]
+    pub buy_qty: Option<u32>,
+    #[serde(default)]
+    pub get_qty: Option<u32>,
+    #[serde(default)]
+    pub valid_from: Option<Datetime>,
+    #[serde(default)]
+    pub valid_until: Option<Datetime>,
+    #[serde(default)]
+    pub min_cart_value: Option<Money>,
+    #[serde(default)]
+    pub max_uses: Option<u32>,
+    #[serde(default)]
+    pub max_uses_per_user: Option<u32>,
+    #[serde(default)]
+    pub used_count: u32,
+    #[serde(default)]
+    pub categories: Vec<String>,
+    #[serde(default)]
+    pub products: Vec<RecordId>,
+    #[serde(default = "default_active")]
+    pub active: bool,
+    #[serde(default)]
+    pub created_at: Option<Datetime>
+}
+
+fn default_active() -> bool {
+    true
+}
+
+/// Why a coupon can't be used on a cart.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CouponRejection {
+    Unknown,
+    Inactive,
+    NotStarted,
+    Expired,
+    BelowMinimum(Money),
+    NotApplicable,
+    UsageLimitReached,
+    UserLimitReached
+}
+
+impl CouponRejection {
+    pub fn message(&self) -> String {
+        match self {
+            CouponRejection::Unknown => "Unknown coupon".to_string(),
+            CouponRejection::Inactive => "This coupon is no longer active".to_string(),
+            CouponRejection::NotStarted => "This coupon is not valid yet".to_string(),
+            CouponRejection::Expired => "This coupon has expired".to_string(),
+            CouponRejection::BelowMinimum(min) => format!("This coupon needs a cart value of at least {}", min),
+            CouponRejection::NotApplicable => "This coupon does not apply to any item in the cart".to_string(),
+            CouponRejection::UsageLimitReached => "This coupon has been used up".to_string(),
+            CouponRejection::UserLimitReached => "You have already used this coupon".to_string()
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LineDiscount {
+    pub sku: String,
+    pub title: String,
+    pub amount: Money
+}
+
+/// A coupon worked out against a cart, with the discount split over the
+/// items it came from.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AppliedCoupon {
+    pub code: String,
+    pub kind: CouponKind,
+    pub discount: Money,
+    #[serde(default)]
+    pub free_shipping: bool,
+    pub lines: Vec<LineDiscount>
+}
+
+impl DatabaseIO for Coupon {
+    type Model = Coupon;
+
+    fn table_name() -> &'static str {
+        "Coupon"
+    }
+
+    async fn init(db: &Surreal<Client>) -> Result<(), Error> {
+        let query_str = r#"
+        DEFINE TABLE IF NOT EXISTS Coupon SCHEMAFULL;
+        DEFINE FIELD IF NOT EXISTS code ON TABLE Coupon TYPE string PERMISSIONS FULL;
+        DEFINE FIELD OVERWRITE kind ON TABLE Coupon TYPE string
+            ASSERT $value IN ['percentage', 'fixed', 'free_shipping', 'buy_x_get_y'] PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS value ON TABLE Coupon TYPE number DEFAULT 0 PERMISSIONS FULL; // made percent_bps or amount_off by migrations::money_amounts
+        DEFINE FIELD IF NOT EXISTS percent_bps ON TABLE Coupon TYPE option<int> PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS buy_qty ON TABLE Coupon TYPE option<int> PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS get_qty ON TABLE Coupon TYPE option<int> PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS valid_from ON TABLE Coupon TYPE option<datetime> PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS valid_until ON TABLE Coupon TYPE option<datetime> PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS min_cart_value ON TABLE Coupon TYPE number DEFAULT 0 PERMISSIONS FULL; // made Money by migrations::money_amounts
+        DEFINE FIELD IF NOT EXISTS amount_off ON TABLE Coupon TYPE option<object> PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS amount_off.amount ON TABLE Coupon TYPE int PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS amount_off.currency ON TABLE Coupon TYPE string PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS max_uses ON TABLE Coupon TYPE option<int> PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS max_uses_per_user ON TABLE Coupon TYPE option<int> PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS used_count ON TABLE Coupon TYPE int DEFAULT 0 PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS categories ON TABLE Coupon TYPE array<string> DEFAULT [] PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS products ON TABLE Coupon TYPE array<record<Product>> DEFAULT [] PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS active ON TABLE Coupon TYPE bool DEFAULT true PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS created_at ON TABLE Coupon TYPE datetime DEFAULT time::now() READONLY PERMISSIONS FULL;
+
+        DEFINE INDEX IF NOT EXISTS couponCodeIndex ON TABLE Coupon FIELDS code UNIQUE;
+
+        // One row per order a coupon was used on, for the per-user limit
+        DEFINE TABLE IF NOT EXISTS coupon_redemption SCHEMAFULL;
+        DEFINE FIELD IF NOT EXISTS coupon ON TABLE coupon_redemption TYPE record<Coupon> READONLY;
+        DEFINE FIELD IF NOT EXISTS user ON TABLE coupon_redemption TYPE record<User> READONLY;
+        DEFINE FIELD IF NOT EXISTS order ON TABLE coupon_redemption TYPE record<Order> READONLY;
+        DEFINE FIELD IF NOT EXISTS at ON TABLE coupon_redemption TYPE datetime DEFAULT time::now() READONLY;
+
+        DEFINE INDEX IF NOT EXISTS couponRedemptionUserIndex ON TABLE coupon_redemption FIELDS coupon, user;"#;
+
+        let resp = db.query(query_str).await;
+
+        match resp {
+            Ok(_) => {
+                println!("Coupon Table Initialized...");
+                Ok(())
+            },
+            Err(e) => {
+                println!("Coupons DB Error : {:?}",e);
+
+                Err(e)
+            }
+        }
+    }
+
+    async fn get_all(db: &Surreal<Client>) -> Vec<Self::Model> {
+        let query = db.query("SELECT * FROM Coupon ORDER BY code").await;
+        let mut response = query.ok().unwrap();
+        response.take(0).
+            ok().unwrap()
+    }
+
+    async fn save(self, db: &Surreal<Client>) -> Result<Self::Model, Error> {
+        match self.id.clone() {
+            None => {
+                let coupon : Option<Coupon> = db.create("Coupon").content(self).await?;
+                coupon.ok_or(Api(Query("Failed to create coupon".to_string())))
+            }
+            Some(id) => {
+                let coupon :Option<Coupon> = db.update(id).content(self).await?;
+                coupon.ok_or(Api(Query("Failed to update coupon".to_string())))
+            }
+        }
+    }
+}
+
+impl Coupon {
+    /// Codes are matched case-insensitively and stored in upper case.
+    pub fn normalize_code(code: &str) -> String {
+        code.trim().to_uppercase()
+    }
+
+    pub async fn find_by_code(code: &str, db: &Surreal<Client>) -> Result<Option<Coupon>, Error> {
+        let mut response = db.query("SELECT * FROM Coupon WHERE code = $code LIMIT 1")
+            .bind(("code", Self::normalize_code(code)))
+            .await?;
+        let mut coupons: Vec<Coupon> = response.take(0)?;
+        Ok(coupons.pop())
+    }
+
+    /// How many orders of `user` this coupon was used on.
+    pub async fn uses_by(&self, user: &RecordId, db: &Surreal<Client>) -> Result<u32, Error> {
+        let mut response = db.query("RETURN array::len(SELECT id FROM coupon_redemption WHERE coupon = $coupon AND user = $user)")
+            .bind(("coupon", self.id.clone()))
+            .bind(("user", user.clone()))
+            .await?;
+        let uses: Option<u32> = response.take(0)?;
+        Ok(uses.unwrap_or(0))
+    }
+
+    /// Looks up `code` and applies it to `items`, including the per-user limit
+    /// when the cart belongs to a user.
+    pub async fn check(code: &str, items: &[(CartItem, Product)], user: Option<&RecordId>, db: &Surreal<Client>) -> Result<Result<(Coupon, AppliedCoupon), CouponRejection>, Error> {
+        let Some(coupon) = Self::find_by_code(code, db).await? else {
+            return Ok(Err(CouponRejection::Unknown));
+        };
+
+        let applied = match coupon.apply(items, Utc::now()) {
+            Ok(applied) => applied,
+            Err(rejection) => return Ok(Err(rejection))
+        };
+
+        if let (Some(max), Some(user)) = (coupon.max_uses_per_user, user) && coupon.uses_by(user, db).await? >= max {
+            return Ok(Err(CouponRejection::UserLimitReached));
+        }
+
+        Ok(Ok((coupon, applied)))
+    }
+
+    /// Whether a database error is the unique `couponCodeIndex` rejecting a write.
+    pub fn is_code_conflict(e: &Error) -> bool {
+        e.to_string().contains("couponCodeIndex")
+    }
+
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errors = Vec::new();
+
+        if self.code.is_empty() || !self.code.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            errors.push("code must only contain letters, digits, dashes and underscores".to_string());
+        }
+
+        match self.kind {
+            CouponKind::Percentage if !self.percent_bps.is_some_and(|bps| bps > 0 && bps <= 10_000) => {
+                errors.push("percent_bps must be between 1 and 10000".to_string());
+            },
+            CouponKind::Fixed if self.amount_off.as_ref().is_none_or(|amount| amount.amount <= 0) => {
+                errors.push("amount_off must be a positive amount".to_string());
+            },
+            CouponKind::BuyXGetY if !matches!((self.buy_qty, self.get_qty), (Some(buy), Some(get)) if buy > 0 && get > 0) => {
+                errors.push("buy_qty and get_qty must be at least 1".to_string());
+            },
+            _ => {}
+        }
+
+        if self.min_cart_value.as_ref().is_some_and(Money::is_negative) {
+            errors.push("min_cart_value must not be negative".to_string());
+        }
+
+        if let (Some(from), Some(until)) = (&self.valid_from, &self.valid_until) && from >= until {
+            errors.push("valid_from must be before valid_until".to_string());
+        }
+
+        if errors.is_empty() { Ok(()) } else { Err(errors) }
+    }
+
+    fn applies_to(&self, item: &CartItem, product: &Product) -> bool {
+        (self.categories.is_empty() && self.products.is_empty())
+            || self.categories.iter().any(|category| category.eq_ignore_ascii_case(&product.category))
+            || self.products.contains(&item.product)
+    }
+
+    /// Works out the discount on `items` at `now`. The per-user limit needs
+    /// the database, see `uses_by`.
+    pub fn apply(&self, items: &[(CartItem, Product)], now: DateTime<Utc>) -> Result<AppliedCoupon, CouponRejection> {
+        if !self.active {
+            return Err(CouponRejection::Inactive);
+        }
+        if self.valid_from.as_ref().is_some_and(|from| now < from.0) {
+            return Err(CouponRejection::NotStarted);
+        }
+        if self.valid_until.as_ref().is_some_and(|until| now >= until.0) {
+            return Err(CouponRejection::Expired);
+        }
+        if self.max_uses.is_some_and(|max| self.used_count >= max) {
+            return Err(CouponRejection::UsageLimitReached);
+        }
+
+        let Some(currency) = items.first().map(|(_, product)| product.price.currency.clone()) else {
+            return Err(CouponRejection::NotApplicable);
+        };
+        // Amounts in another currency can't be compared, such a coupon doesn't apply
+        if [&self.min_cart_value, &self.amount_off].iter()
+            .any(|amount| amount.as_ref().is_some_and(|amount| amount.currency != currency)) {
+            return Err(CouponRejection::NotApplicable);
+        }
+
+        let totals: Vec<Money> = items.iter()
+            .map(|(item, product)| product.price.times(item.quantity))
+            .collect();
+        let Some(cart_total) = Money::sum(&currency, &totals) else {
+            return Err(CouponRejection::NotApplicable);
+        };
+        if let Some(min) = &self.min_cart_value && cart_total.amount < min.amount {
+            return Err(CouponRejection::BelowMinimum(min.clone()));
+        }
+
+        let eligible: Vec<&(CartItem, Product)> = items.iter()
+            .filter(|(item, product)| self.applies_to(item, product))
+            .collect();
+        if eligible.is_empty() {
+            return Err(CouponRejection::NotApplicable);
+        }
+
+        let amounts: Vec<Money> = match self.kind {
+            CouponKind::Percentage => eligible.iter()
+                .map(|(item, product)| product.price.times(item.quantity).basis_points(self.percent_bps.unwrap_or(0)))
+                .collect(),
+            CouponKind::Fixed => {
+                let totals: Vec<i64> = eligible.iter()
+                    .map(|(item, product)| product.price.times(item.quantity).amount)
+                    .collect();
+                let amount_off = self.amount_off.clone().unwrap_or_else(|| Money::zero(&currency));
+                // Never more than the eligible items cost
+                match amount_off.checked_min(Money::new(totals.iter().sum(), &currency)) {
+                    Some(amount_off) => amount_off.allocate(&totals),
+                    None => return Err(CouponRejection::NotApplicable)
+                }
+            },
+            CouponKind::FreeShipping => vec![Money::zero(&currency); eligible.len()],
+            CouponKind::BuyXGetY => {
+                let lines: Vec<(i64, u32)> = eligible.iter()
+                    .map(|(item, product)| (product.price.amount, item.quantity))
+                    .collect();
+                free_units(&lines, self.buy_qty.unwrap_or(1), self.get_qty.unwrap_or(1)).iter()
+                    .zip(eligible.iter())
+                    .map(|(free, (_, product))| product.price.times(*free))
+                    .collect()
+            }
+        };
+
+        let lines: Vec<LineDiscount> = eligible.iter().zip(amounts)
+            .filter(|(_, amount)| amount.amount > 0)
+            .map(|((_, product), amount)| LineDiscount {
+                sku: product.sku.clone(),
+                title: product.title.clone(),
+                amount
+            })
+            .collect();
+
+        let free_shipping = self.kind == CouponKind::FreeShipping;
+        if lines.is_empty() && !free_shipping {
+            return Err(CouponRejection::NotApplicable);
+        }
+
+        let Some(discount) = Money::sum(&currency, lines.iter().map(|line| &line.amount)) else {
+            return Err(CouponRejection::NotApplicable);
+        };
+        Ok(AppliedCoupon {
+            code: self.code.clone(),
+            kind: self.kind,
+            discount,
+            free_shipping,
+            lines
+        })
+    }
+}
+
+/// How many units of each `(unit_price, quantity)` line are free when every
+/// `buy` units bought give `get` more. The cheapest units are the free ones.
+pub fn free_units(lines: &[(i64, u32)], buy: u32, get: u32) -> Vec<u32> {
+    let units = lines.iter().fold(0u32, |units, (_, quantity)| units.saturating_add(*quantity));
+    let mut free = units / buy.saturating_add(get) * get;
+
+    let mut cheapest_first: Vec<usize> = (0..lines.len()).collect();
+    cheapest_first.sort_by_key(|i| lines[*i].0);
+
+    let mut result = vec![0; lines.len()];
+    for i in cheapest_first {
+        let taken = free.min(lines[i].1);
+        result[i] = taken;
+        free -= taken;
+    }
+    result
+}
@@ -0,0 +1,203 @@
+use std::collections::HashMap;
+
+use rocket::serde::{Deserialize, Serialize};
+use surrealdb::engine::remote::ws::Client;
+use surrealdb::{Error, Surreal};
+use surrealdb::Error::Api;
+use surrealdb::error::Api::Query;
+use surrealdb::sql::Datetime;
+
+use super::super::models::{DatabaseIO};
+use crate::money::{is_valid_currency, Money, RoundingMode};
+
+/// What one unit of the store currency is worth in another currency, and how
+/// prices converted into it are rounded.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ExchangeRate {
+    pub currency: String,
+    pub rate: f64,
+    #[serde(default)]
+    pub rounding: RoundingMode,
+    #[serde(default = "default_increment")]
+    pub increment: i64,     // in minor units of `currency`
+    #[serde(default)]
+    pub updated_at: Option<Datetime>
+}
+
+fn default_increment() -> i64 {
+    1
+}
+
+impl DatabaseIO for ExchangeRate {
+    type Model = ExchangeRate;
+
+    fn table_name() -> &'static str {
+        "ExchangeRate"
+    }
+
+    async fn init(db: &Surreal<Client>) -> Result<(), Error> {
+        let query_str = r#"
+        DEFINE TABLE IF NOT EXISTS ExchangeRate SCHEMAFULL;
+        DEFINE FIELD IF NOT EXISTS currency ON TABLE ExchangeRate TYPE string PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS rate ON TABLE ExchangeRate TYPE float ASSERT $value > 0 PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS rounding ON TABLE ExchangeRate TYPE string
+            ASSERT $value IN ['nearest', 'up', 'down'] PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS increment ON TABLE ExchangeRate TYPE int ASSERT $value > 0 PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS updated_at ON TABLE ExchangeRate TYPE datetime VALUE time::now() PERMISSIONS FULL;
+
+        DEFINE INDEX IF NOT EXISTS exchangeRateCurrencyIndex ON TABLE ExchangeRate FIELDS currency UNIQUE;"#;
+
+        let resp = db.query(query_str).await;
+
+        match resp {
+            Ok(_) => {
+                println!("ExchangeRate Table Initialized...");
+                Ok(())
+            },
+            Err(e) => {
+                println!("ExchangeRates DB Error : {:?}",e);
+
+                Err(e)
+            }
+        }
+    }
+
+    async fn get_all(db: &Surreal<Client>) -> Vec<Self::Model> {
+        let query = db.query("SELECT * FROM ExchangeRate ORDER BY currency").await;
+        let mut response = query.ok().unwrap();
+        response.take(0).
+            ok().unwrap()
+    }
+
+    async fn save(self, db: &Surreal<Client>) -> Result<Self::Model, Error> {
+        let mut response = db.query("UPSERT type::thing('ExchangeRate', $rate.currency) CONTENT $rate")
+            .bind(("rate", self))
+            .await?;
+        let mut rates: Vec<ExchangeRate> = response.take(0)?;
+        rates.pop().ok_or(Api(Query("Failed to save exchange rate".to_string())))
+    }
+}
+
+impl ExchangeRate {
+    /// Checks a whole rate table against the store currency `base`, returning
+    /// every problem found.
+    pub fn validate_all(rates: &[ExchangeRate], base: &str) -> Result<(), Vec<String>> {
+        let mut errors = Vec::new();
+
+        for (index, rate) in rates.iter().enumerate() {
+            if !is_valid_currency(&rate.currency) {
+                errors.push(format!("{} is not an ISO 4217 code", rate.currency));
+            }
+            if rate.currency == base {
+                errors.push(format!("{} is the store currency and has no rate", base));
+            }
+            if rates[..index].iter().any(|other| other.currency == rate.currency) {
+                errors.push(format!("{} is listed more than once", rate.currency));
+            }
+            if !rate.rate.is_finite() || rate.rate <= 0.0 {
+                errors.push(format!("rate of {} must be positive", rate.currency));
+            }
+            if rate.increment < 1 {
+                errors.push(format!("increment of {} must be at least 1", rate.currency));
+            }
+        }
+
+        if errors.is_empty() { Ok(()) } else { Err(errors) }
+    }
+
+    /// Reads a rate table from a JSON file holding an array of rates.
+    pub fn read_file(path: &str) -> Result<Vec<ExchangeRate>, String> {
+        let json = std::fs::read_to_string(path)
+            .map_err(|e| format!("Unable to read {} : {}", path, e))?;
+        serde_json::from_str(&json)
+            .map_err(|e| format!("Invalid exchange rates in {} : {}", path, e))
+    }
+
+    /// Swaps the stored rate table for `rates` in one go.
+    pub async fn replace_all(rates: Vec<ExchangeRate>, db: &Surreal<Client>) -> Result<Vec<ExchangeRate>, Error> {
+        db.query(r#"
+            BEGIN TRANSACTION;
+            DELETE ExchangeRate;
+            FOR $rate IN $rates {
+                CREATE type::thing('ExchangeRate', $rate.currency) CONTENT $rate;
+            };
+            COMMIT TRANSACTION;"#)
+            .bind(("rates", rates))
+            .await?
+            .check()?;
+
+        Ok(Self::get_all(db).await)
+    }
+
+    /// The rate table to start with. Rates in `file`, when given, replace the
+    /// stored ones first.
+    pub async fn load(file: Option<&str>, base: &str, db: &Surreal<Client>) -> Result<ExchangeRates, String> {
+        let rates = match file {
+            Some(path) => {
+                let rates = Self::read_file(path)?;
+                Self::validate_all(&rates, base)
+                    .map_err(|errors| format!("Invalid exchange rates in {} : {}", path, errors.join(", ")))?;
+                Self::replace_all(rates, db).await
+                    .map_err(|e| format!("Unable to import exchange rates : {:?}", e))?
+            },
+            None => Self::get_all(db).await
+        };
+
+        Ok(ExchangeRates::new(base, rates))
+    }
+}
+
+/// Rates from the store currency into every other currency prices are shown
+/// in, kept in memory as every product listing needs them.
+#[derive(Debug, Clone, Default)]
+pub struct ExchangeRates {
+    base: String,
+    rates: HashMap<String, ExchangeRate>
+}
+
+impl ExchangeRates {
+    pub fn new(base: &str, rates: Vec<ExchangeRate>) -> ExchangeRates {
+        ExchangeRates {
+            base: base.to_string(),
+            rates: rates.into_iter().map(|rate| (rate.currency.clone(), rate)).collect()
+        }
+    }
+
+    pub fn base(&self) -> &str {
+        &self.base
+    }
+
+    pub fn supports(&self, currency: &str) -> bool {
+        currency == self.base || self.rates.contains_key(currency)
+    }
+
+    /// Every currency prices can be shown in, the store currency first.
+    pub fn currencies(&self) -> Vec<String> {
+        let mut others: Vec<String> = self.rates.keys().cloned().collect();
+        others.sort();
+        std::iter::once(self.base.clone()).chain(others).collect()
+    }
+
+    pub fn rates(&self) -> Vec<&ExchangeRate> {
+        let mut rates: Vec<&ExchangeRate> = self.rates.values().collect();
+        rates.sort_by(|a, b| a.currency.cmp(&b.currency));
+        rates
+    }
+
+    /// `price` in `currency`: the override set for it if any, otherwise
+    /// converted at its rate. None for a currency without one.
+    pub fn price(&self, price: &Money, overrides: &[Money], currency: &str) -> Option<Money> {
+        if price.currency == currency {
+            return Some(price.clone());
+        }
+        if let Some(price) = overrides.iter().find(|price| price.currency == currency) {
+            return Some(price.clone());
+        }
+
+        let rate = self.rates.get(currency)?;
+        if price.currency != self.base {
+            return None;
+        }
+        Some(price.convert(currency, rate.rate, rate.rounding, rate.increment))
+    }
+}
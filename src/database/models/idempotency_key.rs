@@ -0,0 +1,188 @@
+use chrono::{Duration, Utc};
+use rocket::serde::{Deserialize, Serialize};
+use surrealdb::engine::remote::ws::Client;
+use surrealdb::{Error, RecordId, Surreal};
+use surrealdb::Error::Api;
+use surrealdb::error::Api::Query;
+use surrealdb::sql::Datetime;
+
+use super::super::models::{DatabaseIO};
+
+/// How long a stored response is replayed for.
+pub const IDEMPOTENCY_TTL_SECS: i64 = 24 * 60 * 60;
+/// How long a request may run on a key before a retry can take it over.
+pub const IDEMPOTENCY_LEASE_SECS: i64 = 60;
+
+/// A request made with an `Idempotency-Key`. While the first request is still
+/// running there is no response yet, which tells retries to back off until
+/// `locked_until`. A request that never finished, say because the app
+/// restarted, then gives the key up to the next retry.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct IdempotencyKey {
+    #[serde(default)]
+    pub id: Option<RecordId>,
+    pub scope: String,          // who sent the key, keys of different users never clash
+    pub key: String,
+    pub request_hash: String,
+    #[serde(default)]
+    pub response_status: Option<u16>,
+    #[serde(default)]
+    pub response_body: Option<serde_json::Value>,
+    #[serde(default)]
+    pub locked_until: Option<Datetime>,
+    #[serde(default)]
+    pub created_at: Option<Datetime>,
+    pub expires_at: Datetime
+}
+
+pub enum IdempotencyClaim {
+    /// First time this key is seen, the request should run.
+    New(IdempotencyKey),
+    Replay(u16, serde_json::Value),
+    InProgress,
+    Mismatch // the key was used for a different request
+}
+
+impl DatabaseIO for IdempotencyKey {
+    type Model = IdempotencyKey;
+
+    fn table_name() -> &'static str {
+        "idempotency_key"
+    }
+
+    async fn init(db: &Surreal<Client>) -> Result<(), Error> {
+        let query_str = r#"
+        DEFINE TABLE IF NOT EXISTS idempotency_key SCHEMAFULL;
+        DEFINE FIELD IF NOT EXISTS scope ON TABLE idempotency_key TYPE string READONLY;
+        DEFINE FIELD IF NOT EXISTS key ON TABLE idempotency_key TYPE string READONLY;
+        DEFINE FIELD IF NOT EXISTS request_hash ON TABLE idempotency_key TYPE string READONLY;
+        DEFINE FIELD IF NOT EXISTS response_status ON TABLE idempotency_key TYPE option<int>;
+        DEFINE FIELD IF NOT EXISTS response_body ON TABLE idempotency_key FLEXIBLE TYPE option<object>;
+        DEFINE FIELD IF NOT EXISTS locked_until ON TABLE idempotency_key TYPE option<datetime>;
+        DEFINE FIELD IF NOT EXISTS created_at ON TABLE idempotency_key TYPE datetime DEFAULT time::now() READONLY;
+        DEFINE FIELD IF NOT EXISTS expires_at ON TABLE idempotency_key TYPE datetime;
+
+        DEFINE INDEX IF NOT EXISTS idempotencyKeyIndex ON TABLE idempotency_key FIELDS scope, key UNIQUE;
+
+        DELETE idempotency_key WHERE expires_at < time::now();"#;
+
+        let resp = db.query(query_str).await;
+
+        match resp {
+            Ok(_) => {
+                println!("IdempotencyKey Table Initialized...");
+                Ok(())
+            },
+            Err(e) => {
+                println!("IdempotencyKeys DB Error : {:?}",e);
+
+                Err(e)
+            }
+        }
+    }
+
+    async fn get_all(db: &Surreal<Client>) -> Vec<Self::Model> {
+        let query = db.query("SELECT * FROM idempotency_key").await;
+        let mut response = query.ok().unwrap();
+        response.take(0).
+            ok().unwrap()
+    }
+
+    async fn save(self, db: &Surreal<Client>) -> Result<Self::Model, Error> {
+        match self.id.clone() {
+            None => {
+                let record : Option<IdempotencyKey> = db.create("idempotency_key").content(self).await?;
+                record.ok_or(Api(Query("Failed to create idempotency_key".to_string())))
+            }
+            Some(id) => {
+                let record :Option<IdempotencyKey> = db.update(id).content(self).await?;
+                record.ok_or(Api(Query("Failed to update idempotency_key".to_string())))
+            }
+        }
+    }
+}
+
+fn lease_until() -> Datetime {
+    Datetime::from(Utc::now() + Duration::seconds(IDEMPOTENCY_LEASE_SECS))
+}
+
+impl IdempotencyKey {
+    async fn find(scope: &str, key: &str, db: &Surreal<Client>) -> Result<Option<IdempotencyKey>, Error> {
+        let mut response = db.query("SELECT * FROM idempotency_key WHERE scope = $scope AND key = $key LIMIT 1")
+            .bind(("scope", scope.to_string()))
+            .bind(("key", key.to_string()))
+            .await?;
+        let mut records: Vec<IdempotencyKey> = response.take(0)?;
+        Ok(records.pop())
+    }
+
+    /// Claims `key` for a request. The unique index makes sure only one of
+    /// several concurrent requests with the same key gets to run, and only
+    /// one retry takes over a claim whose lease ran out.
+    pub async fn claim(scope: &str, key: &str, request_hash: &str, db: &Surreal<Client>) -> Result<IdempotencyClaim, Error> {
+        // An expired key is free to be used again
+        db.query("DELETE idempotency_key WHERE scope = $scope AND key = $key AND expires_at < time::now()")
+            .bind(("scope", scope.to_string()))
+            .bind(("key", key.to_string()))
+            .await?
+            .check()?;
+
+        let record = IdempotencyKey {
+            id: None,
+            scope: scope.to_string(),
+            key: key.to_string(),
+            request_hash: request_hash.to_string(),
+            response_status: None,
+            response_body: None,
+            locked_until: Some(lease_until()),
+            created_at: None,
+            expires_at: Datetime::from(Utc::now() + Duration::seconds(IDEMPOTENCY_TTL_SECS))
+        };
+
+        match record.save(db).await {
+            Ok(record) => return Ok(IdempotencyClaim::New(record)),
+            Err(e) if e.to_string().contains("idempotencyKeyIndex") => {},
+            Err(e) => return Err(e)
+        }
+
+        let Some(existing) = Self::find(scope, key, db).await? else {
+            return Ok(IdempotencyClaim::InProgress);
+        };
+
+        if existing.request_hash != request_hash {
+            return Ok(IdempotencyClaim::Mismatch);
+        }
+        if let (Some(status), Some(body)) = (existing.response_status, existing.response_body) {
+            return Ok(IdempotencyClaim::Replay(status, body));
+        }
+
+        // Keys claimed before leases have none, they are as good as expired
+        let mut response = db.query(r#"
+            UPDATE $id SET locked_until = $locked_until
+                WHERE response_status = NONE AND (locked_until = NONE OR locked_until < time::now())"#)
+            .bind(("id", existing.id))
+            .bind(("locked_until", lease_until()))
+            .await?;
+        let mut taken: Vec<IdempotencyKey> = response.take(0)?;
+        match taken.pop() {
+            Some(record) => Ok(IdempotencyClaim::New(record)),
+            None => Ok(IdempotencyClaim::InProgress)
+        }
+    }
+
+    /// Stores the response to replay for later requests with this key.
+    pub async fn complete(mut self, status: u16, body: serde_json::Value, db: &Surreal<Client>) -> Result<IdempotencyKey, Error> {
+        self.response_status = Some(status);
+        self.response_body = Some(body);
+        self.save(db).await
+    }
+
+    /// Frees the key so the request can be retried, for responses that should
+    /// not be replayed.
+    pub async fn release(self, db: &Surreal<Client>) -> Result<Option<IdempotencyKey>, Error> {
+        match self.id {
+            Some(id) => db.delete(id).await,
+            None => Ok(None)
+        }
+    }
+}
@@ -0,0 +1,258 @@
+use rocket::serde::{Deserialize, Serialize};
+use surrealdb::engine::remote::ws::Client;
+use surrealdb::{Error, RecordId, Surreal};
+use surrealdb::Error::Api;
+use surrealdb::error::Api::Query;
+use surrealdb::sql::Datetime;
+
+use super::super::models::{DatabaseIO};
+
+/// Why the stock of a product changed.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MovementKind {
+    Sale,
+    Return,
+    RefundRestock,  // goods of a refunded order that never shipped
+    Restock,
+    Damage,
+    Correction
+}
+
+impl MovementKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MovementKind::Sale => "sale",
+            MovementKind::Return => "return",
+            MovementKind::RefundRestock => "refund_restock",
+            MovementKind::Restock => "restock",
+            MovementKind::Damage => "damage",
+            MovementKind::Correction => "correction"
+        }
+    }
+
+    /// Whether `quantity` goes the way this kind moves stock: returns and
+    /// restocks of either kind add to it, sales and damage take from it, corrections do either.
+    pub fn accepts(&self, quantity: i64) -> bool {
+        match self {
+            MovementKind::Return | MovementKind::RefundRestock | MovementKind::Restock => quantity > 0,
+            MovementKind::Sale | MovementKind::Damage => quantity < 0,
+            MovementKind::Correction => quantity != 0
+        }
+    }
+}
+
+/// One change to the stock of a product in a warehouse. Movements are only
+/// ever added, never edited, so together they are the product's stock
+/// history and add up to its stock in each warehouse.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct InventoryMovement {
+    #[serde(default)]
+    pub id: Option<RecordId>,
+    pub product: RecordId,
+    pub warehouse: RecordId,
+    pub sku: String,
+    pub kind: MovementKind,
+    pub quantity: i64,              // added to the stock, negative when taken
+    pub stock_after: i64,           // of the product over all warehouses
+    #[serde(default)]
+    pub actor: Option<RecordId>,    // None for changes no user made, e.g. captured payments or opening stock
+    #[serde(default)]
+    pub order: Option<RecordId>,
+    #[serde(default)]
+    pub reason: Option<String>,
+    #[serde(default)]
+    pub at: Option<Datetime>
+}
+
+/// Stock that doesn't match the sum of its movements, in one warehouse or,
+/// without `warehouse`, the product's total.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StockDiscrepancy {
+    pub product: RecordId,
+    #[serde(default)]
+    pub warehouse: Option<RecordId>,
+    pub sku: String,
+    pub stock_qty: i64,
+    pub ledger_qty: i64
+}
+
+const DISCREPANCIES: &str = r#"
+    array::concat(
+        (SELECT * FROM (
+            SELECT product, warehouse, product.sku AS sku, stock_qty,
+                math::sum(SELECT VALUE quantity FROM inventory_movement WHERE product = $parent.product AND warehouse = $parent.warehouse) AS ledger_qty
+            FROM warehouse_stock
+        ) WHERE stock_qty != ledger_qty ORDER BY sku),
+        (SELECT * FROM (
+            SELECT id AS product, sku, stock_qty, math::sum(SELECT VALUE quantity FROM inventory_movement WHERE product = $parent.id) AS ledger_qty
+            FROM Product
+        ) WHERE stock_qty != ledger_qty ORDER BY sku)
+    )"#;
+
+impl DatabaseIO for InventoryMovement {
+    type Model = InventoryMovement;
+
+    fn table_name() -> &'static str {
+        "inventory_movement"
+    }
+
+    async fn init(db: &Surreal<Client>) -> Result<(), Error> {
+        let query_str = r#"
+        DEFINE TABLE OVERWRITE inventory_movement SCHEMAFULL
+            PERMISSIONS FOR update, delete NONE;
+
+        DEFINE FIELD IF NOT EXISTS product ON TABLE inventory_movement TYPE record<Product> READONLY;
+        // NONE only for movements made before warehouses, migrations::warehouses fills it in
+        DEFINE FIELD IF NOT EXISTS warehouse ON TABLE inventory_movement TYPE option<record<Warehouse>> READONLY;
+        DEFINE FIELD IF NOT EXISTS sku ON TABLE inventory_movement TYPE string READONLY;
+        DEFINE FIELD OVERWRITE kind ON TABLE inventory_movement TYPE string
+            ASSERT $value IN ['sale', 'return', 'refund_restock', 'restock', 'damage', 'correction'] READONLY;
+        DEFINE FIELD IF NOT EXISTS quantity ON TABLE inventory_movement TYPE int ASSERT $value != 0 READONLY;
+        DEFINE FIELD IF NOT EXISTS stock_after ON TABLE inventory_movement TYPE int READONLY;
+        DEFINE FIELD IF NOT EXISTS actor ON TABLE inventory_movement TYPE option<record<User>> READONLY;
+        DEFINE FIELD IF NOT EXISTS order ON TABLE inventory_movement TYPE option<record<Order>> READONLY;
+        DEFINE FIELD IF NOT EXISTS reason ON TABLE inventory_movement TYPE option<string> READONLY;
+        DEFINE FIELD IF NOT EXISTS at ON TABLE inventory_movement TYPE datetime DEFAULT time::now() READONLY;
+
+        DEFINE INDEX IF NOT EXISTS inventoryMovementProductIndex ON TABLE inventory_movement FIELDS product;
+
+        // Tells every subscriber of a variant that can be bought again, once
+        DEFINE FUNCTION OVERWRITE fn::notify_back_in_stock($product: record<Product>) {
+            IF fn::available_qty($product) > 0 {
+                LET $subscriptions = DELETE stock_subscription WHERE product = $product RETURN BEFORE;
+                FOR $subscription IN $subscriptions {
+                    CREATE notification CONTENT {
+                        kind: 'back_in_stock', user: $subscription.user, email: $subscription.email,
+                        product: $product, sku: $product.sku, stock_qty: $product.stock_qty
+                    };
+                };
+            };
+        };
+
+        // The only way stock changes: moves it by $quantity in a warehouse and records why, refusing
+        // to go below zero there. Subscribers hear when a variant is back, the inventory team when it runs low.
+        DEFINE FUNCTION OVERWRITE fn::move_stock($product: record<Product>, $warehouse: record<Warehouse>, $kind: string, $quantity: int, $actor: option<record<User>>, $order: option<record<Order>>, $reason: option<string>) {
+            LET $level = type::thing('warehouse_stock', [$warehouse, $product]);
+            IF !record::exists($level) {
+                CREATE $level CONTENT { warehouse: $warehouse, product: $product, stock_qty: 0 };
+            };
+            IF array::len(UPDATE $level SET stock_qty += $quantity WHERE stock_qty + $quantity >= 0) = 0 {
+                THROW "Insufficient stock";
+            };
+            LET $moved = (UPDATE $product SET stock_qty += $quantity)[0];
+            IF $quantity > 0 {
+                fn::notify_back_in_stock($product);
+            };
+            LET $threshold = $moved.low_stock_threshold;
+            IF $threshold != NONE AND $moved.stock_qty <= $threshold AND $moved.stock_qty - $quantity > $threshold {
+                CREATE notification CONTENT { kind: 'low_stock', product: $product, sku: $moved.sku, stock_qty: $moved.stock_qty };
+            };
+            RETURN CREATE ONLY inventory_movement CONTENT {
+                product: $product, warehouse: $warehouse, sku: $moved.sku, kind: $kind, quantity: $quantity, stock_after: $moved.stock_qty,
+                actor: $actor, order: $order, reason: $reason
+            };
+        };"#;
+
+        let resp = db.query(query_str).await;
+
+        match resp {
+            Ok(_) => {
+                println!("InventoryMovement Table Initialized...");
+                Ok(())
+            },
+            Err(e) => {
+                println!("InventoryMovements DB Error : {:?}",e);
+
+                Err(e)
+            }
+        }
+    }
+
+    async fn get_all(db: &Surreal<Client>) -> Vec<Self::Model> {
+        let query = db.query("SELECT * FROM inventory_movement").await;
+        let mut response = query.ok().unwrap();
+        response.take(0).
+            ok().unwrap()
+    }
+
+    async fn save(self, db: &Surreal<Client>) -> Result<Self::Model, Error> {
+        match self.id {
+            // Goes through `record` so the stock moves along
+            None => Self::record(&self.product, &self.warehouse, self.kind, self.quantity, self.actor.as_ref(), self.reason, db).await?
+                .ok_or(Api(Query("Insufficient stock".to_string()))),
+            // Movements are append-only
+            Some(_) => Err(Api(Query("Inventory movements can not be updated".to_string())))
+        }
+    }
+}
+
+impl InventoryMovement {
+    /// Moves the stock of `product` in `warehouse` by `quantity` on behalf of
+    /// `actor`. None when that would take more than is in stock there.
+    pub async fn record(product: &RecordId, warehouse: &RecordId, kind: MovementKind, quantity: i64, actor: Option<&RecordId>, reason: Option<String>, db: &Surreal<Client>) -> Result<Option<InventoryMovement>, Error> {
+        let mut response = db.query("RETURN fn::move_stock($product, $warehouse, $kind, $quantity, $actor, NONE, $reason)")
+            .bind(("product", product.clone()))
+            .bind(("warehouse", warehouse.clone()))
+            .bind(("kind", kind))
+            .bind(("quantity", quantity))
+            .bind(("actor", actor.cloned()))
+            .bind(("reason", reason))
+            .await?;
+
+        let errors = response.take_errors();
+        if errors.values().any(|e| e.to_string().contains("Insufficient stock")) {
+            return Ok(None);
+        }
+        if !errors.is_empty() {
+            println!("InventoryMovement DB Error : {:?}", errors);
+            return Err(Api(Query("Failed to move stock".to_string())));
+        }
+
+        let movement: Option<InventoryMovement> = response.take(0)?;
+        movement.map(Some).ok_or(Api(Query("Failed to move stock".to_string())))
+    }
+
+    /// Stock history of a product, newest first.
+    pub async fn for_product(product: &RecordId, start: u32, limit: u32, db: &Surreal<Client>) -> Result<Vec<InventoryMovement>, Error> {
+        let mut response = db.query("SELECT * FROM inventory_movement WHERE product = $product ORDER BY at DESC LIMIT $limit START $start")
+            .bind(("product", product.clone()))
+            .bind(("start", start))
+            .bind(("limit", limit))
+            .await?;
+        response.take(0)
+    }
+
+    /// Stock that doesn't add up to its movements.
+    pub async fn discrepancies(db: &Surreal<Client>) -> Result<Vec<StockDiscrepancy>, Error> {
+        let mut response = db.query(DISCREPANCIES).await?;
+        response.take(0)
+    }
+
+    /// Sets every stock level and product total that drifted from its
+    /// movements back to what they add up to, returning the ones fixed.
+    pub async fn reconcile(db: &Surreal<Client>) -> Result<Vec<StockDiscrepancy>, Error> {
+        let mut response = db.query(format!(r#"
+            BEGIN TRANSACTION;
+
+            LET $drifted = {};
+            FOR $stock IN $drifted {{
+                IF $stock.warehouse = NONE {{
+                    UPDATE $stock.product SET stock_qty = $stock.ledger_qty;
+                }} ELSE {{
+                    UPDATE type::thing('warehouse_stock', [$stock.warehouse, $stock.product]) SET stock_qty = $stock.ledger_qty;
+                }};
+            }};
+            RETURN $drifted;
+
+            COMMIT TRANSACTION;"#, DISCREPANCIES))
+            .await?;
+
+        let errors = response.take_errors();
+        if !errors.is_empty() {
+            println!("InventoryMovement DB Error : {:?}", errors);
+            return Err(Api(Query("Failed to reconcile stock".to_string())));
+        }
+        response.take(0)
+    }
+}
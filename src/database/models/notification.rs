@@ -0,0 +1,134 @@
+use rocket::serde::{Deserialize, Serialize};
+use surrealdb::engine::remote::ws::Client;
+use surrealdb::{Error, RecordId, Surreal};
+use surrealdb::Error::Api;
+use surrealdb::error::Api::Query;
+use surrealdb::sql::Datetime;
+
+use super::super::models::{DatabaseIO};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationKind {
+    BackInStock,    // to the customer in `user`
+    LowStock        // to the inventory team
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationStatus {
+    Queued,
+    Sent
+}
+
+impl NotificationStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NotificationStatus::Queued => "queued",
+            NotificationStatus::Sent => "sent"
+        }
+    }
+}
+
+/// A message waiting to be sent, queued by stock movements. The mailer
+/// picks up `queued` ones and marks them `sent`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Notification {
+    #[serde(default)]
+    pub id: Option<RecordId>,
+    pub kind: NotificationKind,
+    #[serde(default)]
+    pub user: Option<RecordId>,
+    #[serde(default)]
+    pub email: Option<String>,
+    pub product: RecordId,
+    pub sku: String,
+    pub stock_qty: i64,
+    pub status: NotificationStatus,
+    #[serde(default)]
+    pub created_at: Option<Datetime>,
+    #[serde(default)]
+    pub sent_at: Option<Datetime>
+}
+
+impl DatabaseIO for Notification {
+    type Model = Notification;
+
+    fn table_name() -> &'static str {
+        "notification"
+    }
+
+    async fn init(db: &Surreal<Client>) -> Result<(), Error> {
+        let query_str = r#"
+        DEFINE TABLE IF NOT EXISTS notification SCHEMAFULL;
+        DEFINE FIELD IF NOT EXISTS kind ON TABLE notification TYPE string
+            ASSERT $value IN ['back_in_stock', 'low_stock'] READONLY;
+        DEFINE FIELD IF NOT EXISTS user ON TABLE notification TYPE option<record<User>> READONLY;
+        DEFINE FIELD IF NOT EXISTS email ON TABLE notification TYPE option<string> READONLY;
+        DEFINE FIELD IF NOT EXISTS product ON TABLE notification TYPE record<Product> READONLY;
+        DEFINE FIELD IF NOT EXISTS sku ON TABLE notification TYPE string READONLY;
+        DEFINE FIELD IF NOT EXISTS stock_qty ON TABLE notification TYPE int READONLY;
+        DEFINE FIELD IF NOT EXISTS status ON TABLE notification TYPE string DEFAULT 'queued'
+            ASSERT $value IN ['queued', 'sent'];
+        DEFINE FIELD IF NOT EXISTS created_at ON TABLE notification TYPE datetime DEFAULT time::now() READONLY;
+        DEFINE FIELD IF NOT EXISTS sent_at ON TABLE notification TYPE option<datetime>;
+
+        DEFINE INDEX IF NOT EXISTS notificationStatusIndex ON TABLE notification FIELDS status;"#;
+
+        let resp = db.query(query_str).await;
+
+        match resp {
+            Ok(_) => {
+                println!("Notification Table Initialized...");
+                Ok(())
+            },
+            Err(e) => {
+                println!("Notifications DB Error : {:?}",e);
+
+                Err(e)
+            }
+        }
+    }
+
+    async fn get_all(db: &Surreal<Client>) -> Vec<Self::Model> {
+        let query = db.query("SELECT * FROM notification").await;
+        let mut response = query.ok().unwrap();
+        response.take(0).
+            ok().unwrap()
+    }
+
+    async fn save(self, db: &Surreal<Client>) -> Result<Self::Model, Error> {
+        match self.id.clone() {
+            None => {
+                let notification : Option<Notification> = db.create("notification").content(self).await?;
+                notification.ok_or(Api(Query("Failed to create notification".to_string())))
+            }
+            Some(id) => {
+                let notification : Option<Notification> = db.update(id).content(self).await?;
+                notification.ok_or(Api(Query("Failed to update notification".to_string())))
+            }
+        }
+    }
+}
+
+impl Notification {
+    /// Notifications in `status`, oldest first so they go out in order.
+    pub async fn with_status(status: NotificationStatus, start: u32, limit: u32, db: &Surreal<Client>) -> Result<Vec<Notification>, Error> {
+        let mut response = db.query("SELECT * FROM notification WHERE status = $status ORDER BY created_at ASC LIMIT $limit START $start")
+            .bind(("status", status))
+            .bind(("start", start))
+            .bind(("limit", limit))
+            .await?;
+        response.take(0)
+    }
+
+    /// Marks a queued notification sent. None when there is no such one
+    /// waiting, e.g. it was sent already.
+    pub async fn mark_sent(id: &str, db: &Surreal<Client>) -> Result<Option<Notification>, Error> {
+        let mut response = db.query("UPDATE type::thing('notification', $id) SET status = 'sent', sent_at = time::now() WHERE status = 'queued'")
+            .bind(("id", id.to_string()))
+            .await?;
+        let mut sent: Vec<Notification> = response.take(0)?;
+        Ok(sent.pop())
+    }
+}
@@ -0,0 +1,528 @@
+use rocket::serde::{Deserialize, Serialize};
+use surrealdb::engine::remote::ws::Client;
+use surrealdb::{Error, RecordId, Surreal};
+use surrealdb::Error::Api;
+use surrealdb::error::Api::Query;
+use surrealdb::sql::Datetime;
+
+use super::super::models::{DatabaseIO};
+use super::Product;
+use super::address::Address;
+use super::cart::{Cart, CartItem};
+use super::coupon::{AppliedCoupon, Coupon, CouponRejection};
+use super::stock_reservation::hold_until;
+use super::warehouse::{allocate, Allocation, AllocationStrategy, Warehouse, WarehouseStock};
+use crate::money::Money;
+use crate::payments::PaymentStatus;
+
+/// Where an order is in its lifecycle.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderStatus {
+    PendingPayment,
+    Paid,
+    Packed,
+    Shipped,
+    Delivered,
+    Cancelled,
+    Refunded
+}
+
+impl OrderStatus {
+    /// Whether an order may move from this status to `next`.
+    pub fn can_transition_to(self, next: OrderStatus) -> bool {
+        use OrderStatus::*;
+
+        matches!((self, next),
+            (PendingPayment, Paid) | (PendingPayment, Cancelled) |
+            (Paid, Packed) | (Paid, Cancelled) | (Paid, Refunded) |
+            (Packed, Shipped) | (Packed, Cancelled) | (Packed, Refunded) |
+            (Shipped, Delivered) |
+            (Delivered, Refunded))
+    }
+
+    /// Whether the allowed move to `next` gives money back to the customer.
+    pub fn requires_refund(self, next: OrderStatus) -> bool {
+        if !self.can_transition_to(next) {
+            return false;
+        }
+        match next {
+            OrderStatus::Refunded => true,
+            OrderStatus::Cancelled => self != OrderStatus::PendingPayment,
+            _ => false
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            OrderStatus::PendingPayment => "pending_payment",
+            OrderStatus::Paid => "paid",
+            OrderStatus::Packed => "packed",
+            OrderStatus::Shipped => "shipped",
+            OrderStatus::Delivered => "delivered",
+            OrderStatus::Cancelled => "cancelled",
+            OrderStatus::Refunded => "refunded"
+        }
+    }
+}
+
+/// A bought variant, copied from the product at checkout so later catalog
+/// edits never change what an order says.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OrderLine {
+    pub product: RecordId,
+    pub sku: String,
+    pub slug: String,
+    pub title: String,
+    pub color: String,
+    pub size: String,
+    pub unit_price: Money,
+    pub quantity: u32,
+    pub line_total: Money
+}
+
+/// Where an order goes, copied from the customer's address book at checkout
+/// and never changed after.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OrderAddress {
+    pub name: String,
+    pub phone: String,
+    pub line1: String,
+    #[serde(default)]
+    pub line2: Option<String>,
+    pub city: String,
+    pub state: String,
+    pub pincode: String,
+    pub country: String
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Order {
+    #[serde(default)]
+    pub id: Option<RecordId>,
+    pub user: RecordId,
+    pub lines: Vec<OrderLine>,
+    pub item_count: u32,
+    pub subtotal: Money,
+    #[serde(default)]
+    pub coupon: Option<AppliedCoupon>,
+    pub discount: Money,
+    pub total: Money,        // what is charged, subtotal less discount
+    #[serde(default)]
+    pub allocations: Vec<Allocation>,   // which warehouses ship the lines
+    #[serde(default)]
+    pub shipping_address: Option<OrderAddress>,     // None only for orders placed before addresses
+    pub status: OrderStatus,
+    #[serde(default)]
+    pub created_at: Option<Datetime>
+}
+
+impl DatabaseIO for Order {
+    type Model = Order;
+
+    fn table_name() -> &'static str {
+        "Order"
+    }
+
+    async fn init(db: &Surreal<Client>) -> Result<(), Error> {
+        let query_str = r#"
+        // Customers only ever reach their own orders, the routes check this as well.
+        // Status changes only go through Order::transition, which records them.
+        DEFINE TABLE OVERWRITE Order SCHEMAFULL
+            PERMISSIONS FOR select, create WHERE user = $auth.id
+            FOR update, delete NONE;
+        DEFINE FIELD IF NOT EXISTS user ON TABLE Order TYPE record<User> PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS lines ON TABLE Order TYPE array<object> PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS lines.*.product ON TABLE Order TYPE record<Product> PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS lines.*.sku ON TABLE Order TYPE string PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS lines.*.slug ON TABLE Order TYPE string PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS lines.*.title ON TABLE Order TYPE string PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS lines.*.color ON TABLE Order TYPE string PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS lines.*.size ON TABLE Order TYPE string PERMISSIONS FULL;
+        // Amounts are stored as numbers first, migrations::money_amounts makes them Money
+        DEFINE FIELD IF NOT EXISTS lines.*.unit_price ON TABLE Order TYPE number PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS lines.*.quantity ON TABLE Order TYPE int ASSERT $value > 0 PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS lines.*.line_total ON TABLE Order TYPE number PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS item_count ON TABLE Order TYPE int PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS subtotal ON TABLE Order TYPE number PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS status ON TABLE Order TYPE string DEFAULT 'pending_payment' PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS created_at ON TABLE Order TYPE datetime DEFAULT time::now() READONLY PERMISSIONS FULL;
+
+        // Coupons, orders placed before them are charged their subtotal
+        DEFINE FIELD IF NOT EXISTS coupon ON TABLE Order FLEXIBLE TYPE option<object> PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS discount ON TABLE Order TYPE number DEFAULT 0 PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS total ON TABLE Order TYPE number PERMISSIONS FULL;
+        UPDATE Order SET discount = 0, total = subtotal WHERE total = NONE;
+
+        // Warehouses, migrations::warehouses allocates orders placed before them
+        DEFINE FIELD IF NOT EXISTS allocations ON TABLE Order TYPE array<object> DEFAULT [] PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS allocations.*.product ON TABLE Order TYPE record<Product> PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS allocations.*.warehouse ON TABLE Order TYPE record<Warehouse> PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS allocations.*.quantity ON TABLE Order TYPE int ASSERT $value > 0 PERMISSIONS FULL;
+
+        // Delivery address, a copy so later edits to the address book never change the order
+        DEFINE FIELD IF NOT EXISTS shipping_address ON TABLE Order TYPE option<object> READONLY PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS shipping_address.name ON TABLE Order TYPE string READONLY PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS shipping_address.phone ON TABLE Order TYPE string READONLY PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS shipping_address.line1 ON TABLE Order TYPE string READONLY PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS shipping_address.line2 ON TABLE Order TYPE option<string> READONLY PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS shipping_address.city ON TABLE Order TYPE string READONLY PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS shipping_address.state ON TABLE Order TYPE string READONLY PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS shipping_address.pincode ON TABLE Order TYPE string READONLY PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS shipping_address.country ON TABLE Order TYPE string READONLY PERMISSIONS FULL;
+
+        DEFINE INDEX IF NOT EXISTS orderUserIndex ON TABLE Order FIELDS user;
+        DEFINE INDEX IF NOT EXISTS orderUserCreatedIndex ON TABLE Order FIELDS user, created_at;"#;
+
+        let resp = db.query(query_str).await;
+
+        match resp {
+            Ok(_) => {
+                println!("Order Table Initialized...");
+                Ok(())
+            },
+            Err(e) => {
+                println!("Orders DB Error : {:?}",e);
+
+                Err(e)
+            }
+        }
+    }
+
+    async fn get_all(db: &Surreal<Client>) -> Vec<Self::Model> {
+        let query = db.query("SELECT * FROM Order").await;
+        let mut response = query.ok().unwrap();
+        response.take(0).
+            ok().unwrap()
+    }
+
+    async fn save(self, db: &Surreal<Client>) -> Result<Self::Model, Error> {
+        match self.id.clone() {
+            None => {
+                let order : Option<Order> = db.create("Order").content(self.clone()).await?;
+                order.ok_or(Api(Query("Failed to create order".to_string())))
+            }
+            Some(id) => {
+                let order :Option<Order> = db.update(id).content(self.clone()).await?;
+                order.ok_or(Api(Query("Failed to update order".to_string())))
+            }
+        }
+    }
+}
+
+/// A cart line that asks for more than is in stock.
+#[derive(Debug, Serialize)]
+pub struct StockShortage {
+    pub sku: String,
+    pub slug: String,
+    pub title: String,
+    pub requested: u32,
+    pub available: u32
+}
+
+pub enum PlaceOrder {
+    Placed(Box<Order>),
+    EmptyCart,
+    OutOfStock(Vec<StockShortage>),
+    CouponRejected(CouponRejection)
+}
+
+impl Order {
+    fn from_cart(user: &RecordId, items: &[(CartItem, Product)], address: &Address, coupon: Option<AppliedCoupon>) -> Option<Order> {
+        let lines: Vec<OrderLine> = items.iter()
+            .map(|(item, product)| OrderLine {
+                product: item.product.clone(),
+                sku: product.sku.clone(),
+                slug: product.slug.clone(),
+                title: product.title.clone(),
+                color: product.color.clone(),
+                size: product.size.clone(),
+                line_total: product.price.times(item.quantity),
+                unit_price: product.price.clone(),
+                quantity: item.quantity
+            })
+            .collect();
+
+        // Carts are never empty here, and all products share one currency
+        let currency = lines[0].unit_price.currency.clone();
+        let discount = coupon.as_ref().map_or_else(|| Money::zero(&currency), |coupon| coupon.discount.clone());
+        let subtotal = Money::sum(&currency, lines.iter().map(|line| &line.line_total))?;
+        let total = subtotal.checked_sub(&discount)?;
+        Some(Order {
+            id: None,
+            user: user.clone(),
+            item_count: lines.iter().map(|line| line.quantity).sum(),
+            subtotal,
+            coupon,
+            discount,
+            total,
+            lines,
+            allocations: Vec::new(),
+            shipping_address: Some(address.snapshot()),
+            status: OrderStatus::PendingPayment,
+            created_at: None
+        })
+    }
+
+    pub async fn find_by_id(id: &RecordId, db: &Surreal<Client>) -> Result<Option<Order>, Error> {
+        db.select(id.clone()).await
+    }
+
+    /// `key` is the part of the order id after `Order:`.
+    pub async fn find_by_key(key: &str, db: &Surreal<Client>) -> Result<Option<Order>, Error> {
+        db.select(("Order", key)).await
+    }
+
+    /// Orders of `user`, newest first.
+    pub async fn for_user(user: &RecordId, start: u32, limit: u32, db: &Surreal<Client>) -> Result<Vec<Order>, Error> {
+        let mut response = db.query("SELECT * FROM Order WHERE user = $user ORDER BY created_at DESC LIMIT $limit START $start")
+            .bind(("user", user.clone()))
+            .bind(("start", start))
+            .bind(("limit", limit))
+            .await?;
+        response.take(0)
+    }
+
+    /// Turns the cart of `user` into an order delivered to `address`. Its
+    /// lines are allocated to warehouses by `strategy`, nearest first, and
+    /// the stock is held there for `hold_secs` in one transaction, so either
+    /// all of it is held or none, and the cart is emptied and the first order
+    /// event written in the same transaction. Stock is only taken for good
+    /// once the order is paid.
+    ///
+    /// The cart's coupon is checked again and its use recorded in the same
+    /// transaction too, so usage limits hold with concurrent orders.
+    pub async fn place(user: &RecordId, cart: &Cart, address: &Address, strategy: AllocationStrategy, hold_secs: usize, db: &Surreal<Client>) -> Result<PlaceOrder, Error> {
+        let items = cart.products(db).await?;
+        if items.is_empty() {
+            return Ok(PlaceOrder::EmptyCart);
+        }
+
+        let shortages = find_shortages(&items);
+        if !shortages.is_empty() {
+            return Ok(PlaceOrder::OutOfStock(shortages));
+        }
+
+        let coupon = match &cart.coupon_code {
+            Some(code) => match Coupon::check(code, &items, Some(user), db).await? {
+                Ok(checked) => Some(checked),
+                Err(rejection) => return Ok(PlaceOrder::CouponRejected(rejection))
+            },
+            None => None
+        };
+        let (coupon_id, per_user_limit) = coupon.as_ref()
+            .map_or((None, None), |(coupon, _)| (coupon.id.clone(), coupon.max_uses_per_user));
+
+        let mut order = Self::from_cart(user, &items, address, coupon.map(|(_, applied)| applied))
+            .ok_or(Api(Query("Order amounts are in different currencies".to_string())))?;
+        let warehouses: Vec<RecordId> = Warehouse::nearest_first(Some(&address.pincode), db).await?
+            .into_iter()
+            .filter_map(|warehouse| warehouse.id)
+            .collect();
+        let lines: Vec<(RecordId, u32)> = order.lines.iter().map(|line| (line.product.clone(), line.quantity)).collect();
+        let products: Vec<RecordId> = lines.iter().map(|(product, _)| product.clone()).collect();
+        let stock = WarehouseStock::for_products(&products, db).await?;
+        order.allocations = match allocate(&lines, &warehouses, &stock, strategy) {
+            Ok(allocations) => allocations,
+            Err(short) => return Ok(PlaceOrder::OutOfStock(warehouse_shortages(&items, &short)))
+        };
+
+        let mut response = db.query(r#"
+            BEGIN TRANSACTION;
+
+            // The stock check is repeated here, someone may have bought the last one meanwhile.
+            // Writing the stock level makes concurrent holds on it conflict.
+            FOR $allocation IN $allocations {
+                LET $level = type::thing('warehouse_stock', [$allocation.warehouse, $allocation.product]);
+                IF array::len(UPDATE $level SET stock_qty = stock_qty WHERE fn::warehouse_available_qty(warehouse, product) >= $allocation.quantity) = 0 {
+                    THROW "Out of stock";
+                };
+            };
+            UPDATE $cart SET items = [], coupon_code = NONE;
+            LET $placed = CREATE ONLY Order CONTENT $order;
+            FOR $allocation IN $allocations {
+                CREATE stock_reservation CONTENT {
+                    product: $allocation.product, warehouse: $allocation.warehouse, order: $placed.id,
+                    quantity: $allocation.quantity, expires_at: $hold_until
+                };
+            };
+            IF $coupon != NONE {
+                IF array::len(UPDATE $coupon SET used_count += 1 WHERE active = true AND (max_uses = NONE OR used_count < max_uses)) = 0 {
+                    THROW "Coupon used up";
+                };
+                IF $per_user_limit != NONE AND array::len(SELECT id FROM coupon_redemption WHERE coupon = $coupon AND user = $placed.user) >= $per_user_limit {
+                    THROW "Coupon already used";
+                };
+                CREATE coupon_redemption CONTENT { coupon: $coupon, user: $placed.user, order: $placed.id };
+            };
+            CREATE order_event CONTENT { order: $placed.id, to: $placed.status, actor: $placed.user };
+            RETURN $placed;
+
+            COMMIT TRANSACTION;"#)
+            .bind(("allocations", order.allocations.clone()))
+            .bind(("cart", cart.id.clone()))
+            .bind(("coupon", coupon_id))
+            .bind(("per_user_limit", per_user_limit))
+            .bind(("hold_until", hold_until(hold_secs)))
+            .bind(("order", order))
+            .await?;
+
+        let errors = response.take_errors();
+        if !errors.is_empty() {
+            if errors.values().any(|e| e.to_string().contains("Coupon used up")) {
+                return Ok(PlaceOrder::CouponRejected(CouponRejection::UsageLimitReached));
+            }
+            if errors.values().any(|e| e.to_string().contains("Coupon already used")) {
+                return Ok(PlaceOrder::CouponRejected(CouponRejection::UserLimitReached));
+            }
+            let stock = WarehouseStock::for_products(&products, db).await?;
+            if let Err(short) = allocate(&lines, &warehouses, &stock, strategy) {
+                return Ok(PlaceOrder::OutOfStock(warehouse_shortages(&items, &short)));
+            }
+            println!("Order DB Error : {:?}", errors);
+            return Err(Api(Query("Failed to place order".to_string())));
+        }
+
+        let order: Option<Order> = response.take(0)?;
+        order.map(|order| PlaceOrder::Placed(Box::new(order)))
+            .ok_or(Api(Query("Failed to place order".to_string())))
+    }
+
+    /// Moves the order to `to` on behalf of `actor` and records the change.
+    /// Paying takes the held stock for good, or nothing when some of it was
+    /// sold since the hold ran out. Cancelling releases the hold or,
+    /// once paid, puts the stock of every line back, each as an inventory
+    /// movement, and so does refunding an order that hasn't shipped. Cancelling or refunding also gives back the coupon's use.
+    pub async fn transition(&self, to: OrderStatus, actor: Option<&RecordId>, note: Option<String>, db: &Surreal<Client>) -> Result<Transition, Error> {
+        self.apply_transition(to, actor, note, None, db).await
+    }
+
+    /// Moves the order to `to` as the gateway reported, and stores `status`
+    /// on `payment` in the same transaction. When the order can't move the
+    /// payment is left as it was.
+    pub async fn settle_payment(&self, to: OrderStatus, payment: &RecordId, status: PaymentStatus, note: Option<String>, db: &Surreal<Client>) -> Result<Transition, Error> {
+        self.apply_transition(to, None, note, Some((payment.clone(), status)), db).await
+    }
+
+    async fn apply_transition(&self, to: OrderStatus, actor: Option<&RecordId>, note: Option<String>, payment: Option<(RecordId, PaymentStatus)>, db: &Surreal<Client>) -> Result<Transition, Error> {
+        if !self.status.can_transition_to(to) {
+            return Ok(Transition::Illegal { from: self.status, to });
+        }
+        let id = self.id.clone().ok_or(Api(Query("Order has no id".to_string())))?;
+
+        let mut response = db.query(r#"
+            BEGIN TRANSACTION;
+
+            // Only moves on if nobody changed the status since it was read
+            LET $updated = (UPDATE $id SET status = $to WHERE status = $from)[0];
+            IF $updated = NONE {
+                THROW "Order status changed";
+            };
+            IF $payment != NONE {
+                UPDATE $payment SET status = $payment_status;
+            };
+            IF $to = 'paid' {
+                FOR $allocation IN $updated.allocations {
+                    // A hold that ran out before a late capture may have been sold meanwhile,
+                    // the stock must still cover the order besides the other orders' holds
+                    LET $level = type::thing('warehouse_stock', [$allocation.warehouse, $allocation.product]);
+                    LET $held = math::sum(SELECT VALUE quantity FROM stock_reservation
+                        WHERE product = $allocation.product AND warehouse = $allocation.warehouse AND order != $id AND expires_at > time::now());
+                    IF ($level.stock_qty OR 0) - $held < $allocation.quantity {
+                        THROW "Out of stock";
+                    };
+                    fn::move_stock($allocation.product, $allocation.warehouse, 'sale', -$allocation.quantity, $actor, $id, $note);
+                };
+            };
+            IF $to = 'cancelled' AND $from != 'pending_payment' {
+                FOR $allocation IN $updated.allocations {
+                    fn::move_stock($allocation.product, $allocation.warehouse, 'return', $allocation.quantity, $actor, $id, $note);
+                };
+            };
+            // Refunded before shipping, the goods never left the warehouse
+            IF $to = 'refunded' AND $from IN ['paid', 'packed'] {
+                FOR $allocation IN $updated.allocations {
+                    fn::move_stock($allocation.product, $allocation.warehouse, 'refund_restock', $allocation.quantity, $actor, $id, $note);
+                };
+            };
+            IF $to IN ['paid', 'cancelled'] {
+                DELETE stock_reservation WHERE order = $id;
+            };
+            // A released hold may bring a variant back, as a movement would
+            IF $to = 'cancelled' AND $from = 'pending_payment' {
+                FOR $allocation IN $updated.allocations {
+                    fn::notify_back_in_stock($allocation.product);
+                };
+            };
+            // The coupon use goes back, so it counts against neither limit
+            IF $to IN ['cancelled', 'refunded'] {
+                FOR $redemption IN (DELETE coupon_redemption WHERE order = $id RETURN BEFORE) {
+                    UPDATE $redemption.coupon SET used_count -= 1 WHERE used_count > 0;
+                };
+            };
+            CREATE order_event CONTENT { order: $id, from: $from, to: $to, actor: $actor, note: $note };
+            RETURN $updated;
+
+            COMMIT TRANSACTION;"#)
+            .bind(("id", id))
+            .bind(("from", self.status))
+            .bind(("to", to))
+            .bind(("actor", actor.cloned()))
+            .bind(("note", note))
+            .bind(("payment", payment.as_ref().map(|(payment, _)| payment.clone())))
+            .bind(("payment_status", payment.map(|(_, status)| status)))
+            .await?;
+
+        let errors = response.take_errors();
+        if !errors.is_empty() {
+            if errors.values().any(|e| e.to_string().contains("Order status changed")) {
+                return Ok(Transition::Stale);
+            }
+            if errors.values().any(|e| e.to_string().contains("Out of stock")) {
+                return Ok(Transition::OutOfStock);
+            }
+            println!("Order DB Error : {:?}", errors);
+            return Err(Api(Query("Failed to update order status".to_string())));
+        }
+
+        let order: Option<Order> = response.take(0)?;
+        order.map(|order| Transition::Moved(Box::new(order)))
+            .ok_or(Api(Query("Failed to update order status".to_string())))
+    }
+}
+
+pub enum Transition {
+    Moved(Box<Order>),
+    Illegal { from: OrderStatus, to: OrderStatus },
+    Stale, // the order changed status concurrently
+    OutOfStock // paying it, its items were no longer in stock
+}
+
+fn find_shortages(items: &[(CartItem, Product)]) -> Vec<StockShortage> {
+    items.iter()
+        .filter(|(item, product)| item.quantity > product.available_qty)
+        .map(|(item, product)| StockShortage {
+            sku: product.sku.clone(),
+            slug: product.slug.clone(),
+            title: product.title.clone(),
+            requested: item.quantity,
+            available: product.available_qty
+        })
+        .collect()
+}
+
+/// Shortages of the `short` lines (product and units on hand) that
+/// `allocate` couldn't cover from the warehouses it was given.
+fn warehouse_shortages(items: &[(CartItem, Product)], short: &[(RecordId, u32)]) -> Vec<StockShortage> {
+    items.iter()
+        .filter_map(|(item, product)| short.iter()
+            .find(|(id, _)| id == &item.product)
+            .map(|(_, available)| StockShortage {
+                sku: product.sku.clone(),
+                slug: product.slug.clone(),
+                title: product.title.clone(),
+                requested: item.quantity,
+                available: *available
+            }))
+        .collect()
+}
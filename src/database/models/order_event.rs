@@ -0,0 +1,93 @@
+use rocket::serde::{Deserialize, Serialize};
+use surrealdb::engine::remote::ws::Client;
+use surrealdb::{Error, RecordId, Surreal};
+use surrealdb::Error::Api;
+use surrealdb::error::Api::Query;
+use surrealdb::sql::Datetime;
+
+use super::super::models::{DatabaseIO};
+use super::order::OrderStatus;
+
+/// One status change of an order. Events are only ever added, never edited,
+/// so together they are the order's history.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OrderEvent {
+    #[serde(default)]
+    pub id: Option<RecordId>,
+    pub order: RecordId,
+    #[serde(default)]
+    pub from: Option<OrderStatus>, // None when the order was placed
+    pub to: OrderStatus,
+    #[serde(default)]
+    pub actor: Option<RecordId>,   // None for changes reported by the payment gateway
+    #[serde(default)]
+    pub note: Option<String>,
+    #[serde(default)]
+    pub at: Option<Datetime>
+}
+
+impl DatabaseIO for OrderEvent {
+    type Model = OrderEvent;
+
+    fn table_name() -> &'static str {
+        "order_event"
+    }
+
+    async fn init(db: &Surreal<Client>) -> Result<(), Error> {
+        let query_str = r#"
+        DEFINE TABLE OVERWRITE order_event SCHEMAFULL
+            PERMISSIONS FOR select WHERE order.user = $auth.id
+            FOR create, update, delete NONE;
+
+        DEFINE FIELD IF NOT EXISTS order ON TABLE order_event TYPE record<Order> READONLY;
+        DEFINE FIELD IF NOT EXISTS from ON TABLE order_event TYPE option<string> READONLY;
+        DEFINE FIELD IF NOT EXISTS to ON TABLE order_event TYPE string READONLY;
+        DEFINE FIELD OVERWRITE actor ON TABLE order_event TYPE option<record<User>> READONLY;
+        DEFINE FIELD IF NOT EXISTS note ON TABLE order_event TYPE option<string> READONLY;
+        DEFINE FIELD IF NOT EXISTS at ON TABLE order_event TYPE datetime DEFAULT time::now() READONLY;
+
+        DEFINE INDEX IF NOT EXISTS orderEventOrderIndex ON TABLE order_event FIELDS order;"#;
+
+        let resp = db.query(query_str).await;
+
+        match resp {
+            Ok(_) => {
+                println!("OrderEvent Table Initialized...");
+                Ok(())
+            },
+            Err(e) => {
+                println!("OrderEvents DB Error : {:?}",e);
+
+                Err(e)
+            }
+        }
+    }
+
+    async fn get_all(db: &Surreal<Client>) -> Vec<Self::Model> {
+        let query = db.query("SELECT * FROM order_event").await;
+        let mut response = query.ok().unwrap();
+        response.take(0).
+            ok().unwrap()
+    }
+
+    async fn save(self, db: &Surreal<Client>) -> Result<Self::Model, Error> {
+        match self.id {
+            None => {
+                let event : Option<OrderEvent> = db.create("order_event").content(self).await?;
+                event.ok_or(Api(Query("Failed to create order_event".to_string())))
+            }
+            // Events are append-only
+            Some(_) => Err(Api(Query("Order events can not be updated".to_string())))
+        }
+    }
+}
+
+impl OrderEvent {
+    /// History of an order, oldest first.
+    pub async fn for_order(order: &RecordId, db: &Surreal<Client>) -> Result<Vec<OrderEvent>, Error> {
+        let mut response = db.query("SELECT * FROM order_event WHERE order = $order ORDER BY at ASC")
+            .bind(("order", order.clone()))
+            .await?;
+        response.take(0)
+    }
+}
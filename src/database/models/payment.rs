@@ -0,0 +1,114 @@
+use rocket::serde::{Deserialize, Serialize};
+use surrealdb::engine::remote::ws::Client;
+use surrealdb::{Error, RecordId, Surreal};
+use surrealdb::Error::Api;
+use surrealdb::error::Api::Query;
+use surrealdb::sql::Datetime;
+
+use super::super::models::{DatabaseIO};
+use crate::money::Money;
+use crate::payments::PaymentStatus;
+
+/// A payment attempt for an order at the gateway. An order may have several
+/// attempts, only the latest one counts.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Payment {
+    #[serde(default)]
+    pub id: Option<RecordId>,
+    pub order: RecordId,
+    pub provider: String,
+    pub intent_id: String,
+    pub amount: Money,
+    pub status: PaymentStatus,
+    #[serde(default)]
+    pub created_at: Option<Datetime>,
+    #[serde(default)]
+    pub updated_at: Option<Datetime>
+}
+
+impl DatabaseIO for Payment {
+    type Model = Payment;
+
+    fn table_name() -> &'static str {
+        "Payment"
+    }
+
+    async fn init(db: &Surreal<Client>) -> Result<(), Error> {
+        let query_str = r#"
+        DEFINE TABLE IF NOT EXISTS Payment SCHEMAFULL
+            PERMISSIONS FOR select WHERE order.user = $auth.id
+            FOR create, update, delete NONE;
+
+        DEFINE FIELD IF NOT EXISTS order ON TABLE Payment TYPE record<Order> READONLY;
+        DEFINE FIELD IF NOT EXISTS provider ON TABLE Payment TYPE string READONLY;
+        DEFINE FIELD IF NOT EXISTS intent_id ON TABLE Payment TYPE string READONLY;
+        DEFINE FIELD IF NOT EXISTS amount ON TABLE Payment TYPE number READONLY; // made Money by migrations::money_amounts
+        DEFINE FIELD IF NOT EXISTS currency ON TABLE Payment TYPE string READONLY;
+        DEFINE FIELD IF NOT EXISTS status ON TABLE Payment TYPE string
+            ASSERT $value IN ['pending', 'captured', 'failed', 'refunded'];
+        DEFINE FIELD IF NOT EXISTS created_at ON TABLE Payment TYPE datetime DEFAULT time::now() READONLY;
+        DEFINE FIELD IF NOT EXISTS updated_at ON TABLE Payment TYPE option<datetime> VALUE time::now();
+
+        DEFINE INDEX IF NOT EXISTS paymentOrderIndex ON TABLE Payment FIELDS order;
+        DEFINE INDEX IF NOT EXISTS paymentIntentIndex ON TABLE Payment FIELDS provider, intent_id UNIQUE;"#;
+
+        let resp = db.query(query_str).await;
+
+        match resp {
+            Ok(_) => {
+                println!("Payment Table Initialized...");
+                Ok(())
+            },
+            Err(e) => {
+                println!("Payments DB Error : {:?}",e);
+
+                Err(e)
+            }
+        }
+    }
+
+    async fn get_all(db: &Surreal<Client>) -> Vec<Self::Model> {
+        let query = db.query("SELECT * FROM Payment").await;
+        let mut response = query.ok().unwrap();
+        response.take(0).
+            ok().unwrap()
+    }
+
+    async fn save(self, db: &Surreal<Client>) -> Result<Self::Model, Error> {
+        match self.id.clone() {
+            None => {
+                let payment : Option<Payment> = db.create("Payment").content(self).await?;
+                payment.ok_or(Api(Query("Failed to create payment".to_string())))
+            }
+            Some(id) => {
+                let payment :Option<Payment> = db.update(id).content(self).await?;
+                payment.ok_or(Api(Query("Failed to update payment".to_string())))
+            }
+        }
+    }
+}
+
+impl Payment {
+    pub async fn find_by_intent(provider: &str, intent_id: &str, db: &Surreal<Client>) -> Result<Option<Payment>, Error> {
+        let mut response = db.query("SELECT * FROM Payment WHERE provider = $provider AND intent_id = $intent_id LIMIT 1")
+            .bind(("provider", provider.to_string()))
+            .bind(("intent_id", intent_id.to_string()))
+            .await?;
+        let mut payments: Vec<Payment> = response.take(0)?;
+        Ok(payments.pop())
+    }
+
+    /// The most recent payment attempt of an order.
+    pub async fn latest_for_order(order: &RecordId, db: &Surreal<Client>) -> Result<Option<Payment>, Error> {
+        let mut response = db.query("SELECT * FROM Payment WHERE order = $order ORDER BY created_at DESC LIMIT 1")
+            .bind(("order", order.clone()))
+            .await?;
+        let mut payments: Vec<Payment> = response.take(0)?;
+        Ok(payments.pop())
+    }
+
+    pub async fn set_status(mut self, status: PaymentStatus, db: &Surreal<Client>) -> Result<Payment, Error> {
+        self.status = status;
+        self.save(db).await
+    }
+}
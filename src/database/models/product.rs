@@ -1,16 +1,25 @@
-use std::collections::HashMap;
-
 use rocket::serde::{Deserialize, Serialize};
 use surrealdb::engine::remote::ws::Client;
-use surrealdb::{Error, RecordId, Surreal, sql::Value};
+use surrealdb::{Error, RecordId, Surreal, sql::{Datetime, Value}};
 use surrealdb::Error::Api;
 use surrealdb::error::Api::Query;
 use super::super::models::{DatabaseIO};
+use super::product_group::{is_valid_slug, ProductGroup};
+use super::exchange_rate::ExchangeRates;
+use crate::money::{is_valid_currency, Money};
 
+/// A single purchasable variant (one color and size) of a `ProductGroup`.
+/// Variants stay in the `Product` table rather than a table of their own, as
+/// carts, orders, stock holds, warehouse stock and the inventory ledger all
+/// point at `record<Product>`.
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Product{
     #[serde(default)]
     pub id: Option<RecordId>,
+    #[serde(default)]
+    pub product_group: Option<RecordId>,
+    #[serde(default)]
+    pub sku: String,
     pub title: String,
     pub slug: String,
     pub desc: String,
@@ -18,10 +27,18 @@ pub struct Product{
     pub category: String,
     pub color: String,
     pub size: String,
-    pub price: f32,
+    pub price: Money,
+    #[serde(default)]
+    pub price_overrides: Vec<Money>,   // set prices in other currencies, used instead of converting
     pub stock_qty: u32,
     #[serde(default)]
-    pub extras: Option<serde_json::Value>
+    pub low_stock_threshold: Option<u32>,   // the inventory team is alerted when stock falls to it
+    #[serde(default)]
+    pub available_qty: u32,     // stock less active reservations, worked out when read
+    #[serde(default)]
+    pub extras: Option<serde_json::Value>,
+    #[serde(default)]
+    pub created_at: Option<Datetime>
 }
 
 impl DatabaseIO for Product{
@@ -41,11 +58,38 @@ impl DatabaseIO for Product{
         DEFINE FIELD IF NOT EXISTS category ON TABLE Product TYPE String PERMISSIONS FULL;
         DEFINE FIELD IF NOT EXISTS color ON TABLE Product TYPE String PERMISSIONS FULL;
         DEFINE FIELD IF NOT EXISTS size ON TABLE Product TYPE String PERMISSIONS FULL;
-        DEFINE FIELD IF NOT EXISTS price ON TABLE Product TYPE Number PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS price ON TABLE Product TYPE Number PERMISSIONS FULL; // made Money by migrations::money_amounts
+        DEFINE FIELD IF NOT EXISTS price_overrides ON TABLE Product TYPE array<object> DEFAULT [] PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS price_overrides.*.amount ON TABLE Product TYPE int ASSERT $value >= 0 PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS price_overrides.*.currency ON TABLE Product TYPE string PERMISSIONS FULL;
         DEFINE FIELD IF NOT EXISTS stock_qty ON TABLE Product TYPE Number PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS low_stock_threshold ON TABLE Product TYPE option<int> ASSERT $value = NONE OR $value >= 0 PERMISSIONS FULL;
         DEFINE FIELD IF NOT EXISTS extras ON TABLE Product FLEXIBLE TYPE OBJECT DEFAULT {} PERMISSIONS FULL; // Json extra data
+        DEFINE FIELD IF NOT EXISTS product_group ON TABLE Product TYPE option<record<ProductGroup>> PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS sku ON TABLE Product TYPE option<string> PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS created_at ON TABLE Product TYPE datetime DEFAULT time::now() PERMISSIONS FULL;
+
+        // Variants created before skus existed get one derived from their slug
+        UPDATE Product SET sku = string::uppercase(slug) WHERE sku = NONE;
+        UPDATE Product SET created_at = time::now() WHERE created_at = NONE;
+        UPDATE Product SET price_overrides = [] WHERE price_overrides = NONE;
 
-        DEFINE INDEX IF NOT EXISTS slugIndex ON TABLE Product FIELDS slug UNIQUE;"#;
+        DEFINE INDEX IF NOT EXISTS slugIndex ON TABLE Product FIELDS slug UNIQUE;
+        DEFINE INDEX IF NOT EXISTS skuIndex ON TABLE Product FIELDS sku UNIQUE;
+        DEFINE INDEX IF NOT EXISTS productGroupIndex ON TABLE Product FIELDS product_group;
+        DEFINE INDEX IF NOT EXISTS categoryIndex ON TABLE Product FIELDS category;
+
+        // Full-text search. search_text joins every searchable field (extras flattened to
+        // text) so a query may match across fields, the per field indexes weight and highlight.
+        DEFINE ANALYZER IF NOT EXISTS productAnalyzer TOKENIZERS class FILTERS lowercase, ascii, snowball(english);
+        DEFINE FIELD IF NOT EXISTS search_text ON TABLE Product TYPE option<string>
+            VALUE string::join(' ', title, desc, category, <string> extras) PERMISSIONS FULL;
+        UPDATE Product WHERE search_text = NONE;
+
+        DEFINE INDEX IF NOT EXISTS titleSearchIndex ON TABLE Product FIELDS title SEARCH ANALYZER productAnalyzer BM25 HIGHLIGHTS;
+        DEFINE INDEX IF NOT EXISTS descSearchIndex ON TABLE Product FIELDS desc SEARCH ANALYZER productAnalyzer BM25 HIGHLIGHTS;
+        DEFINE INDEX IF NOT EXISTS categorySearchIndex ON TABLE Product FIELDS category SEARCH ANALYZER productAnalyzer BM25;
+        DEFINE INDEX IF NOT EXISTS fullTextSearchIndex ON TABLE Product FIELDS search_text SEARCH ANALYZER productAnalyzer BM25;"#;
 
         let resp = db.query(query_str).await;
 
@@ -64,34 +108,194 @@ impl DatabaseIO for Product{
     }
 
     async fn get_all(db: &Surreal<Client>) -> Vec<Self::Model> {
-        let query = db.query("SELECT * FROM Product").await;
+        let query = db.query(format!("SELECT {} FROM Product", PRODUCT_FIELDS)).await;
         let mut response = query.ok().unwrap();
-        let all_products = response.take(0).
-            ok().unwrap();
-        all_products
+        response.take(0).
+            ok().unwrap()
     }
 
+    /// Writes the product. A new one starts out of stock and updates keep the
+    /// stored `stock_qty`, as stock only moves through `InventoryMovement`,
+    /// which knows the warehouse it is in.
     async fn save(self, db: &Surreal<Client>) -> Result<Self::Model, Error> {
-        match self.id.clone() {
-            None => {
-                let product : Option<Product> = db.create("Product").content(self.clone()).await?;
-                product.ok_or(Api(Query("Failed to create product".to_string())))
-            }
-            Some(id) => {
-                let product :Option<Product> = db.update(id).content(self.clone()).await?;
-                product.ok_or(Api(Query("Failed to update product".to_string())))
-            }
+        let Some(id) = self.id.clone() else {
+            return self.create(None, db).await;
+        };
+        let mut response = db.query(r#"
+            BEGIN TRANSACTION;
+            LET $stock_qty = (SELECT VALUE stock_qty FROM ONLY $id);
+            UPDATE $id CONTENT $product;
+            RETURN UPDATE ONLY $id SET stock_qty = $stock_qty;
+            COMMIT TRANSACTION;"#)
+            .bind(("id", id))
+            .bind(("product", self))
+            .await?;
+
+        // Passes on the error that failed the transaction, e.g. a slug clash
+        let errors = response.take_errors();
+        if let Some(e) = errors.into_values().find(|e| !e.to_string().contains("failed transaction")) {
+            return Err(e);
         }
+        let product: Option<Product> = response.take(0)?;
+        product.ok_or(Api(Query("Failed to update product".to_string())))
     }
 }
 
+/// Stock a new product comes with, recorded as a restock by `actor`.
+pub struct OpeningStock {
+    pub warehouse: RecordId,
+    pub quantity: u32,
+    pub actor: RecordId
+}
+
 
 impl Product {
-    pub async fn find(db: &Surreal<Client>, filters: HashMap<String, Value>) -> Result<Vec<Product>, Error> {
-        // Build dynamic WHERE clause
+    /// Creates the product and the movement of its `opening` stock in one
+    /// transaction, so neither is written without the other. A product with
+    /// no group joins the one named after its title, which is made in the
+    /// same transaction when missing.
+    pub async fn create(mut self, opening: Option<OpeningStock>, db: &Surreal<Client>) -> Result<Product, Error> {
+        self.stock_qty = 0;
+        let new_group = match self.product_group {
+            None => Some(ProductGroup::for_variant(&self)),
+            Some(_) => None
+        };
+        let (warehouse, quantity, actor) = match opening {
+            Some(opening) => (Some(opening.warehouse), opening.quantity, Some(opening.actor)),
+            None => (None, 0, None)
+        };
+
+        let mut response = db.query(format!(r#"
+            BEGIN TRANSACTION;
+            LET $existing_group = (SELECT VALUE id FROM ProductGroup WHERE slug = $new_group.slug LIMIT 1)[0];
+            LET $group = IF $new_group = NONE {{ $product.product_group }}
+                ELSE IF $existing_group != NONE {{ $existing_group }}
+                ELSE {{ (CREATE ONLY ProductGroup CONTENT $new_group).id }};
+            LET $created = CREATE ONLY Product CONTENT $product;
+            UPDATE $created.id SET product_group = $group;
+            IF $warehouse != NONE AND $quantity > 0 {{
+                fn::move_stock($created.id, $warehouse, 'restock', $quantity, $actor, NONE, 'Opening stock');
+            }};
+            RETURN SELECT {} FROM ONLY $created.id;
+            COMMIT TRANSACTION;"#, PRODUCT_FIELDS))
+            .bind(("product", self))
+            .bind(("new_group", new_group))
+            .bind(("warehouse", warehouse))
+            .bind(("quantity", quantity))
+            .bind(("actor", actor))
+            .await?;
+
+        // Passes on the error that failed the transaction, e.g. a slug clash
+        let errors = response.take_errors();
+        if let Some(e) = errors.into_values().find(|e| !e.to_string().contains("failed transaction")) {
+            return Err(e);
+        }
+        let product: Option<Product> = response.take(0)?;
+        product.ok_or(Api(Query("Failed to create product".to_string())))
+    }
+
+    pub async fn find_by_slug(slug: &str, db: &Surreal<Client>) -> Result<Option<Product>, Error> {
+        let mut response = db.query(format!("SELECT {} FROM Product WHERE slug = $slug LIMIT 1", PRODUCT_FIELDS))
+            .bind(("slug", slug.to_string()))
+            .await?;
+        let mut products: Vec<Product> = response.take(0)?;
+        Ok(products.pop())
+    }
+
+    pub async fn find_by_sku(sku: &str, db: &Surreal<Client>) -> Result<Option<Product>, Error> {
+        let mut response = db.query(format!("SELECT {} FROM Product WHERE sku = $sku LIMIT 1", PRODUCT_FIELDS))
+            .bind(("sku", sku.to_string()))
+            .await?;
+        let mut products: Vec<Product> = response.take(0)?;
+        Ok(products.pop())
+    }
+
+    /// Products at or below their low stock threshold, the emptiest first.
+    pub async fn low_stock(db: &Surreal<Client>) -> Result<Vec<Product>, Error> {
+        let mut response = db.query(format!("SELECT {} FROM Product WHERE low_stock_threshold != NONE AND stock_qty <= low_stock_threshold ORDER BY stock_qty ASC", PRODUCT_FIELDS))
+            .await?;
+        response.take(0)
+    }
+
+    pub async fn delete(self, db: &Surreal<Client>) -> Result<Option<Product>, Error> {
+        match self.id {
+            Some(id) => db.delete(id).await,
+            None => Ok(None)
+        }
+    }
+
+    /// Checks the fields an admin may send, returning every problem found.
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errors = Vec::new();
+
+        for (field, value) in [("title", &self.title), ("desc", &self.desc), ("img", &self.img),
+                               ("category", &self.category), ("color", &self.color), ("size", &self.size)] {
+            if value.trim().is_empty() {
+                errors.push(format!("{} must not be empty", field));
+            }
+        }
+
+        if !is_valid_slug(&self.slug) {
+            errors.push("slug must only contain lowercase letters, digits and dashes".to_string());
+        }
+
+        if self.sku.trim().is_empty() {
+            errors.push("sku must not be empty".to_string());
+        }
+
+        if self.price.is_negative() || self.price.is_zero() {
+            errors.push("price must be positive".to_string());
+        }
+
+        if !is_valid_currency(&self.price.currency) {
+            errors.push("price currency must be an ISO 4217 code".to_string());
+        }
+
+        for (index, price) in self.price_overrides.iter().enumerate() {
+            if price.is_negative() || price.is_zero() {
+                errors.push(format!("price override in {} must be positive", price.currency));
+            }
+            if !is_valid_currency(&price.currency) {
+                errors.push(format!("price override currency {} must be an ISO 4217 code", price.currency));
+            }
+            if price.currency == self.price.currency || self.price_overrides[..index].iter().any(|other| other.currency == price.currency) {
+                errors.push(format!("only one price may be set in {}", price.currency));
+            }
+        }
+
+        if self.extras.as_ref().is_some_and(|extras| !extras.is_object()) {
+            errors.push("extras must be an object".to_string());
+        }
+
+        if errors.is_empty() { Ok(()) } else { Err(errors) }
+    }
+
+    /// What the product sells for in `currency`, None if it can't be shown in it.
+    pub fn price_in(&self, currency: &str, rates: &ExchangeRates) -> Option<Money> {
+        rates.price(&self.price, &self.price_overrides, currency)
+    }
+
+    /// Whether a database error is the unique `slugIndex` rejecting a write.
+    pub fn is_slug_conflict(e: &Error) -> bool {
+        e.to_string().contains("slugIndex")
+    }
+
+    /// Whether a database error is the unique `skuIndex` rejecting a write.
+    pub fn is_sku_conflict(e: &Error) -> bool {
+        e.to_string().contains("skuIndex")
+    }
+
+    pub async fn find(db: &Surreal<Client>, query: ProductQuery) -> Result<Vec<Product>, Error> {
+        // Field names end up in the query text, so only known ones get through
+        if let Some((field, _, _)) = query.filters.iter().find(|(field, _, _)| !FILTERABLE_FIELDS.contains(&field.as_str())) {
+            return Err(Api(Query(format!("Cannot filter products by {}", field))));
+        }
+
+        // Build dynamic WHERE clause, one binding per filter so a field can be bounded twice
         let mut where_clauses = Vec::new();
-        for key in filters.keys() {
-            where_clauses.push(format!("{key} = ${key}"));
+        for (index, (field, op, _)) in query.filters.iter().enumerate() {
+            let field = if field == "available_qty" { "fn::available_qty(id)" } else { field };
+            where_clauses.push(format!("{field} {} $filter{index}", op.as_sql()));
         }
 
         let where_clause = if where_clauses.is_empty() {
@@ -100,16 +304,185 @@ impl Product {
             format!("WHERE {}", where_clauses.join(" AND "))
         };
 
+        let order_clause = match query.sort {
+            // Ties are broken by id, otherwise pages could overlap or skip products
+            Some(sort) => format!("ORDER BY {}, id", sort.as_sql()),
+            None => "ORDER BY id".to_string()
+        };
+
+        let limit_clause = match query.limit {
+            Some(limit) => format!("LIMIT {} START {}", limit, query.start),
+            None => format!("START {}", query.start)
+        };
+
         // Build and bind query dynamically
-        let query = format!("SELECT * FROM Product {}", where_clause);
-        let mut query_builder = db.query(&query);
+        let sql = format!("SELECT {} FROM Product {} {} {}", PRODUCT_FIELDS, where_clause, order_clause, limit_clause);
+        let mut query_builder = db.query(&sql);
 
-        for (key, val) in filters {
-            query_builder = query_builder.bind((key, val));
+        for (index, (_, _, val)) in query.filters.into_iter().enumerate() {
+            query_builder = query_builder.bind((format!("filter{index}"), val));
         }
 
         let mut response = query_builder.await?;
         let result: Vec<Product> = response.take(0)?;
         Ok(result)
     }
-}
\ No newline at end of file
+}
+
+/// A product matched by `Product::search`, best matches first.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SearchHit {
+    pub sku: String,
+    pub slug: String,
+    pub title: String,
+    pub img: String,
+    pub category: String,
+    pub color: String,
+    pub size: String,
+    pub price: Money,
+    #[serde(default, skip_serializing)]
+    pub price_overrides: Vec<Money>,
+    pub available_qty: u32,
+    pub score: f32,
+    #[serde(default)]
+    pub title_highlight: Option<String>,
+    #[serde(default)]
+    pub desc_highlight: Option<String>
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FacetCount {
+    pub value: String,
+    pub count: u32
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchFacets {
+    pub category: Vec<FacetCount>,
+    pub color: Vec<FacetCount>,
+    pub size: Vec<FacetCount>
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchResults {
+    pub hits: Vec<SearchHit>,
+    pub facets: SearchFacets
+}
+
+impl Product {
+    /// Ranks products against `terms` across title, desc, category and extras.
+    /// Title matches weigh the most. Facets count every match, not just the page.
+    pub async fn search(db: &Surreal<Client>, terms: &str, start: u32, limit: u32) -> Result<SearchResults, Error> {
+        let mut response = db.query(r#"
+            SELECT sku, slug, title, img, category, color, size, price, price_overrides, fn::available_qty(id) AS available_qty,
+                (search::score(0) ?? 0) * 3 + (search::score(1) ?? 0) + (search::score(2) ?? 0) * 2 + (search::score(3) ?? 0) AS score,
+                search::highlight('<mark>', '</mark>', 0) AS title_highlight,
+                search::highlight('<mark>', '</mark>', 1) AS desc_highlight
+            FROM Product
+            WHERE search_text @3@ $terms OR title @0@ $terms OR desc @1@ $terms OR category @2@ $terms
+            ORDER BY score DESC
+            LIMIT $limit START $start;
+
+            SELECT category AS value, count() AS count FROM Product
+            WHERE search_text @@ $terms
+            GROUP BY value ORDER BY count DESC;
+
+            SELECT color AS value, count() AS count FROM Product
+            WHERE search_text @@ $terms
+            GROUP BY value ORDER BY count DESC;
+
+            SELECT size AS value, count() AS count FROM Product
+            WHERE search_text @@ $terms
+            GROUP BY value ORDER BY count DESC;"#)
+            .bind(("terms", terms.to_string()))
+            .bind(("start", start))
+            .bind(("limit", limit))
+            .await?;
+
+        let hits: Vec<SearchHit> = response.take(0)?;
+        let facets = SearchFacets {
+            category: response.take(1)?,
+            color: response.take(2)?,
+            size: response.take(3)?
+        };
+
+        Ok(SearchResults { hits, facets })
+    }
+}
+
+/// Fields `Product::find` accepts filters on.
+pub const FILTERABLE_FIELDS: [&str; 9] = ["title", "slug", "sku", "category", "color", "size", "price.amount", "stock_qty", "available_qty"];
+
+/// What product queries select, every column plus `available_qty`.
+pub const PRODUCT_FIELDS: &str = "*, fn::available_qty(id) AS available_qty";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterOp {
+    Eq,
+    Gt,
+    Gte,
+    Lt,
+    Lte
+}
+
+impl FilterOp {
+    fn as_sql(&self) -> &'static str {
+        match self {
+            FilterOp::Eq => "=",
+            FilterOp::Gt => ">",
+            FilterOp::Gte => ">=",
+            FilterOp::Lt => "<",
+            FilterOp::Lte => "<="
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProductSort {
+    PriceAsc,
+    PriceDesc,
+    Newest,
+    Title
+}
+
+impl ProductSort {
+    fn as_sql(&self) -> &'static str {
+        match self {
+            ProductSort::PriceAsc => "price.amount ASC",
+            ProductSort::PriceDesc => "price.amount DESC",
+            ProductSort::Newest => "created_at DESC",
+            ProductSort::Title => "title ASC"
+        }
+    }
+}
+
+/// Filters, ordering and paging for `Product::find`.
+#[derive(Debug, Default)]
+pub struct ProductQuery {
+    filters: Vec<(String, FilterOp, Value)>,
+    sort: Option<ProductSort>,
+    limit: Option<u32>,
+    start: u32
+}
+
+impl ProductQuery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn filter(mut self, field: &str, op: FilterOp, value: impl Into<Value>) -> Self {
+        self.filters.push((field.to_string(), op, value.into()));
+        self
+    }
+
+    pub fn sort(mut self, sort: ProductSort) -> Self {
+        self.sort = Some(sort);
+        self
+    }
+
+    pub fn page(mut self, start: u32, limit: u32) -> Self {
+        self.start = start;
+        self.limit = Some(limit);
+        self
+    }
+}
@@ -0,0 +1,181 @@
+use std::collections::HashMap;
+
+use rocket::serde::{Deserialize, Serialize};
+use surrealdb::engine::remote::ws::Client;
+use surrealdb::{Error, RecordId, Surreal};
+use surrealdb::Error::Api;
+use surrealdb::error::Api::Query;
+use super::super::models::{DatabaseIO};
+use super::Product;
+use super::product::PRODUCT_FIELDS;
+
+/// What a shopper thinks of as "a product": the shared title, description and
+/// images. Every `Product` row linked to it is one purchasable variant of it.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ProductGroup {
+    #[serde(default)]
+    pub id: Option<RecordId>,
+    pub title: String,
+    pub slug: String,
+    pub desc: String,
+    pub category: String,
+    #[serde(default)]
+    pub images: Vec<String>
+}
+
+impl DatabaseIO for ProductGroup {
+    type Model = ProductGroup;
+
+    fn table_name() -> &'static str {
+        "ProductGroup"
+    }
+
+    async fn init(db: &Surreal<Client>) -> Result<(), Error> {
+        let query_str = r#"
+        DEFINE TABLE IF NOT EXISTS ProductGroup SCHEMAFULL;
+        DEFINE FIELD IF NOT EXISTS title ON TABLE ProductGroup TYPE String PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS slug ON TABLE ProductGroup TYPE String PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS desc ON TABLE ProductGroup TYPE String PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS category ON TABLE ProductGroup TYPE String PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS images ON TABLE ProductGroup TYPE array<string> DEFAULT [] PERMISSIONS FULL;
+
+        DEFINE INDEX IF NOT EXISTS groupSlugIndex ON TABLE ProductGroup FIELDS slug UNIQUE;"#;
+
+        let resp = db.query(query_str).await;
+
+        match resp {
+            Ok(_) => {
+                println!("ProductGroup Table Initialized...");
+                Ok(())
+            },
+            Err(e) => {
+                println!("ProductGroups DB Error : {:?}",e);
+
+                Err(e)
+            }
+        }
+    }
+
+    async fn get_all(db: &Surreal<Client>) -> Vec<Self::Model> {
+        let query = db.query("SELECT * FROM ProductGroup").await;
+        let mut response = query.ok().unwrap();
+        response.take(0).
+            ok().unwrap()
+    }
+
+    async fn save(self, db: &Surreal<Client>) -> Result<Self::Model, Error> {
+        match self.id.clone() {
+            None => {
+                let group : Option<ProductGroup> = db.create("ProductGroup").content(self.clone()).await?;
+                group.ok_or(Api(Query("Failed to create product group".to_string())))
+            }
+            Some(id) => {
+                let group :Option<ProductGroup> = db.update(id).content(self.clone()).await?;
+                group.ok_or(Api(Query("Failed to update product group".to_string())))
+            }
+        }
+    }
+}
+
+impl ProductGroup {
+    pub async fn find_by_slug(slug: &str, db: &Surreal<Client>) -> Result<Option<ProductGroup>, Error> {
+        let mut response = db.query("SELECT * FROM ProductGroup WHERE slug = $slug LIMIT 1")
+            .bind(("slug", slug.to_string()))
+            .await?;
+        let mut groups: Vec<ProductGroup> = response.take(0)?;
+        Ok(groups.pop())
+    }
+
+    /// A new group for `product`, named after its title. Titles without
+    /// letters or digits take the variant's slug instead.
+    pub fn for_variant(product: &Product) -> ProductGroup {
+        let slug = match slugify(&product.title) {
+            slug if slug.is_empty() => product.slug.clone(),
+            slug => slug
+        };
+
+        ProductGroup {
+            id: None,
+            title: product.title.clone(),
+            slug,
+            desc: product.desc.clone(),
+            category: product.category.clone(),
+            images: vec![product.img.clone()]
+        }
+    }
+
+    /// Group a variant belongs to by its title, created from the variant when missing.
+    pub async fn find_or_create_for(product: &Product, db: &Surreal<Client>) -> Result<ProductGroup, Error> {
+        let group = Self::for_variant(product);
+        if let Some(existing) = Self::find_by_slug(&group.slug, db).await? {
+            return Ok(existing);
+        }
+        group.save(db).await
+    }
+
+    /// Variants of this group, in no particular order.
+    pub async fn variants(&self, db: &Surreal<Client>) -> Result<Vec<Product>, Error> {
+        let mut response = db.query(format!("SELECT {} FROM Product WHERE product_group = $group", PRODUCT_FIELDS))
+            .bind(("group", self.id.clone()))
+            .await?;
+        response.take(0)
+    }
+
+    /// Moves products created before groups existed into a group per title.
+    pub async fn link_ungrouped_products(db: &Surreal<Client>) -> Result<(), Error> {
+        let mut response = db.query("SELECT * FROM Product WHERE product_group = NONE").await?;
+        let products: Vec<Product> = response.take(0)?;
+
+        let mut by_title: HashMap<String, Vec<Product>> = HashMap::new();
+        for product in products {
+            by_title.entry(product.title.clone()).or_default().push(product);
+        }
+
+        for variants in by_title.into_values() {
+            let mut group = Self::find_or_create_for(&variants[0], db).await?;
+            for variant in &variants {
+                if !group.images.contains(&variant.img) {
+                    group.images.push(variant.img.clone());
+                }
+            }
+            let group = group.save(db).await?;
+
+            db.query("UPDATE Product SET product_group = $group WHERE product_group = NONE AND title = $title")
+                .bind(("group", group.id.clone()))
+                .bind(("title", group.title.clone()))
+                .await?
+                .check()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errors = Vec::new();
+
+        for (field, value) in [("title", &self.title), ("desc", &self.desc), ("category", &self.category)] {
+            if value.trim().is_empty() {
+                errors.push(format!("{} must not be empty", field));
+            }
+        }
+
+        if !is_valid_slug(&self.slug) {
+            errors.push("slug must only contain lowercase letters, digits and dashes".to_string());
+        }
+
+        if errors.is_empty() { Ok(()) } else { Err(errors) }
+    }
+}
+
+pub fn is_valid_slug(slug: &str) -> bool {
+    !slug.is_empty() && slug.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+}
+
+/// Turns a title into a slug, e.g. "Hacker Hoodie®" into "hacker-hoodie".
+pub fn slugify(title: &str) -> String {
+    title.to_lowercase()
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<&str>>()
+        .join("-")
+}
@@ -0,0 +1,117 @@
+use serde::{Deserialize, Serialize};
+use surrealdb::engine::remote::ws::Client;
+use surrealdb::{Error, RecordId, Surreal};
+use surrealdb::Error::Api;
+use surrealdb::error::Api::Query;
+
+use super::super::models::{DatabaseIO};
+
+pub const CUSTOMER: &str = "customer";
+pub const SUPPORT: &str = "support";
+pub const CATALOG_MANAGER: &str = "catalog-manager";
+pub const MARKETING: &str = "marketing";
+pub const ADMIN: &str = "admin";
+
+/// Permission names carried in `Role.permissions` and in the JWT claims.
+pub mod permissions {
+    pub const ALL: &str = "*";
+    pub const PRODUCTS_MANAGE: &str = "products:manage";
+    pub const INVENTORY_MANAGE: &str = "inventory:manage";
+    pub const ORDERS_READ: &str = "orders:read";
+    pub const ORDERS_MANAGE: &str = "orders:manage";
+    pub const USERS_READ: &str = "users:read";
+    pub const USERS_MANAGE: &str = "users:manage";
+    pub const COUPONS_MANAGE: &str = "coupons:manage";
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Role {
+    #[serde(default)]
+    pub id: Option<RecordId>,
+    pub name: String,
+    #[serde(default)]
+    pub permissions: Vec<String>
+}
+
+impl DatabaseIO for Role {
+    type Model = Role;
+
+    fn table_name() -> &'static str {
+        "Role"
+    }
+
+    async fn init(db: &Surreal<Client>) -> Result<(), Error> {
+        // Default roles are only inserted once, edits made afterwards are kept
+        let query_str = r#"
+        DEFINE TABLE IF NOT EXISTS Role SCHEMAFULL;
+        DEFINE FIELD IF NOT EXISTS name ON TABLE Role TYPE string PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS permissions ON TABLE Role TYPE array<string> DEFAULT [] PERMISSIONS FULL;
+
+        DEFINE INDEX IF NOT EXISTS nameIndex ON TABLE Role FIELDS name UNIQUE;
+
+        INSERT IGNORE INTO Role [
+            { id: "customer", name: "customer", permissions: [] },
+            { id: "support", name: "support", permissions: ["orders:read", "users:read"] },
+            { id: "catalog-manager", name: "catalog-manager", permissions: ["products:manage", "inventory:manage"] },
+            { id: "marketing", name: "marketing", permissions: ["coupons:manage"] },
+            { id: "admin", name: "admin", permissions: ["*"] }
+        ];"#;
+
+        let resp = db.query(query_str).await;
+
+        match resp {
+            Ok(_) => {
+                println!("Role Table Initialized...");
+                Ok(())
+            },
+            Err(e) => {
+                println!("Roles DB Error : {:?}",e);
+
+                Err(e)
+            }
+        }
+    }
+
+    async fn get_all(db: &Surreal<Client>) -> Vec<Self::Model> {
+        let query = db.query("SELECT * FROM Role").await;
+        let mut response = query.ok().unwrap();
+        response.take(0).
+            ok().unwrap()
+    }
+
+    async fn save(self, db: &Surreal<Client>) -> Result<Self::Model, Error> {
+        match self.id.clone() {
+            None => {
+                let role : Option<Role> = db.create("Role").content(self.clone()).await?;
+                role.ok_or(Api(Query("Failed to create role".to_string())))
+            }
+            Some(id) => {
+                let role :Option<Role> = db.update(id).content(self.clone()).await?;
+                role.ok_or(Api(Query("Failed to update role".to_string())))
+            }
+        }
+    }
+}
+
+impl Role {
+    /// Union of the permissions granted by the given roles.
+    pub async fn permissions_for(roles: &[String], db: &Surreal<Client>) -> Result<Vec<String>, Error> {
+        let mut response = db.query("SELECT VALUE permissions FROM Role WHERE name IN $roles")
+            .bind(("roles", roles.to_vec()))
+            .await?;
+        let granted: Vec<Vec<String>> = response.take(0)?;
+
+        let mut permissions: Vec<String> = granted.into_iter().flatten().collect();
+        permissions.sort();
+        permissions.dedup();
+        Ok(permissions)
+    }
+
+    pub async fn exists(name: &str, db: &Surreal<Client>) -> Result<bool, Error> {
+        let mut response = db.query("SELECT VALUE name FROM Role WHERE name = $name")
+            .bind(("name", name.to_string()))
+            .await?;
+        let names: Vec<String> = response.take(0)?;
+        Ok(!names.is_empty())
+    }
+}
@@ -8,6 +8,7 @@ use surrealdb::sql::{Datetime,Uuid};
 
 use super::super::models::{DatabaseIO};
 use super::User;
+use crate::utils::auth::ClientInfo;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct SessionToken {
@@ -18,7 +19,19 @@ pub struct SessionToken {
     pub issued_at: Datetime,      // Unix timestamp for when the token was issued
     pub expires_at: Datetime,     // Unix timestamp for when the token expires
     #[serde(default)]
-    pub revoked: bool      // Indicates if the token is revoked
+    pub revoked: bool,     // Indicates if the token is revoked
+    #[serde(default)]
+    pub user_agent: Option<String>, // Client that requested the token
+    #[serde(default)]
+    pub ip: Option<String>,         // Address the token was requested from
+    #[serde(default)]
+    pub family: Option<Uuid>,       // Shared by every session rotated out of the same login
+    #[serde(default)]
+    pub refresh_hash: Option<String>,          // SHA-256 of the opaque refresh token
+    #[serde(default)]
+    pub refresh_expires_at: Option<Datetime>,  // When the refresh token stops being accepted
+    #[serde(default)]
+    pub rotated: bool               // Refresh token was already exchanged for a new session
 }
 
 impl DatabaseIO for SessionToken{
@@ -50,6 +63,34 @@ impl DatabaseIO for SessionToken{
             PERMISSIONS FOR update, delete WHERE false;
         
         DEFINE FIELD IF NOT EXISTS revoked ON TABLE sessiontoken TYPE bool DEFAULT false;
+
+        DEFINE FIELD IF NOT EXISTS user_agent ON TABLE sessiontoken TYPE option<string>
+            PERMISSIONS FOR select, create WHERE true
+            PERMISSIONS FOR update, delete WHERE false;
+
+        DEFINE FIELD IF NOT EXISTS ip ON TABLE sessiontoken TYPE option<string>
+            PERMISSIONS FOR select, create WHERE true
+            PERMISSIONS FOR update, delete WHERE false;
+        
+        DEFINE FIELD IF NOT EXISTS family ON TABLE sessiontoken TYPE option<uuid>
+            PERMISSIONS FOR select, create WHERE true
+            PERMISSIONS FOR update, delete WHERE false;
+
+        DEFINE FIELD IF NOT EXISTS refresh_hash ON TABLE sessiontoken TYPE option<string>
+            PERMISSIONS FOR select, create WHERE true
+            PERMISSIONS FOR update, delete WHERE false;
+
+        DEFINE FIELD IF NOT EXISTS refresh_expires_at ON TABLE sessiontoken TYPE option<datetime>
+            PERMISSIONS FOR select, create WHERE true
+            PERMISSIONS FOR update, delete WHERE false;
+
+        DEFINE FIELD IF NOT EXISTS rotated ON TABLE sessiontoken TYPE bool DEFAULT false;
+        // Sessions issued before refresh tokens have never been rotated
+        UPDATE sessiontoken SET rotated = false WHERE rotated = NONE;
+        
+        DEFINE INDEX IF NOT EXISTS userIndex ON TABLE sessiontoken FIELDS user;
+        DEFINE INDEX IF NOT EXISTS familyIndex ON TABLE sessiontoken FIELDS family;
+        DEFINE INDEX IF NOT EXISTS refreshHashIndex ON TABLE sessiontoken FIELDS refresh_hash UNIQUE;
         
         DEFINE INDEX IF NOT EXISTS jtiIndex ON TABLE sessiontoken FIELDS jti UNIQUE;"#;
 
@@ -72,9 +113,8 @@ impl DatabaseIO for SessionToken{
     async fn get_all(db: &Surreal<Client>) -> Vec<Self::Model> {
         let query = db.query("SELECT * FROM sessiontoken").await;
         let mut response = query.ok().unwrap();
-        let all_session_tokens = response.take(0).
-            ok().unwrap();
-        all_session_tokens
+        response.take(0).
+            ok().unwrap()
     }
 
     async fn save(self, db: &Surreal<Client>) -> Result<Self::Model, Error> {
@@ -92,19 +132,111 @@ impl DatabaseIO for SessionToken{
 }
 
 impl SessionToken {
-    pub fn new(jti: Uuid, user: &User, issued_at : usize, expires_at : usize) -> Self {
+    pub fn new(jti: Uuid, user: &User, client: &ClientInfo, issued_at : usize, expires_at : usize) -> Self {
         let userid = user.id.clone().unwrap();
         SessionToken {
             id : None,
             jti,
             user : userid,
-            issued_at : Datetime::from(Utc.timestamp_opt(issued_at as i64, 0)
-                .single()
-                .expect("Invalid timestamp")),
-            expires_at : Datetime::from(Utc.timestamp_opt(expires_at as i64, 0)
-                .single()
-                .expect("Invalid timestamp")),
-            revoked: false
+            issued_at : to_datetime(issued_at),
+            expires_at : to_datetime(expires_at),
+            revoked: false,
+            user_agent: client.user_agent.clone(),
+            ip: client.ip.clone(),
+            family: None,
+            refresh_hash: None,
+            refresh_expires_at: None,
+            rotated: false
+        }
+    }
+
+    /// Attaches a refresh token (by its hash) to the session.
+    pub fn with_refresh(mut self, family: Uuid, refresh_hash: String, refresh_expires_at: usize) -> Self {
+        self.family = Some(family);
+        self.refresh_hash = Some(refresh_hash);
+        self.refresh_expires_at = Some(to_datetime(refresh_expires_at));
+        self
+    }
+
+    pub async fn find_by_jti(jti: Uuid, db: &Surreal<Client>) -> Result<Option<SessionToken>, Error> {
+        let mut response = db.query("SELECT * FROM sessiontoken WHERE jti = $jti LIMIT 1")
+            .bind(("jti", jti))
+            .await?;
+        let mut session_tokens: Vec<SessionToken> = response.take(0)?;
+        Ok(session_tokens.pop())
+    }
+
+    pub async fn find_by_refresh_hash(refresh_hash: &str, db: &Surreal<Client>) -> Result<Option<SessionToken>, Error> {
+        let mut response = db.query("SELECT * FROM sessiontoken WHERE refresh_hash = $refresh_hash LIMIT 1")
+            .bind(("refresh_hash", refresh_hash.to_string()))
+            .await?;
+        let mut session_tokens: Vec<SessionToken> = response.take(0)?;
+        Ok(session_tokens.pop())
+    }
+
+    /// Sessions of a user that are neither revoked nor expired, newest first.
+    /// Sessions that were rotated out by a refresh are left out, so every login
+    /// shows up once.
+    pub async fn find_active_for_user(user: &RecordId, db: &Surreal<Client>) -> Result<Vec<SessionToken>, Error> {
+        let mut response = db.query(r#"
+            SELECT * FROM sessiontoken
+            WHERE user = $user AND revoked = false AND rotated != true
+                AND (refresh_expires_at ?? expires_at) > time::now()
+            ORDER BY issued_at DESC"#)
+            .bind(("user", user.clone()))
+            .await?;
+        response.take(0)
+    }
+
+    /// Marks the refresh token of this session as used. Returns `false` when
+    /// someone else already rotated it, which means the token was replayed.
+    pub async fn mark_rotated(&self, db: &Surreal<Client>) -> Result<bool, Error> {
+        let id = self.id.clone().ok_or(Api(Query("Session token has no id".to_string())))?;
+        let mut response = db.query("UPDATE $id SET rotated = true WHERE rotated != true RETURN AFTER")
+            .bind(("id", id))
+            .await?;
+        let session_tokens: Vec<SessionToken> = response.take(0)?;
+        Ok(!session_tokens.is_empty())
+    }
+
+    /// Revokes a session together with every session rotated out of the same
+    /// login, only if it belongs to `user`. Returns the revoked sessions.
+    pub async fn revoke(jti: Uuid, user: &RecordId, db: &Surreal<Client>) -> Result<Vec<SessionToken>, Error> {
+        let session = match Self::find_by_jti(jti, db).await? {
+            Some(session) if &session.user == user => session,
+            _ => return Ok(Vec::new())
+        };
+
+        match session.family {
+            Some(family) => Self::revoke_family(family, db).await,
+            None => {
+                let mut response = db.query("UPDATE sessiontoken SET revoked = true WHERE jti = $jti RETURN AFTER")
+                    .bind(("jti", jti))
+                    .await?;
+                response.take(0)
+            }
         }
     }
+
+    /// Revokes every session sharing a refresh token family.
+    pub async fn revoke_family(family: Uuid, db: &Surreal<Client>) -> Result<Vec<SessionToken>, Error> {
+        let mut response = db.query("UPDATE sessiontoken SET revoked = true WHERE family = $family AND revoked = false RETURN AFTER")
+            .bind(("family", family))
+            .await?;
+        response.take(0)
+    }
+
+    /// Revokes every live session of `user` and returns the revoked ones.
+    pub async fn revoke_all_for_user(user: &RecordId, db: &Surreal<Client>) -> Result<Vec<SessionToken>, Error> {
+        let mut response = db.query("UPDATE sessiontoken SET revoked = true WHERE user = $user AND revoked = false RETURN AFTER")
+            .bind(("user", user.clone()))
+            .await?;
+        response.take(0)
+    }
+}
+
+fn to_datetime(timestamp: usize) -> Datetime {
+    Datetime::from(Utc.timestamp_opt(timestamp as i64, 0)
+        .single()
+        .expect("Invalid timestamp"))
 }
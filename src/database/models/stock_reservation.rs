@@ -0,0 +1,181 @@
+use std::time::Duration;
+
+use chrono::Utc;
+use rocket::serde::{Deserialize, Serialize};
+use surrealdb::engine::remote::ws::Client;
+use surrealdb::{Error, RecordId, Surreal};
+use surrealdb::Error::Api;
+use surrealdb::error::Api::Query;
+use surrealdb::sql::Datetime;
+
+use super::super::models::{DatabaseIO};
+use super::Order;
+
+/// Stock held for an order awaiting payment. It stops counting once
+/// `expires_at` passes, so an abandoned checkout frees its items on its own,
+/// and is dropped when the order is paid or cancelled.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StockReservation {
+    #[serde(default)]
+    pub id: Option<RecordId>,
+    pub product: RecordId,
+    pub warehouse: RecordId,
+    pub order: RecordId,
+    pub quantity: u32,
+    pub expires_at: Datetime,
+    #[serde(default)]
+    pub created_at: Option<Datetime>
+}
+
+impl DatabaseIO for StockReservation {
+    type Model = StockReservation;
+
+    fn table_name() -> &'static str {
+        "stock_reservation"
+    }
+
+    async fn init(db: &Surreal<Client>) -> Result<(), Error> {
+        let query_str = r#"
+        DEFINE TABLE IF NOT EXISTS stock_reservation SCHEMAFULL;
+        DEFINE FIELD IF NOT EXISTS product ON TABLE stock_reservation TYPE record<Product> READONLY;
+        DEFINE FIELD IF NOT EXISTS order ON TABLE stock_reservation TYPE record<Order> READONLY;
+        // NONE only for holds made before warehouses, migrations::warehouses fills it in
+        DEFINE FIELD IF NOT EXISTS warehouse ON TABLE stock_reservation TYPE option<record<Warehouse>>;
+        DEFINE FIELD IF NOT EXISTS quantity ON TABLE stock_reservation TYPE int ASSERT $value > 0 READONLY;
+        DEFINE FIELD IF NOT EXISTS expires_at ON TABLE stock_reservation TYPE datetime;
+        DEFINE FIELD IF NOT EXISTS created_at ON TABLE stock_reservation TYPE datetime DEFAULT time::now() READONLY;
+
+        DEFINE INDEX IF NOT EXISTS stockReservationProductIndex ON TABLE stock_reservation FIELDS product;
+        DEFINE INDEX IF NOT EXISTS stockReservationOrderIndex ON TABLE stock_reservation FIELDS order;
+
+        // What a warehouse can still ship of a product, its stock there less the holds that haven't expired
+        DEFINE FUNCTION OVERWRITE fn::warehouse_available_qty($warehouse: record<Warehouse>, $product: record<Product>) {
+            LET $stock = type::thing('warehouse_stock', [$warehouse, $product]).stock_qty OR 0;
+            LET $held = math::sum(SELECT VALUE quantity FROM stock_reservation WHERE product = $product AND warehouse = $warehouse AND expires_at > time::now());
+            RETURN math::max([0, $stock - $held]);
+        };
+
+        // What can still be sold of a product, summed over the warehouses that ship
+        DEFINE FUNCTION OVERWRITE fn::available_qty($product: record<Product>) {
+            RETURN math::sum(SELECT VALUE fn::warehouse_available_qty(warehouse, product) FROM warehouse_stock
+                WHERE product = $product AND warehouse.active = true);
+        };"#;
+
+        let resp = db.query(query_str).await;
+
+        match resp {
+            Ok(_) => {
+                println!("StockReservation Table Initialized...");
+                Ok(())
+            },
+            Err(e) => {
+                println!("StockReservations DB Error : {:?}",e);
+
+                Err(e)
+            }
+        }
+    }
+
+    async fn get_all(db: &Surreal<Client>) -> Vec<Self::Model> {
+        let query = db.query("SELECT * FROM stock_reservation").await;
+        let mut response = query.ok().unwrap();
+        response.take(0).
+            ok().unwrap()
+    }
+
+    async fn save(self, db: &Surreal<Client>) -> Result<Self::Model, Error> {
+        match self.id.clone() {
+            None => {
+                let reservation : Option<StockReservation> = db.create("stock_reservation").content(self).await?;
+                reservation.ok_or(Api(Query("Failed to create stock reservation".to_string())))
+            }
+            Some(id) => {
+                let reservation : Option<StockReservation> = db.update(id).content(self).await?;
+                reservation.ok_or(Api(Query("Failed to update stock reservation".to_string())))
+            }
+        }
+    }
+}
+
+/// When a hold made now for `ttl_secs` runs out.
+pub fn hold_until(ttl_secs: usize) -> Datetime {
+    Datetime::from(Utc::now() + chrono::Duration::seconds(ttl_secs as i64))
+}
+
+impl StockReservation {
+    /// Holds the items of `order` for another `ttl_secs` in the warehouses
+    /// they were allocated from, taking them again if the earlier hold ran
+    /// out. False when some are no longer available there.
+    pub async fn hold(order: &Order, ttl_secs: usize, db: &Surreal<Client>) -> Result<bool, Error> {
+        let mut response = db.query(r#"
+            BEGIN TRANSACTION;
+
+            DELETE stock_reservation WHERE order = $order;
+            FOR $allocation IN $allocations {
+                // Writing the stock level makes concurrent holds on it conflict
+                LET $level = type::thing('warehouse_stock', [$allocation.warehouse, $allocation.product]);
+                IF array::len(UPDATE $level SET stock_qty = stock_qty WHERE fn::warehouse_available_qty(warehouse, product) >= $allocation.quantity) = 0 {
+                    THROW "Out of stock";
+                };
+                CREATE stock_reservation CONTENT {
+                    product: $allocation.product, warehouse: $allocation.warehouse, order: $order,
+                    quantity: $allocation.quantity, expires_at: $expires_at
+                };
+            };
+
+            COMMIT TRANSACTION;"#)
+            .bind(("order", order.id.clone()))
+            .bind(("allocations", order.allocations.clone()))
+            .bind(("expires_at", hold_until(ttl_secs)))
+            .await?;
+
+        let errors = response.take_errors();
+        if errors.values().any(|e| e.to_string().contains("Out of stock")) {
+            return Ok(false);
+        }
+        if !errors.is_empty() {
+            println!("StockReservation DB Error : {:?}", errors);
+            return Err(Api(Query("Failed to hold stock".to_string())));
+        }
+        Ok(true)
+    }
+
+    /// Deletes holds that have run out, returning how many there were.
+    /// Subscribers of the variants they free up are told they are back.
+    pub async fn release_expired(db: &Surreal<Client>) -> Result<usize, Error> {
+        let mut response = db.query(r#"
+            BEGIN TRANSACTION;
+
+            LET $released = DELETE stock_reservation WHERE expires_at <= time::now() RETURN BEFORE;
+            FOR $product IN array::distinct($released.product) {
+                fn::notify_back_in_stock($product);
+            };
+            RETURN $released;
+
+            COMMIT TRANSACTION;"#)
+            .await?;
+
+        let errors = response.take_errors();
+        if !errors.is_empty() {
+            println!("StockReservation DB Error : {:?}", errors);
+            return Err(Api(Query("Failed to release stock reservations".to_string())));
+        }
+        let released: Vec<StockReservation> = response.take(0)?;
+        Ok(released.len())
+    }
+
+    /// Releases expired holds every `every` for as long as the app runs.
+    /// Expired holds already stopped counting, this keeps the table small
+    /// and sends the back in stock notifications they were holding up.
+    pub async fn sweep(db: Surreal<Client>, every: Duration) {
+        let mut interval = rocket::tokio::time::interval(every);
+        loop {
+            interval.tick().await;
+            match Self::release_expired(&db).await {
+                Ok(0) => {},
+                Ok(released) => println!("Released {} expired stock reservations...", released),
+                Err(e) => println!("Stock reservation sweep DB Error : {:?}", e)
+            }
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use rocket::serde::{Deserialize, Serialize};
+use surrealdb::engine::remote::ws::Client;
+use surrealdb::{Error, RecordId, Surreal};
+use surrealdb::Error::Api;
+use surrealdb::error::Api::Query;
+use surrealdb::sql::Datetime;
+
+use super::super::models::{DatabaseIO};
+
+/// A customer waiting for a sold out variant. It is turned into a
+/// `back_in_stock` notification, and dropped, once the variant is restocked.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StockSubscription {
+    #[serde(default)]
+    pub id: Option<RecordId>,
+    pub product: RecordId,
+    pub user: RecordId,
+    pub email: String,
+    #[serde(default)]
+    pub created_at: Option<Datetime>
+}
+
+impl DatabaseIO for StockSubscription {
+    type Model = StockSubscription;
+
+    fn table_name() -> &'static str {
+        "stock_subscription"
+    }
+
+    async fn init(db: &Surreal<Client>) -> Result<(), Error> {
+        let query_str = r#"
+        DEFINE TABLE IF NOT EXISTS stock_subscription SCHEMAFULL;
+        DEFINE FIELD IF NOT EXISTS product ON TABLE stock_subscription TYPE record<Product> READONLY;
+        DEFINE FIELD IF NOT EXISTS user ON TABLE stock_subscription TYPE record<User> READONLY;
+        DEFINE FIELD IF NOT EXISTS email ON TABLE stock_subscription TYPE string;
+        DEFINE FIELD IF NOT EXISTS created_at ON TABLE stock_subscription TYPE datetime DEFAULT time::now() READONLY;
+
+        DEFINE INDEX IF NOT EXISTS stockSubscriptionIndex ON TABLE stock_subscription FIELDS product, user UNIQUE;"#;
+
+        let resp = db.query(query_str).await;
+
+        match resp {
+            Ok(_) => {
+                println!("StockSubscription Table Initialized...");
+                Ok(())
+            },
+            Err(e) => {
+                println!("StockSubscriptions DB Error : {:?}",e);
+
+                Err(e)
+            }
+        }
+    }
+
+    async fn get_all(db: &Surreal<Client>) -> Vec<Self::Model> {
+        let query = db.query("SELECT * FROM stock_subscription").await;
+        let mut response = query.ok().unwrap();
+        response.take(0).
+            ok().unwrap()
+    }
+
+    async fn save(self, db: &Surreal<Client>) -> Result<Self::Model, Error> {
+        match self.id.clone() {
+            None => {
+                let subscription : Option<StockSubscription> = db.create("stock_subscription").content(self).await?;
+                subscription.ok_or(Api(Query("Failed to create stock subscription".to_string())))
+            }
+            Some(id) => {
+                let subscription : Option<StockSubscription> = db.update(id).content(self).await?;
+                subscription.ok_or(Api(Query("Failed to update stock subscription".to_string())))
+            }
+        }
+    }
+}
+
+impl StockSubscription {
+    pub async fn find(product: &RecordId, user: &RecordId, db: &Surreal<Client>) -> Result<Option<StockSubscription>, Error> {
+        let mut response = db.query("SELECT * FROM stock_subscription WHERE product = $product AND user = $user LIMIT 1")
+            .bind(("product", product.clone()))
+            .bind(("user", user.clone()))
+            .await?;
+        let mut subscriptions: Vec<StockSubscription> = response.take(0)?;
+        Ok(subscriptions.pop())
+    }
+
+    /// Stops waiting for `product`, false when `user` wasn't.
+    pub async fn cancel(product: &RecordId, user: &RecordId, db: &Surreal<Client>) -> Result<bool, Error> {
+        let mut response = db.query("DELETE stock_subscription WHERE product = $product AND user = $user RETURN BEFORE")
+            .bind(("product", product.clone()))
+            .bind(("user", user.clone()))
+            .await?;
+        let deleted: Vec<StockSubscription> = response.take(0)?;
+        Ok(!deleted.is_empty())
+    }
+}
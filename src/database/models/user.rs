@@ -5,6 +5,7 @@ use surrealdb::Error::Api;
 use surrealdb::error::Api::Query;
 
 use super::super::models::{DatabaseIO};
+use super::role;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct User {
@@ -13,7 +14,13 @@ pub struct User {
     pub email: String,
     pub password_hash: String,
     #[serde(default)]
-    pub is_admin: bool
+    pub is_admin: bool,
+    #[serde(default = "default_roles")]
+    pub roles: Vec<String>
+}
+
+fn default_roles() -> Vec<String> {
+    vec![role::CUSTOMER.to_string()]
 }
 
 impl DatabaseIO for User{
@@ -37,6 +44,7 @@ impl DatabaseIO for User{
             
         DEFINE FIELD IF NOT EXISTS password_hash ON TABLE User TYPE STRING PERMISSIONS FULL;
         DEFINE FIELD IF NOT EXISTS is_admin ON TABLE User TYPE BOOL DEFAULT false PERMISSIONS FOR update WHERE false;
+        DEFINE FIELD IF NOT EXISTS roles ON TABLE User TYPE array<string> DEFAULT ['customer'] PERMISSIONS FOR update WHERE false;
         
         DEFINE INDEX IF NOT EXISTS emailIndex ON TABLE User FIELDS email UNIQUE;
         "#;
@@ -59,9 +67,8 @@ impl DatabaseIO for User{
     async fn get_all(db: &Surreal<Client>) -> Vec<Self::Model> {
         let query = db.query("SELECT * FROM User").await;
         let mut response = query.ok().unwrap();
-        let all_users = response.take(0).
-            ok().unwrap();
-        all_users
+        response.take(0).
+            ok().unwrap()
     }
 
     async fn save(self, db: &Surreal<Client>) -> Result<Self::Model, Error> {
@@ -88,4 +95,13 @@ impl User {
             ok().unwrap();
         user.pop().ok_or(Api(Query("User not found".to_string())))
     }
+
+    /// Roles of the user, `is_admin` counting as the admin role.
+    pub fn role_names(&self) -> Vec<String> {
+        let mut roles = self.roles.clone();
+        if self.is_admin && !roles.iter().any(|role| role == role::ADMIN) {
+            roles.push(role::ADMIN.to_string());
+        }
+        roles
+    }
 }
\ No newline at end of file
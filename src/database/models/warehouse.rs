@@ -0,0 +1,230 @@
+use rocket::serde::{Deserialize, Serialize};
+use surrealdb::engine::remote::ws::Client;
+use surrealdb::{Error, RecordId, Surreal};
+use surrealdb::Error::Api;
+use surrealdb::error::Api::Query;
+use surrealdb::sql::Datetime;
+
+use super::super::models::{DatabaseIO};
+
+/// A location orders ship from. Its stock of each product is kept in
+/// `warehouse_stock`, the product's `stock_qty` being the sum over all of them.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Warehouse {
+    #[serde(default)]
+    pub id: Option<RecordId>,
+    pub code: String,
+    pub name: String,
+    #[serde(default)]
+    pub pincode: Option<String>,
+    #[serde(default = "default_active")]
+    pub active: bool,       // inactive ones keep their stock but don't ship
+    #[serde(default)]
+    pub created_at: Option<Datetime>
+}
+
+fn default_active() -> bool {
+    true
+}
+
+/// Stock of one product in one warehouse.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WarehouseStock {
+    pub warehouse: RecordId,
+    pub product: RecordId,
+    pub stock_qty: i64,
+    #[serde(default)]
+    pub available_qty: u32      // stock less active reservations, worked out when read
+}
+
+impl DatabaseIO for Warehouse {
+    type Model = Warehouse;
+
+    fn table_name() -> &'static str {
+        "Warehouse"
+    }
+
+    async fn init(db: &Surreal<Client>) -> Result<(), Error> {
+        let query_str = r#"
+        DEFINE TABLE IF NOT EXISTS Warehouse SCHEMAFULL;
+        DEFINE FIELD IF NOT EXISTS code ON TABLE Warehouse TYPE string PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS name ON TABLE Warehouse TYPE string PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS pincode ON TABLE Warehouse TYPE option<string> PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS active ON TABLE Warehouse TYPE bool DEFAULT true PERMISSIONS FULL;
+        DEFINE FIELD IF NOT EXISTS created_at ON TABLE Warehouse TYPE datetime DEFAULT time::now() READONLY PERMISSIONS FULL;
+
+        DEFINE INDEX IF NOT EXISTS warehouseCodeIndex ON TABLE Warehouse FIELDS code UNIQUE;
+
+        // Record ids are [warehouse, product], so a level is found without a lookup
+        DEFINE TABLE IF NOT EXISTS warehouse_stock SCHEMAFULL;
+        DEFINE FIELD IF NOT EXISTS warehouse ON TABLE warehouse_stock TYPE record<Warehouse> READONLY;
+        DEFINE FIELD IF NOT EXISTS product ON TABLE warehouse_stock TYPE record<Product> READONLY;
+        DEFINE FIELD IF NOT EXISTS stock_qty ON TABLE warehouse_stock TYPE int ASSERT $value >= 0;
+
+        DEFINE INDEX IF NOT EXISTS warehouseStockProductIndex ON TABLE warehouse_stock FIELDS product;"#;
+
+        let resp = db.query(query_str).await;
+
+        match resp {
+            Ok(_) => {
+                println!("Warehouse Table Initialized...");
+                Ok(())
+            },
+            Err(e) => {
+                println!("Warehouses DB Error : {:?}",e);
+
+                Err(e)
+            }
+        }
+    }
+
+    async fn get_all(db: &Surreal<Client>) -> Vec<Self::Model> {
+        let query = db.query("SELECT * FROM Warehouse ORDER BY code").await;
+        let mut response = query.ok().unwrap();
+        response.take(0).
+            ok().unwrap()
+    }
+
+    async fn save(self, db: &Surreal<Client>) -> Result<Self::Model, Error> {
+        let mut response = db.query("UPSERT type::thing('Warehouse', $warehouse.code) CONTENT $warehouse")
+            .bind(("warehouse", self))
+            .await?;
+        let mut warehouses: Vec<Warehouse> = response.take(0)?;
+        warehouses.pop().ok_or(Api(Query("Failed to save warehouse".to_string())))
+    }
+}
+
+/// Indian postal codes, six digits not starting with 0.
+pub fn is_valid_pincode(pincode: &str) -> bool {
+    pincode.len() == 6 && pincode.chars().all(|c| c.is_ascii_digit()) && !pincode.starts_with('0')
+}
+
+/// How far apart two pincodes are. Their leading digits name the region,
+/// district and sorting office, so sharing more of them counts first and the
+/// numeric gap breaks ties.
+pub fn pincode_distance(from: &str, to: &str) -> u64 {
+    if !is_valid_pincode(from) || !is_valid_pincode(to) {
+        return u64::MAX;
+    }
+    let shared = from.chars().zip(to.chars()).take_while(|(a, b)| a == b).count() as u64;
+    let gap = from.parse::<u64>().unwrap().abs_diff(to.parse::<u64>().unwrap());
+    (6 - shared) * 1_000_000 + gap
+}
+
+impl Warehouse {
+    /// Checks the fields an admin may send, returning every problem found.
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errors = Vec::new();
+
+        if self.code.is_empty() || !self.code.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '-') {
+            errors.push("code must be uppercase letters, digits and hyphens".to_string());
+        }
+        if self.name.trim().is_empty() {
+            errors.push("name must not be empty".to_string());
+        }
+        if let Some(pincode) = &self.pincode && !is_valid_pincode(pincode) {
+            errors.push("pincode must be six digits".to_string());
+        }
+
+        if errors.is_empty() { Ok(()) } else { Err(errors) }
+    }
+
+    pub async fn find_by_code(code: &str, db: &Surreal<Client>) -> Result<Option<Warehouse>, Error> {
+        db.select(("Warehouse", code)).await
+    }
+
+    /// Warehouses that ship, nearest to `pincode` first. Without one they
+    /// come in code order.
+    pub async fn nearest_first(pincode: Option<&str>, db: &Surreal<Client>) -> Result<Vec<Warehouse>, Error> {
+        let mut response = db.query("SELECT * FROM Warehouse WHERE active = true ORDER BY code").await?;
+        let mut warehouses: Vec<Warehouse> = response.take(0)?;
+
+        if let Some(pincode) = pincode {
+            warehouses.sort_by_key(|warehouse| warehouse.pincode.as_deref().map_or(u64::MAX, |at| pincode_distance(pincode, at)));
+        }
+        Ok(warehouses)
+    }
+}
+
+impl WarehouseStock {
+    /// Stock of `products` in every warehouse holding some.
+    pub async fn for_products(products: &[RecordId], db: &Surreal<Client>) -> Result<Vec<WarehouseStock>, Error> {
+        let mut response = db.query(r#"
+            SELECT warehouse, product, stock_qty, fn::warehouse_available_qty(warehouse, product) AS available_qty
+            FROM warehouse_stock WHERE product IN $products ORDER BY warehouse"#)
+            .bind(("products", products.to_vec()))
+            .await?;
+        response.take(0)
+    }
+}
+
+/// How an order's lines are spread over warehouses.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AllocationStrategy {
+    Nearest,            // every line from the nearest warehouse that has it, split when needed
+    SingleWarehouse     // the nearest warehouse that has the whole order, so it ships as one parcel
+}
+
+impl std::str::FromStr for AllocationStrategy {
+    type Err = String;
+
+    /// `nearest` or `single_warehouse`.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "nearest" => Ok(AllocationStrategy::Nearest),
+            "single_warehouse" => Ok(AllocationStrategy::SingleWarehouse),
+            _ => Err(format!("Unknown allocation strategy {}", value))
+        }
+    }
+}
+
+/// Units of a product an order takes from a warehouse.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Allocation {
+    pub product: RecordId,
+    pub warehouse: RecordId,
+    pub quantity: u32
+}
+
+/// Spreads `lines` (product and quantity) over `warehouses`, nearest first,
+/// from what `stock` says each can still ship. When no single warehouse has
+/// the whole order, `SingleWarehouse` splits it like `Nearest`. When the
+/// warehouses together can't cover some lines, those are returned with the
+/// units the warehouses do have of them.
+pub fn allocate(lines: &[(RecordId, u32)], warehouses: &[RecordId], stock: &[WarehouseStock], strategy: AllocationStrategy) -> Result<Vec<Allocation>, Vec<(RecordId, u32)>> {
+    let available = |warehouse: &RecordId, product: &RecordId| stock.iter()
+        .find(|level| &level.warehouse == warehouse && &level.product == product)
+        .map_or(0, |level| level.available_qty);
+
+    if strategy == AllocationStrategy::SingleWarehouse
+        && let Some(warehouse) = warehouses.iter().find(|warehouse| lines.iter().all(|(product, quantity)| available(warehouse, product) >= *quantity)) {
+        return Ok(lines.iter()
+            .map(|(product, quantity)| Allocation { product: product.clone(), warehouse: warehouse.clone(), quantity: *quantity })
+            .collect());
+    }
+
+    let mut allocations: Vec<Allocation> = Vec::new();
+    let mut short: Vec<(RecordId, u32)> = Vec::new();
+    for (product, quantity) in lines {
+        let mut remaining = *quantity;
+        for warehouse in warehouses {
+            if remaining == 0 {
+                break;
+            }
+            let taken: u32 = allocations.iter()
+                .filter(|allocation| &allocation.warehouse == warehouse && &allocation.product == product)
+                .map(|allocation| allocation.quantity)
+                .sum();
+            let take = available(warehouse, product).saturating_sub(taken).min(remaining);
+            if take > 0 {
+                allocations.push(Allocation { product: product.clone(), warehouse: warehouse.clone(), quantity: take });
+                remaining -= take;
+            }
+        }
+        if remaining > 0 {
+            short.push((product.clone(), quantity - remaining));
+        }
+    }
+    if short.is_empty() { Ok(allocations) } else { Err(short) }
+}
@@ -1,6 +1,8 @@
 pub mod database;
 pub mod routes;
 pub mod utils;
+pub mod payments;
+pub mod money;
 
 #[cfg(test)]
 mod tests {
@@ -13,7 +15,7 @@ mod tests {
         },
         Argon2
     };
-    use jsonwebtoken::{DecodingKey, EncodingKey};
+    use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
     use ring::signature::{Ed25519KeyPair, KeyPair};
     use surrealdb::sql::Value;
 
@@ -38,11 +40,15 @@ mod tests {
     #[test]
     fn test_ed_dsa_keygen() {
         let doc = Ed25519KeyPair::generate_pkcs8(&ring::rand::SystemRandom::new()).unwrap();
-        // let encoding_key = EncodingKey::from_ed_der(doc.as_ref());
+        let encoding_key = EncodingKey::from_ed_der(doc.as_ref());
 
         let pair = Ed25519KeyPair::from_pkcs8(doc.as_ref()).unwrap();
-        println!("{:?}", doc.as_ref().to_vec());
-        // let decoding_key = DecodingKey::from_ed_der(pair.public_key().as_ref());
+        let decoding_key = DecodingKey::from_ed_der(pair.public_key().as_ref());
+
+        // A token signed with the generated key verifies with its public key
+        let claims = serde_json::json!({"sub": "test", "exp": 4102444800u64});
+        let token = jsonwebtoken::encode(&Header::new(Algorithm::EdDSA), &claims, &encoding_key).unwrap();
+        assert!(jsonwebtoken::decode::<serde_json::Value>(&token, &decoding_key, &Validation::new(Algorithm::EdDSA)).is_ok());
     }
 
     #[test]
@@ -73,5 +79,263 @@ mod tests {
         println!("{}",query);
         println!("printing done")
     }
+
+    #[test]
+    fn session_cache_forgets_stale_entries() {
+        use std::time::Duration;
+        use crate::utils::auth::{SessionCache, SessionStatus};
+
+        let jti = uuid::Uuid::new_v4();
+
+        let cache = SessionCache::new(Duration::from_secs(60));
+        cache.insert(jti, SessionStatus::Revoked);
+        assert_eq!(cache.get(&jti), Some(SessionStatus::Revoked));
+        cache.invalidate(&jti);
+        assert_eq!(cache.get(&jti), None);
+
+        let cache = SessionCache::new(Duration::ZERO);
+        cache.insert(jti, SessionStatus::Active);
+        assert_eq!(cache.get(&jti), None);
+    }
+
+    #[test]
+    fn key_ring_keeps_retired_keys() {
+        use crate::utils::auth::load_key_ring;
+
+        let key_dir = std::env::temp_dir().join(format!("hackerwear-keys-{}", uuid::Uuid::new_v4()));
+
+        let old_ring = load_key_ring(&key_dir, Some("key-1"), None);
+        let new_ring = load_key_ring(&key_dir, Some("key-2"), None);
+
+        assert_eq!(new_ring.active_kid(), "key-2");
+        assert_eq!(new_ring.get("key-1").unwrap().public_key, old_ring.active().public_key);
+        assert_eq!(new_ring.jwks()["keys"].as_array().unwrap().len(), 2);
+
+        // Without an explicit kid the newest one wins
+        assert_eq!(load_key_ring(&key_dir, None, None).active_kid(), "key-2");
+
+        // The key from before rotation keeps verifying the tokens it signed
+        let legacy_path = key_dir.join("legacy").join("jwt_private_key.der");
+        let legacy = crate::utils::auth::get_pkcs8_der(&legacy_path);
+        let ring = load_key_ring(&key_dir, None, Some(&legacy_path));
+        assert_eq!(ring.active_kid(), "key-2");
+        assert_eq!(ring.get("legacy").unwrap().public_key, crate::utils::auth::generate_ed_dsa_keypair(&legacy).public_key);
+
+        std::fs::remove_dir_all(&key_dir).unwrap();
+    }
+
+    #[test]
+    fn product_validation() {
+        use crate::database::models::Product;
+        use crate::money::Money;
+
+        let mut product = Product {
+            id: None,
+            product_group: None,
+            sku: "HOODIE-BLK-M".into(),
+            title: "Hacker Hoodie".into(),
+            slug: "hacker-hoodie-black-m".into(),
+            desc: "Warm".into(),
+            img: "hoodie.png".into(),
+            category: "hoodies".into(),
+            color: "black".into(),
+            size: "M".into(),
+            price: Money::new(129900, "INR"),
+            price_overrides: vec![Money::new(1599, "USD")],
+            stock_qty: 3,
+            low_stock_threshold: None,
+            available_qty: 3,
+            extras: None,
+            created_at: None
+        };
+        assert!(product.validate().is_ok());
+
+        product.slug = "Hacker Hoodie".into();
+        product.price = Money::new(-100, "INR");
+        assert_eq!(product.validate().unwrap_err().len(), 2);
+
+        product.price = Money::new(0, "INR");
+        assert_eq!(product.validate().unwrap_err().len(), 2);
+    }
+
+    #[test]
+    fn slugify_titles() {
+        use crate::database::models::product_group::slugify;
+
+        assert_eq!(slugify("Horizon Zero Dawn®"), "horizon-zero-dawn");
+        assert_eq!(slugify("  Hacker   Hoodie (v2) "), "hacker-hoodie-v2");
+    }
+
+    #[test]
+    fn guest_cart_merge_caps_at_stock() {
+        use crate::database::models::cart::merged_quantity;
+
+        assert_eq!(merged_quantity(1, 2, 10), 3);
+        assert_eq!(merged_quantity(2, 5, 4), 4);
+        // What the user already had is kept even if stock dropped since
+        assert_eq!(merged_quantity(3, 1, 2), 3);
+        assert_eq!(merged_quantity(u32::MAX, 1, u32::MAX), u32::MAX);
+        assert_eq!(merged_quantity(90, 20, 500), 100);
+    }
+
+    #[test]
+    fn order_status_transitions() {
+        use crate::database::models::OrderStatus::*;
+
+        assert!(PendingPayment.can_transition_to(Paid));
+        assert!(Packed.can_transition_to(Shipped));
+        assert!(!Cancelled.can_transition_to(Shipped));
+        assert!(!Shipped.can_transition_to(Cancelled));
+        assert!(!Paid.can_transition_to(Paid));
+    }
+
+    #[test]
+    fn webhook_signatures() {
+        use std::time::{SystemTime, UNIX_EPOCH};
+        use crate::payments::{parse_webhook, sign_webhook, verify_webhook_signature, PaymentStatus};
+
+        let payload = br#"{"type":"payment_intent.succeeded","data":{"object":{"id":"pi_123"}}}"#;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+
+        let signature = sign_webhook("secret", now, payload);
+        assert!(verify_webhook_signature("secret", payload, &signature).is_ok());
+        assert!(verify_webhook_signature("other", payload, &signature).is_err());
+        assert!(verify_webhook_signature("secret", b"{}", &signature).is_err());
+
+        // Replayed long after it was sent
+        let stale = sign_webhook("secret", now - 3600, payload);
+        assert!(verify_webhook_signature("secret", payload, &stale).is_err());
+
+        let event = parse_webhook(payload).unwrap();
+        assert_eq!(event.intent_id, "pi_123");
+        assert_eq!(event.status, PaymentStatus::Captured);
+    }
+
+    #[test]
+    fn money_amounts() {
+        use crate::money::Money;
+
+        assert_eq!(Money::parse("1299.5", "INR"), Some(Money::new(129950, "INR")));
+        assert_eq!(Money::parse("1299", "JPY"), Some(Money::new(1299, "JPY")));
+        assert_eq!(Money::parse("12.999", "INR"), None);
+        assert_eq!(Money::new(129950, "INR").to_string(), "INR 1299.50");
+        assert_eq!(Money::new(-5, "KWD").to_string(), "KWD -0.005");
+
+        // 10% of 9.99 rounds half away from zero
+        assert_eq!(Money::new(999, "INR").basis_points(1000), Money::new(100, "INR"));
+        assert_eq!(Money::new(-999, "INR").basis_points(1000), Money::new(-100, "INR"));
+        // 12.5% of 0.20 is 0.025, which rounds up
+        assert_eq!(Money::new(20, "INR").basis_points(1250), Money::new(3, "INR"));
+
+        // Shares always add back up to the amount
+        let shares = Money::new(1000, "INR").allocate(&[1, 1, 1]);
+        assert_eq!(shares, vec![Money::new(333, "INR"), Money::new(333, "INR"), Money::new(334, "INR")]);
+
+        // Mixed currencies don't add up
+        assert_eq!(Money::new(100, "INR").checked_add(&Money::new(1, "USD")), None);
+        assert_eq!(Money::new(100, "INR").checked_sub(&Money::new(1, "INR")), Some(Money::new(99, "INR")));
+    }
+
+    #[test]
+    fn display_prices_in_other_currencies() {
+        use crate::database::models::exchange_rate::{ExchangeRate, ExchangeRates};
+        use crate::money::{Money, RoundingMode};
+        use crate::routes::products::parse_accept_currency;
+
+        let rate = |currency: &str, rate, rounding, increment| ExchangeRate { currency: currency.into(), rate, rounding, increment, updated_at: None };
+        let rates = ExchangeRates::new("INR", vec![
+            rate("USD", 0.012, RoundingMode::Up, 100),
+            rate("EUR", 0.011, RoundingMode::Nearest, 1),
+            rate("JPY", 1.8, RoundingMode::Down, 10)
+        ]);
+        let price = Money::new(129900, "INR");
+
+        assert_eq!(rates.price(&price, &[], "USD"), Some(Money::new(1600, "USD")));
+        assert_eq!(rates.price(&price, &[], "EUR"), Some(Money::new(1429, "EUR")));
+        assert_eq!(rates.price(&price, &[], "JPY"), Some(Money::new(2330, "JPY")));
+        assert_eq!(rates.price(&price, &[Money::new(1499, "USD")], "USD"), Some(Money::new(1499, "USD")));
+        assert_eq!(rates.price(&price, &[], "GBP"), None);
+
+        assert!(ExchangeRate::validate_all(&[rate("INR", 1.0, RoundingMode::Nearest, 1)], "INR").is_err());
+        assert_eq!(parse_accept_currency("eur;q=0.5, USD, GBP;q=0"), vec!["USD", "EUR"]);
+    }
+
+    #[test]
+    fn buy_x_get_y_frees_cheapest_units() {
+        use crate::database::models::coupon::free_units;
+
+        // Buy 2 get 1: 5 units make one full group, so one unit is free
+        assert_eq!(free_units(&[(50000, 2), (20000, 3)], 2, 1), vec![0, 1]);
+        assert_eq!(free_units(&[(50000, 3), (20000, 1), (30000, 2)], 1, 1), vec![0, 1, 2]);
+        assert_eq!(free_units(&[(50000, 2)], 2, 1), vec![0]);
+        assert_eq!(free_units(&[(50000, u32::MAX), (20000, 1)], 1, 1)[1], 1);
+    }
+
+    #[test]
+    fn stock_movement_directions() {
+        use crate::database::models::inventory_movement::MovementKind::*;
+
+        assert!(Restock.accepts(5));
+        assert!(!RefundRestock.accepts(-2));
+        assert!(!Restock.accepts(-5));
+        assert!(Damage.accepts(-1));
+        assert!(!Sale.accepts(2));
+        assert!(Correction.accepts(-3));
+        assert!(!Correction.accepts(0));
+    }
+
+    #[test]
+    fn product_patch_clears_low_stock_threshold() {
+        use crate::routes::admin::ProductPatch;
+
+        let patch = |body: &str| serde_json::from_str::<ProductPatch>(body).unwrap().low_stock_threshold;
+        assert_eq!(patch(r#"{"low_stock_threshold": 5}"#), Some(Some(5)));
+        assert_eq!(patch(r#"{"low_stock_threshold": null}"#), Some(None));
+        assert_eq!(patch(r#"{"title": "Tee"}"#), None);
+    }
+
+    #[test]
+    fn allocate_orders_to_warehouses() {
+        use surrealdb::RecordId;
+        use crate::database::models::warehouse::*;
+
+        let (near, far) = (RecordId::from(("Warehouse", "NEAR")), RecordId::from(("Warehouse", "FAR")));
+        let (tee, cap) = (RecordId::from(("Product", "tee")), RecordId::from(("Product", "cap")));
+        let level = |warehouse: &RecordId, product: &RecordId, available_qty| WarehouseStock {
+            warehouse: warehouse.clone(), product: product.clone(), stock_qty: available_qty as i64, available_qty
+        };
+        let stock = vec![level(&near, &tee, 2), level(&far, &tee, 5), level(&far, &cap, 1)];
+        let lines = vec![(tee.clone(), 3), (cap.clone(), 1)];
+        let warehouses = vec![near.clone(), far.clone()];
+
+        let split = allocate(&lines, &warehouses, &stock, AllocationStrategy::Nearest).unwrap();
+        assert_eq!(split.iter().map(|a| (&a.warehouse, a.quantity)).collect::<Vec<_>>(), vec![(&near, 2), (&far, 1), (&far, 1)]);
+        let single = allocate(&lines, &warehouses, &stock, AllocationStrategy::SingleWarehouse).unwrap();
+        assert!(single.iter().all(|a| a.warehouse == far));
+        assert_eq!(allocate(&[(tee.clone(), 8), (cap.clone(), 2)], &warehouses, &stock, AllocationStrategy::Nearest), Err(vec![(tee, 7), (cap, 1)]));
+
+        assert!(pincode_distance("411001", "411038") < pincode_distance("411001", "400001"));
+        assert_eq!(pincode_distance("411001", "01234"), u64::MAX);
+    }
+
+    #[test]
+    fn address_validation() {
+        use crate::database::models::Address;
+
+        let mut address = Address {
+            id: None, user: None, name: "Asha".to_string(), phone: "+919876543210".to_string(),
+            line1: "12 MG Road".to_string(), line2: None, city: "Pune".to_string(), state: "MH".to_string(),
+            pincode: "411001".to_string(), country: "IN".to_string(),
+            default_shipping: false, default_billing: false, created_at: None
+        };
+        assert!(address.validate().is_ok());
+
+        address.pincode = "SW1A 1AA".to_string();
+        address.phone = "12345".to_string();
+        assert_eq!(address.validate().unwrap_err().len(), 2);
+        address.country = "GB".to_string();
+        address.phone = "447700900123".to_string();
+        assert!(address.validate().is_ok());
+    }
     
 }
\ No newline at end of file
@@ -1,12 +1,22 @@
 #[macro_use] extern crate rocket;
 
 use std::sync::Arc;
+use std::time::Duration;
 
 use hackerwear_api::database::db::{connect_to_database};
+use hackerwear_api::database::migrations;
 use hackerwear_api::database::models::session_token::SessionToken;
+use hackerwear_api::database::models::idempotency_key::IdempotencyKey;
 use hackerwear_api::database::models::*;
 use hackerwear_api::utils::{extract_app_config_from_env, AppConfig, AppState};
 use hackerwear_api::routes::index::*;
+use hackerwear_api::routes::sessions::*;
+use hackerwear_api::routes::admin::*;
+use hackerwear_api::routes::products::*;
+use hackerwear_api::routes::cart::*;
+use hackerwear_api::routes::orders::*;
+use hackerwear_api::routes::payments::*;
+use hackerwear_api::routes::addresses::*;
 
 
 #[rocket::main]
@@ -21,14 +31,58 @@ async fn main() -> Result<(), String> {
         .expect("Could not connect to database");
 
     // Init Models
+    ProductGroup::init(&db).await.expect("Could not initialize product group table");
     Product::init(&db).await.expect("Could not initialize product table");
+    Warehouse::init(&db).await.expect("Could not initialize warehouse table");
+    StockReservation::init(&db).await.expect("Could not initialize stock reservation table");
+    InventoryMovement::init(&db).await.expect("Could not initialize inventory movement table");
+    StockSubscription::init(&db).await.expect("Could not initialize stock subscription table");
+    Notification::init(&db).await.expect("Could not initialize notification table");
     User::init(&db).await.expect("Could not initialize user table");
+    Address::init(&db).await.expect("Could not initialize address table");
+    Role::init(&db).await.expect("Could not initialize role table");
     SessionToken::init(&db).await.expect("Could not initialize Session Token table");
+    Cart::init(&db).await.expect("Could not initialize cart table");
+    Order::init(&db).await.expect("Could not initialize order table");
+    OrderEvent::init(&db).await.expect("Could not initialize order event table");
+    Payment::init(&db).await.expect("Could not initialize payment table");
+    IdempotencyKey::init(&db).await.expect("Could not initialize idempotency key table");
+    Coupon::init(&db).await.expect("Could not initialize coupon table");
+    ExchangeRate::init(&db).await.expect("Could not initialize exchange rate table");
+
+    // Migrations, before anything reads the migrated records
+    migrations::money_amounts(&app_config.payment_config.currency, &db).await.expect("Could not migrate amounts to money");
+    migrations::stock_reservations(app_config.stock.hold_secs, &db).await.expect("Could not hold stock of unpaid orders");
+    migrations::inventory_ledger(&db).await.expect("Could not open the inventory ledger");
+    migrations::warehouses(&db).await.expect("Could not move stock into warehouses");
+    ProductGroup::link_ungrouped_products(&db).await.expect("Could not group existing products");
+
+    let rates = ExchangeRate::load(app_config.exchange_rates_file.as_deref(), &app_config.payment_config.currency, &db).await
+        .expect("Could not load exchange rates");
     
+    rocket::tokio::spawn(StockReservation::sweep(db.clone(), Duration::from_secs(60)));
+    rocket::tokio::spawn(Cart::sweep(db.clone(), Duration::from_secs(60 * 60)));
+
     println!("Rocket ready to Launch....\nIn 3... 2... 1...");
     rocket::build()
-        .mount("/", routes![index, get_products, sign_up, login, verify_user])
-        .manage(Arc::new(AppState::new(db, &app_config.jwt_key_path)))
+        .mount("/", routes![index, get_products, get_product, list_products, search_products, sign_up, login, verify_user,
+                             logout, logout_all, list_sessions, revoke_session, refresh_token, jwks,
+                             set_user_roles, create_product, update_product, patch_product, delete_product,
+                             create_product_group, update_product_group, list_coupons, create_coupon, update_coupon,
+                             replace_exchange_rates, list_currencies,
+                             adjust_stock, list_stock_movements, stock_discrepancies, reconcile_stock,
+                             list_low_stock, list_warehouses, create_warehouse, update_warehouse, list_notifications, mark_notification_sent, subscribe_to_restock, unsubscribe_from_restock,
+                             list_addresses, get_address, create_address, update_address, delete_address,
+                             get_cart, add_cart_item, update_cart_item, remove_cart_item, clear_cart, apply_coupon, remove_coupon,
+                             place_order, list_orders, get_order, cancel_order, get_order_admin, set_order_status,
+                             create_payment, capture_payment, payment_webhook])
+        .manage(Arc::new(AppState::new(db,
+                                       &app_config.jwt_keys,
+                                       app_config.token_lifetimes,
+                                       app_config.guest_cart_secs,
+                                       &app_config.payment_config,
+                                       rates,
+                                       app_config.stock)))
         .launch().await
         .expect("Could not launch app");
 
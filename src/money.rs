@@ -0,0 +1,171 @@
+use std::fmt;
+
+use rocket::serde::{Deserialize, Serialize};
+
+/// An amount of money in the smallest unit of its currency (paise for INR,
+/// cents for USD), so totals add up exactly. Serializes as
+/// `{ "amount": 129900, "currency": "INR" }`.
+///
+/// Amounts of different currencies can't be added up or compared, the
+/// `checked_` methods give `None` for them, convert first.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+pub struct Money {
+    pub amount: i64,
+    pub currency: String   // ISO 4217 code
+}
+
+impl Money {
+    pub fn new(amount: i64, currency: &str) -> Money {
+        Money { amount, currency: currency.to_string() }
+    }
+
+    pub fn zero(currency: &str) -> Money {
+        Money::new(0, currency)
+    }
+
+    /// Parses a decimal amount in major units, e.g. `"1299.50"`. More decimals
+    /// than the currency has are rejected rather than rounded away.
+    pub fn parse(value: &str, currency: &str) -> Option<Money> {
+        let exponent = minor_unit_exponent(currency) as usize;
+        let (whole, fraction) = value.trim().split_once('.').unwrap_or((value.trim(), ""));
+        if whole.is_empty() || fraction.len() > exponent
+            || !whole.chars().all(|c| c.is_ascii_digit()) || !fraction.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+
+        let amount = format!("{}{:0<width$}", whole, fraction, width = exponent).parse().ok()?;
+        Some(Money::new(amount, currency))
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.amount == 0
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.amount < 0
+    }
+
+    /// Price of `quantity` items costing `self` each.
+    pub fn times(&self, quantity: u32) -> Money {
+        Money::new(self.amount.saturating_mul(quantity as i64), &self.currency)
+    }
+
+    /// `bps` hundredths of a percent of the amount, rounded half away from
+    /// zero to a minor unit.
+    pub fn basis_points(&self, bps: u32) -> Money {
+        let scaled = self.amount as i128 * bps as i128;
+        let half = if scaled < 0 { -5_000 } else { 5_000 };
+        Money::new(((scaled + half) / 10_000) as i64, &self.currency)
+    }
+
+    /// `None` when the currencies differ or the sum overflows.
+    pub fn checked_add(&self, other: &Money) -> Option<Money> {
+        self.same_currency(other)?;
+        Some(Money::new(self.amount.checked_add(other.amount)?, &self.currency))
+    }
+
+    /// `None` when the currencies differ or the difference overflows.
+    pub fn checked_sub(&self, other: &Money) -> Option<Money> {
+        self.same_currency(other)?;
+        Some(Money::new(self.amount.checked_sub(other.amount)?, &self.currency))
+    }
+
+    /// The larger of the two amounts, `None` when the currencies differ.
+    pub fn checked_max(self, other: Money) -> Option<Money> {
+        self.same_currency(&other)?;
+        Some(if other.amount > self.amount { other } else { self })
+    }
+
+    /// The smaller of the two amounts, `None` when the currencies differ.
+    pub fn checked_min(self, other: Money) -> Option<Money> {
+        self.same_currency(&other)?;
+        Some(if other.amount < self.amount { other } else { self })
+    }
+
+    /// Adds up `amounts`, `None` unless all are in `currency` and the total fits.
+    pub fn sum<'a>(currency: &str, amounts: impl IntoIterator<Item = &'a Money>) -> Option<Money> {
+        amounts.into_iter().try_fold(Money::zero(currency), |total, amount| total.checked_add(amount))
+    }
+
+    fn same_currency(&self, other: &Money) -> Option<()> {
+        (self.currency == other.currency).then_some(())
+    }
+
+    /// Converts into `currency` at `rate` units of it per unit of this
+    /// currency, rounded with `rounding` to a multiple of `increment` minor
+    /// units (an increment of 100 gives whole USD prices).
+    pub fn convert(&self, currency: &str, rate: f64, rounding: RoundingMode, increment: i64) -> Money {
+        let scale = 10f64.powi(minor_unit_exponent(currency) as i32 - minor_unit_exponent(&self.currency) as i32);
+        // Rounded to a millionth first so float noise doesn't push an exact price up
+        let steps = ((self.amount as f64 * rate * scale / increment as f64) * 1e6).round() / 1e6;
+        let steps = match rounding {
+            RoundingMode::Nearest => steps.round(),
+            RoundingMode::Up => steps.ceil(),
+            RoundingMode::Down => steps.floor()
+        };
+        Money::new(steps as i64 * increment, currency)
+    }
+
+    /// Splits the amount over `weights` in proportion to them. The shares are
+    /// whole minor units and always add up to the amount, the last share takes
+    /// what rounding left over.
+    pub fn allocate(&self, weights: &[i64]) -> Vec<Money> {
+        let total: i64 = weights.iter().sum();
+        if total == 0 {
+            return weights.iter().map(|_| Money::zero(&self.currency)).collect();
+        }
+
+        let mut left = self.amount;
+        weights.iter().enumerate()
+            .map(|(i, weight)| {
+                let share = if i + 1 == weights.len() {
+                    left
+                } else {
+                    (self.amount as i128 * *weight as i128 / total as i128) as i64
+                };
+                left -= share;
+                Money::new(share, &self.currency)
+            })
+            .collect()
+    }
+}
+
+/// How a converted amount is rounded to its increment.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum RoundingMode {
+    #[default]
+    Nearest,
+    Up,
+    Down
+}
+
+/// `INR 1299.50`
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let exponent = minor_unit_exponent(&self.currency);
+        let sign = if self.is_negative() { "-" } else { "" };
+        let amount = self.amount.unsigned_abs();
+        if exponent == 0 {
+            return write!(f, "{} {}{}", self.currency, sign, amount);
+        }
+
+        let scale = 10u64.pow(exponent);
+        write!(f, "{} {}{}.{:0width$}", self.currency, sign, amount / scale, amount % scale, width = exponent as usize)
+    }
+}
+
+/// Whether `code` looks like an ISO 4217 currency code.
+pub fn is_valid_currency(code: &str) -> bool {
+    code.len() == 3 && code.chars().all(|c| c.is_ascii_uppercase())
+}
+
+/// Number of decimals of a currency. Most have two.
+pub fn minor_unit_exponent(currency: &str) -> u32 {
+    match currency {
+        "BIF" | "CLP" | "DJF" | "GNF" | "ISK" | "JPY" | "KMF" | "KRW" | "PYG"
+        | "RWF" | "UGX" | "VND" | "VUV" | "XAF" | "XOF" | "XPF" => 0,
+        "BHD" | "IQD" | "JOD" | "KWD" | "LYD" | "OMR" | "TND" => 3,
+        _ => 2
+    }
+}
@@ -0,0 +1,89 @@
+use std::time::Duration;
+
+use ring::rand::{SecureRandom, SystemRandom};
+
+use crate::database::models::Order;
+use super::{parse_webhook, to_hex, verify_webhook_signature, PaymentError, PaymentIntent, PaymentProvider, PaymentStatus, WebhookEvent};
+
+/// What the mock gateway does with every capture.
+#[derive(Debug, Clone, Copy)]
+pub enum MockOutcome {
+    Succeed,
+    Fail,
+    Delay(Duration) // succeeds, but only after the delay
+}
+
+impl std::str::FromStr for MockOutcome {
+    type Err = String;
+
+    /// `success`, `failure` or `delay:<seconds>`.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.split_once(':') {
+            None if value == "success" => Ok(MockOutcome::Succeed),
+            None if value == "failure" => Ok(MockOutcome::Fail),
+            Some(("delay", secs)) => secs.parse()
+                .map(|secs| MockOutcome::Delay(Duration::from_secs(secs)))
+                .map_err(|_| format!("Invalid mock payment delay {}", secs)),
+            _ => Err(format!("Unknown mock payment outcome {}", value))
+        }
+    }
+}
+
+/// Offline stand-in for a real gateway. Webhooks are signed and parsed like
+/// Stripe's, so they can be sent by hand with `sign_webhook` and the secret.
+pub struct MockProvider {
+    outcome: MockOutcome,
+    webhook_secret: String
+}
+
+impl MockProvider {
+    pub fn new(outcome: MockOutcome, webhook_secret: &str) -> Self {
+        MockProvider { outcome, webhook_secret: webhook_secret.to_string() }
+    }
+
+    fn random_id(prefix: &str) -> String {
+        let mut bytes = [0u8; 12];
+        SystemRandom::new().fill(&mut bytes)
+            .expect("Failed to generate mock payment id");
+        format!("{}_{}", prefix, to_hex(&bytes))
+    }
+}
+
+#[rocket::async_trait]
+impl PaymentProvider for MockProvider {
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+
+    fn signature_header(&self) -> &'static str {
+        "Mock-Signature"
+    }
+
+    async fn create_intent(&self, _order: &Order) -> Result<PaymentIntent, PaymentError> {
+        Ok(PaymentIntent {
+            id: Self::random_id("mock_pi"),
+            client_secret: Some(Self::random_id("mock_secret")),
+            status: PaymentStatus::Pending
+        })
+    }
+
+    async fn capture(&self, _intent_id: &str) -> Result<PaymentStatus, PaymentError> {
+        match self.outcome {
+            MockOutcome::Succeed => Ok(PaymentStatus::Captured),
+            MockOutcome::Fail => Ok(PaymentStatus::Failed),
+            MockOutcome::Delay(delay) => {
+                rocket::tokio::time::sleep(delay).await;
+                Ok(PaymentStatus::Captured)
+            }
+        }
+    }
+
+    async fn refund(&self, _intent_id: &str) -> Result<PaymentStatus, PaymentError> {
+        Ok(PaymentStatus::Refunded)
+    }
+
+    fn verify_webhook(&self, payload: &[u8], signature: &str) -> Result<WebhookEvent, PaymentError> {
+        verify_webhook_signature(&self.webhook_secret, payload, signature)?;
+        parse_webhook(payload)
+    }
+}
@@ -1 +1,10 @@
-pub mod index;
\ No newline at end of file
+pub mod index;
+pub mod sessions;
+pub mod guards;
+pub mod admin;
+pub mod products;
+pub mod cart;
+pub mod orders;
+pub mod payments;
+pub mod idempotency;
+pub mod addresses;
\ No newline at end of file
@@ -0,0 +1,136 @@
+use std::sync::Arc;
+
+use rocket::{delete, get, post, put, State};
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use serde_json::json;
+use surrealdb::RecordId;
+use crate::utils::AppState;
+use crate::database::models::*;
+use crate::utils::auth::{current_user, Claims};
+
+pub type AddressResponse = (Status, Json<serde_json::Value>);
+
+fn address_error(status: Status, error: &str) -> AddressResponse {
+    (status, Json(json!({"success" : false, "error" : error })))
+}
+
+async fn address_user(jwt_claims: &Claims, state: &AppState) -> Result<RecordId, AddressResponse> {
+    match current_user(jwt_claims, &state.db).await {
+        Ok(user) => Ok(user.id.unwrap()),
+        Err(_) => Err(address_error(Status::Unauthorized, "User not found"))
+    }
+}
+
+/// The address `id` if it belongs to `user`. Other users' addresses are
+/// reported as missing, so their ids can not be probed.
+pub async fn owned_address(id: &str, user: &RecordId, state: &AppState) -> Result<Address, AddressResponse> {
+    match Address::find_by_key(id, &state.db).await {
+        Ok(Some(address)) if address.user.as_ref() == Some(user) => Ok(address),
+        Ok(_) => Err(address_error(Status::NotFound, "Address not found")),
+        Err(e) => {
+            println!("{:?}", e);
+            Err(address_error(Status::InternalServerError, "Unable to retrieve data"))
+        }
+    }
+}
+
+async fn save_address(address: Address, status: Status, state: &AppState) -> AddressResponse {
+    if let Err(errors) = address.validate() {
+        return (Status::UnprocessableEntity, Json(json!({"success" : false, "error" : "Invalid address", "errors" : errors })));
+    }
+
+    match address.save(&state.db).await {
+        Ok(address) => (status, Json(json!({"success" : true, "address" : address }))),
+        Err(e) => {
+            println!("{:?}", e);
+            address_error(Status::InternalServerError, "Unable to save address")
+        }
+    }
+}
+
+#[get("/addresses")]
+pub async fn list_addresses(jwt_claims: Claims, state: &State<Arc<AppState>>) -> AddressResponse {
+    let user_id = match address_user(&jwt_claims, state).await {
+        Ok(user_id) => user_id,
+        Err(e) => return e
+    };
+
+    match Address::for_user(&user_id, &state.db).await {
+        Ok(addresses) => (Status::Ok, Json(json!({"success" : true, "addresses" : addresses }))),
+        Err(e) => {
+            println!("{:?}", e);
+            address_error(Status::InternalServerError, "Unable to retrieve data")
+        }
+    }
+}
+
+#[get("/addresses/<id>")]
+pub async fn get_address(id: &str, jwt_claims: Claims, state: &State<Arc<AppState>>) -> AddressResponse {
+    let user_id = match address_user(&jwt_claims, state).await {
+        Ok(user_id) => user_id,
+        Err(e) => return e
+    };
+
+    match owned_address(id, &user_id, state).await {
+        Ok(address) => (Status::Ok, Json(json!({"success" : true, "address" : address }))),
+        Err(e) => e
+    }
+}
+
+/// Adds an address. The first one becomes the default for shipping and
+/// billing.
+#[post("/addresses", format = "application/json", data = "<address>")]
+pub async fn create_address(address: Json<Address>, jwt_claims: Claims, state: &State<Arc<AppState>>) -> AddressResponse {
+    let user_id = match address_user(&jwt_claims, state).await {
+        Ok(user_id) => user_id,
+        Err(e) => return e
+    };
+
+    let mut address = address.into_inner();
+    address.id = None;
+    address.user = Some(user_id);
+    address.created_at = None;
+
+    save_address(address, Status::Created, state).await
+}
+
+#[put("/addresses/<id>", format = "application/json", data = "<address>")]
+pub async fn update_address(id: &str, address: Json<Address>, jwt_claims: Claims, state: &State<Arc<AppState>>) -> AddressResponse {
+    let user_id = match address_user(&jwt_claims, state).await {
+        Ok(user_id) => user_id,
+        Err(e) => return e
+    };
+    let existing = match owned_address(id, &user_id, state).await {
+        Ok(address) => address,
+        Err(e) => return e
+    };
+
+    let mut address = address.into_inner();
+    address.id = existing.id;
+    address.user = existing.user;
+    address.created_at = existing.created_at;
+
+    save_address(address, Status::Ok, state).await
+}
+
+/// Orders already placed keep their copy of the address.
+#[delete("/addresses/<id>")]
+pub async fn delete_address(id: &str, jwt_claims: Claims, state: &State<Arc<AppState>>) -> AddressResponse {
+    let user_id = match address_user(&jwt_claims, state).await {
+        Ok(user_id) => user_id,
+        Err(e) => return e
+    };
+    let address = match owned_address(id, &user_id, state).await {
+        Ok(address) => address,
+        Err(e) => return e
+    };
+
+    match address.delete(&state.db).await {
+        Ok(_) => (Status::Ok, Json(json!({"success" : true, "message" : "Address deleted" }))),
+        Err(e) => {
+            println!("{:?}", e);
+            address_error(Status::InternalServerError, "Unable to delete address")
+        }
+    }
+}
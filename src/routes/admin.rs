@@ -0,0 +1,758 @@
+use std::sync::Arc;
+
+use rocket::{delete, get, patch, post, put, State};
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::serde::{Deserialize, Deserializer};
+use serde_json::json;
+use surrealdb::RecordId;
+use crate::money::Money;
+use crate::utils::AppState;
+use crate::database::models::*;
+use crate::database::models::order::Transition;
+use crate::database::models::product::OpeningStock;
+use crate::database::models::exchange_rate::ExchangeRates;
+use crate::database::models::inventory_movement::MovementKind;
+use crate::database::models::notification::NotificationStatus;
+use crate::database::models::warehouse::WarehouseStock;
+use crate::utils::auth::current_user;
+use super::payments::{capture_order_payment, refund_order};
+use super::guards::{ManageCoupons, ManageInventory, ManageOrders, ManageProducts, ManageUsers, ReadOrders, RequirePermission};
+
+#[derive(Debug, Deserialize)]
+pub struct RoleAssignment {
+    pub roles: Vec<String>
+}
+
+#[put("/admin/users/<email>/roles", format = "application/json", data = "<assignment>")]
+pub async fn set_user_roles(email: &str, assignment: Json<RoleAssignment>, _caller: RequirePermission<ManageUsers>, state: &State<Arc<AppState>>) -> (Status, Json<serde_json::Value>) {
+    for name in &assignment.roles {
+        match Role::exists(name, &state.db).await {
+            Ok(true) => {},
+            Ok(false) => return (Status::UnprocessableEntity, Json(json!({"success" : false, "error" : format!("Unknown role {}", name) }))),
+            Err(e) => {
+                println!("{:?}", e);
+                return (Status::InternalServerError, Json(json!({"success" : false, "error" : "Unable to retrieve data" })));
+            }
+        }
+    }
+
+    let mut user = match User::find_by_email(email, &state.db).await {
+        Ok(user) => user,
+        Err(_) => return (Status::NotFound, Json(json!({"success" : false, "error" : "User not found" })))
+    };
+    user.roles = assignment.into_inner().roles;
+
+    // Takes effect as soon as the user's access token is refreshed
+    match user.save(&state.db).await {
+        Ok(user) => (Status::Ok, Json(json!({"success" : true, "email" : user.email, "roles" : user.roles }))),
+        Err(e) => {
+            println!("{:?}", e);
+            (Status::InternalServerError, Json(json!({"success" : false, "error" : "Unable to update roles" })))
+        }
+    }
+}
+
+fn product_error(status: Status, error: &str) -> (Status, Json<serde_json::Value>) {
+    (status, Json(json!({"success" : false, "error" : error })))
+}
+
+/// Points a variant at the group named by `group_slug`. Without one a new
+/// variant joins the group matching its title when it is created.
+async fn assign_group(product: &mut Product, group_slug: Option<&str>, state: &AppState) -> Result<(), (Status, Json<serde_json::Value>)> {
+    let Some(slug) = group_slug else {
+        return Ok(());
+    };
+
+    match ProductGroup::find_by_slug(slug, &state.db).await {
+        Ok(Some(group)) => {
+            product.product_group = group.id;
+            Ok(())
+        },
+        Ok(None) => Err(product_error(Status::UnprocessableEntity, "Unknown product group")),
+        Err(e) => {
+            println!("{:?}", e);
+            Err(product_error(Status::InternalServerError, "Unable to retrieve data"))
+        }
+    }
+}
+
+/// Validates and writes a product, mapping slug clashes to 409.
+async fn save_product(product: Product, status: Status, state: &AppState) -> (Status, Json<serde_json::Value>) {
+    match write_product(product, None, state).await {
+        Ok(product) => (status, Json(json!({"success" : true, "product" : product }))),
+        Err(e) => e
+    }
+}
+
+/// Validates and writes `product`, creating a new one with its `opening`
+/// stock.
+async fn write_product(mut product: Product, opening: Option<OpeningStock>, state: &AppState) -> Result<Product, (Status, Json<serde_json::Value>)> {
+    if product.sku.trim().is_empty() {
+        product.sku = product.slug.to_uppercase();
+    }
+
+    if let Err(errors) = product.validate() {
+        return Err((Status::UnprocessableEntity, Json(json!({"success" : false, "error" : "Invalid product", "errors" : errors }))));
+    }
+    if product.price.currency != state.currency {
+        return Err(product_error(Status::UnprocessableEntity, &format!("price must be in {}", state.currency)));
+    }
+
+    // Checked up front for a clear message, the unique index still guards against races
+    match Product::find_by_slug(&product.slug, &state.db).await {
+        Ok(Some(existing)) if existing.id != product.id => {
+            return Err(product_error(Status::Conflict, "A product with this slug already exists"));
+        },
+        Ok(_) => {},
+        Err(e) => {
+            println!("{:?}", e);
+            return Err(product_error(Status::InternalServerError, "Unable to retrieve data"));
+        }
+    }
+
+    let saved = match product.id {
+        None => product.create(opening, &state.db).await,
+        Some(_) => product.save(&state.db).await
+    };
+    match saved {
+        Ok(product) => Ok(product),
+        Err(e) if Product::is_slug_conflict(&e) => Err(product_error(Status::Conflict, "A product with this slug already exists")),
+        Err(e) if Product::is_sku_conflict(&e) => Err(product_error(Status::Conflict, "A product with this sku already exists")),
+        Err(e) => {
+            println!("{:?}", e);
+            Err(product_error(Status::InternalServerError, "Unable to save product"))
+        }
+    }
+}
+
+async fn existing_product(slug: &str, state: &AppState) -> Result<Product, (Status, Json<serde_json::Value>)> {
+    match Product::find_by_slug(slug, &state.db).await {
+        Ok(Some(product)) => Ok(product),
+        Ok(None) => Err(product_error(Status::NotFound, "Product not found")),
+        Err(e) => {
+            println!("{:?}", e);
+            Err(product_error(Status::InternalServerError, "Unable to retrieve data"))
+        }
+    }
+}
+
+/// A variant as sent by an admin, its group referred to by slug.
+#[derive(Debug, Deserialize)]
+pub struct ProductBody {
+    #[serde(flatten)]
+    pub product: Product,
+    #[serde(default)]
+    pub group: Option<String>,
+    #[serde(default)]
+    pub warehouse: Option<String>   // code of the warehouse a new product's stock is in
+}
+
+/// The warehouse with `code` or, without one, the only warehouse that ships.
+async fn stock_warehouse(code: Option<&str>, state: &AppState) -> Result<RecordId, (Status, Json<serde_json::Value>)> {
+    let warehouses = match code {
+        Some(code) => Warehouse::find_by_code(code, &state.db).await.map(|warehouse| warehouse.into_iter().collect()),
+        None => Warehouse::nearest_first(None, &state.db).await
+    };
+
+    match warehouses {
+        Ok(warehouses) if warehouses.len() == 1 => Ok(warehouses[0].id.clone().unwrap()),
+        Ok(_) if code.is_some() => Err(product_error(Status::UnprocessableEntity, "Unknown warehouse")),
+        Ok(_) => Err(product_error(Status::UnprocessableEntity, "warehouse is required when there isn't exactly one")),
+        Err(e) => {
+            println!("{:?}", e);
+            Err(product_error(Status::InternalServerError, "Unable to retrieve data"))
+        }
+    }
+}
+
+/// Creates a variant, its `stock_qty` recorded as opening stock in
+/// `warehouse` in the same transaction.
+#[post("/admin/products", format = "application/json", data = "<body>")]
+pub async fn create_product(body: Json<ProductBody>, caller: RequirePermission<ManageProducts>, state: &State<Arc<AppState>>) -> (Status, Json<serde_json::Value>) {
+    let ProductBody { mut product, group, warehouse } = body.into_inner();
+    product.id = None;
+    product.product_group = None;
+
+    let actor = match current_user(&caller.claims, &state.db).await {
+        Ok(user) => user.id.unwrap(),
+        Err(_) => return (Status::Unauthorized, Json(json!({"success" : false, "error" : "User not found" })))
+    };
+    let opening = match product.stock_qty {
+        0 => None,
+        quantity => match stock_warehouse(warehouse.as_deref(), state).await {
+            Ok(warehouse) => Some(OpeningStock { warehouse, quantity, actor }),
+            Err(e) => return e
+        }
+    };
+
+    if let Err(e) = assign_group(&mut product, group.as_deref(), state).await {
+        return e;
+    }
+
+    match write_product(product, opening, state).await {
+        Ok(product) => (Status::Created, Json(json!({"success" : true, "product" : product }))),
+        Err(e) => e
+    }
+}
+
+#[put("/admin/products/<slug>", format = "application/json", data = "<body>")]
+pub async fn update_product(slug: &str, body: Json<ProductBody>, _caller: RequirePermission<ManageProducts>, state: &State<Arc<AppState>>) -> (Status, Json<serde_json::Value>) {
+    let existing = match existing_product(slug, state).await {
+        Ok(product) => product,
+        Err(e) => return e
+    };
+
+    let ProductBody { mut product, group, .. } = body.into_inner();
+    product.id = existing.id;
+    product.product_group = existing.product_group;
+    product.created_at = existing.created_at;
+
+    // The stored stock is kept, it only changes through stock adjustments
+    if let Err(e) = assign_group(&mut product, group.as_deref(), state).await {
+        return e;
+    }
+
+    save_product(product, Status::Ok, state).await
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ProductPatch {
+    pub group: Option<String>,
+    pub sku: Option<String>,
+    pub title: Option<String>,
+    pub slug: Option<String>,
+    pub desc: Option<String>,
+    pub img: Option<String>,
+    pub category: Option<String>,
+    pub color: Option<String>,
+    pub size: Option<String>,
+    pub price: Option<Money>,
+    pub price_overrides: Option<Vec<Money>>,
+    pub stock_qty: Option<u32>,     // refused, stock only changes through stock adjustments
+    #[serde(default, deserialize_with = "nullable")]
+    pub low_stock_threshold: Option<Option<u32>>,   // null clears it
+    pub extras: Option<serde_json::Value>
+}
+
+/// Tells a field set to `null`, Some(None), from one left out, None.
+fn nullable<'de, D: Deserializer<'de>, T: Deserialize<'de>>(deserializer: D) -> Result<Option<Option<T>>, D::Error> {
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+#[patch("/admin/products/<slug>", format = "application/json", data = "<patch>")]
+pub async fn patch_product(slug: &str, patch: Json<ProductPatch>, _caller: RequirePermission<ManageProducts>, state: &State<Arc<AppState>>) -> (Status, Json<serde_json::Value>) {
+    let mut product = match existing_product(slug, state).await {
+        Ok(product) => product,
+        Err(e) => return e
+    };
+
+    let patch = patch.into_inner();
+    if patch.stock_qty.is_some() {
+        return product_error(Status::UnprocessableEntity, "stock_qty is changed through /admin/inventory/<sku>/adjustments");
+    }
+    if let Some(sku) = patch.sku { product.sku = sku; }
+    if let Some(title) = patch.title { product.title = title; }
+    if let Some(slug) = patch.slug { product.slug = slug; }
+    if let Some(desc) = patch.desc { product.desc = desc; }
+    if let Some(img) = patch.img { product.img = img; }
+    if let Some(category) = patch.category { product.category = category; }
+    if let Some(color) = patch.color { product.color = color; }
+    if let Some(size) = patch.size { product.size = size; }
+    if let Some(price) = patch.price { product.price = price; }
+    if let Some(price_overrides) = patch.price_overrides { product.price_overrides = price_overrides; }
+    if let Some(threshold) = patch.low_stock_threshold { product.low_stock_threshold = threshold; }
+    if let Some(extras) = patch.extras { product.extras = Some(extras); }
+
+    if let Err(e) = assign_group(&mut product, patch.group.as_deref(), state).await {
+        return e;
+    }
+
+    save_product(product, Status::Ok, state).await
+}
+
+#[delete("/admin/products/<slug>")]
+pub async fn delete_product(slug: &str, _caller: RequirePermission<ManageProducts>, state: &State<Arc<AppState>>) -> (Status, Json<serde_json::Value>) {
+    let product = match existing_product(slug, state).await {
+        Ok(product) => product,
+        Err(e) => return e
+    };
+
+    match product.delete(&state.db).await {
+        Ok(_) => (Status::Ok, Json(json!({"success" : true, "message" : "Product deleted" }))),
+        Err(e) => {
+            println!("{:?}", e);
+            product_error(Status::InternalServerError, "Unable to delete product")
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StockAdjustment {
+    pub kind: MovementKind,
+    pub quantity: i64,      // added to the stock, negative to take from it
+    pub reason: String,
+    #[serde(default)]
+    pub warehouse: Option<String>   // code, may be left out while there is only one
+}
+
+async fn product_by_sku(sku: &str, state: &AppState) -> Result<Product, (Status, Json<serde_json::Value>)> {
+    match Product::find_by_sku(sku, &state.db).await {
+        Ok(Some(product)) => Ok(product),
+        Ok(None) => Err(product_error(Status::NotFound, "Product not found")),
+        Err(e) => {
+            println!("{:?}", e);
+            Err(product_error(Status::InternalServerError, "Unable to retrieve data"))
+        }
+    }
+}
+
+#[post("/admin/inventory/<sku>/adjustments", format = "application/json", data = "<adjustment>")]
+pub async fn adjust_stock(sku: &str, adjustment: Json<StockAdjustment>, caller: RequirePermission<ManageInventory>, state: &State<Arc<AppState>>) -> (Status, Json<serde_json::Value>) {
+    let StockAdjustment { kind, quantity, reason, warehouse } = adjustment.into_inner();
+    if !kind.accepts(quantity) {
+        return product_error(Status::UnprocessableEntity, &format!("quantity does not suit a {} movement", kind.as_str()));
+    }
+    if reason.trim().is_empty() {
+        return product_error(Status::UnprocessableEntity, "reason must not be empty");
+    }
+
+    let product = match product_by_sku(sku, state).await {
+        Ok(product) => product,
+        Err(e) => return e
+    };
+    let warehouse = match stock_warehouse(warehouse.as_deref(), state).await {
+        Ok(warehouse) => warehouse,
+        Err(e) => return e
+    };
+    let actor = match current_user(&caller.claims, &state.db).await {
+        Ok(user) => user.id.unwrap(),
+        Err(_) => return (Status::Unauthorized, Json(json!({"success" : false, "error" : "User not found" })))
+    };
+
+    match InventoryMovement::record(product.id.as_ref().unwrap(), &warehouse, kind, quantity, Some(&actor), Some(reason.trim().to_string()), &state.db).await {
+        Ok(Some(movement)) => (Status::Created, Json(json!({"success" : true, "movement" : movement }))),
+        Ok(None) => product_error(Status::Conflict, &format!("Not enough of {} in stock at {}", sku, warehouse.key())),
+        Err(e) => {
+            println!("{:?}", e);
+            product_error(Status::InternalServerError, "Unable to adjust stock")
+        }
+    }
+}
+
+const DEFAULT_HISTORY_SIZE: u32 = 50;
+const MAX_HISTORY_SIZE: u32 = 200;
+const MAX_HISTORY_OFFSET: u32 = 100_000;
+
+#[get("/admin/inventory/<sku>/movements?<offset>&<limit>")]
+pub async fn list_stock_movements(sku: &str, offset: Option<u32>, limit: Option<u32>, _caller: RequirePermission<ManageInventory>, state: &State<Arc<AppState>>) -> (Status, Json<serde_json::Value>) {
+    let product = match product_by_sku(sku, state).await {
+        Ok(product) => product,
+        Err(e) => return e
+    };
+
+    let offset = offset.unwrap_or(0).min(MAX_HISTORY_OFFSET);
+    let limit = limit.unwrap_or(DEFAULT_HISTORY_SIZE).clamp(1, MAX_HISTORY_SIZE);
+
+    // One extra row tells whether there is a next page
+    let mut movements = match InventoryMovement::for_product(product.id.as_ref().unwrap(), offset, limit + 1, &state.db).await {
+        Ok(movements) => movements,
+        Err(e) => {
+            println!("{:?}", e);
+            return product_error(Status::InternalServerError, "Unable to retrieve data");
+        }
+    };
+
+    let has_more = movements.len() > limit as usize;
+    movements.truncate(limit as usize);
+    let next_offset = if has_more { Some(offset.saturating_add(limit)) } else { None };
+
+    let levels = match WarehouseStock::for_products(&[product.id.clone().unwrap()], &state.db).await {
+        Ok(levels) => levels,
+        Err(e) => {
+            println!("{:?}", e);
+            return product_error(Status::InternalServerError, "Unable to retrieve data");
+        }
+    };
+
+    (Status::Ok, Json(json!({
+        "success" : true,
+        "sku" : product.sku,
+        "stock_qty" : product.stock_qty,
+        "available_qty" : product.available_qty,
+        "levels" : levels,
+        "movements" : movements,
+        "pagination" : { "offset" : offset, "limit" : limit, "next_offset" : next_offset }
+    })))
+}
+
+/// Stock levels and products whose stock doesn't add up to their movements.
+#[get("/admin/inventory/reconcile")]
+pub async fn stock_discrepancies(_caller: RequirePermission<ManageInventory>, state: &State<Arc<AppState>>) -> (Status, Json<serde_json::Value>) {
+    match InventoryMovement::discrepancies(&state.db).await {
+        Ok(discrepancies) => (Status::Ok, Json(json!({"success" : true, "discrepancies" : discrepancies }))),
+        Err(e) => {
+            println!("{:?}", e);
+            product_error(Status::InternalServerError, "Unable to retrieve data")
+        }
+    }
+}
+
+/// Sets drifted stock back to what the movements add up to, the ledger
+/// being the record of what happened.
+#[post("/admin/inventory/reconcile")]
+pub async fn reconcile_stock(_caller: RequirePermission<ManageInventory>, state: &State<Arc<AppState>>) -> (Status, Json<serde_json::Value>) {
+    match InventoryMovement::reconcile(&state.db).await {
+        Ok(fixed) => (Status::Ok, Json(json!({"success" : true, "reconciled" : fixed }))),
+        Err(e) => {
+            println!("{:?}", e);
+            product_error(Status::InternalServerError, "Unable to reconcile stock")
+        }
+    }
+}
+
+/// Products at or below their low stock threshold.
+#[get("/admin/inventory/low-stock")]
+pub async fn list_low_stock(_caller: RequirePermission<ManageInventory>, state: &State<Arc<AppState>>) -> (Status, Json<serde_json::Value>) {
+    match Product::low_stock(&state.db).await {
+        Ok(products) => (Status::Ok, Json(json!({"success" : true, "products" : products }))),
+        Err(e) => {
+            println!("{:?}", e);
+            product_error(Status::InternalServerError, "Unable to retrieve data")
+        }
+    }
+}
+
+#[get("/admin/warehouses")]
+pub async fn list_warehouses(_caller: RequirePermission<ManageInventory>, state: &State<Arc<AppState>>) -> (Status, Json<serde_json::Value>) {
+    let warehouses = Warehouse::get_all(&state.db).await;
+    (Status::Ok, Json(json!({"success" : true, "warehouses" : warehouses })))
+}
+
+async fn save_warehouse(warehouse: Warehouse, status: Status, state: &AppState) -> (Status, Json<serde_json::Value>) {
+    if let Err(errors) = warehouse.validate() {
+        return (Status::UnprocessableEntity, Json(json!({"success" : false, "error" : "Invalid warehouse", "errors" : errors })));
+    }
+
+    match warehouse.save(&state.db).await {
+        Ok(warehouse) => (status, Json(json!({"success" : true, "warehouse" : warehouse }))),
+        Err(e) => {
+            println!("{:?}", e);
+            product_error(Status::InternalServerError, "Unable to save warehouse")
+        }
+    }
+}
+
+#[post("/admin/warehouses", format = "application/json", data = "<warehouse>")]
+pub async fn create_warehouse(warehouse: Json<Warehouse>, _caller: RequirePermission<ManageInventory>, state: &State<Arc<AppState>>) -> (Status, Json<serde_json::Value>) {
+    let mut warehouse = warehouse.into_inner();
+    warehouse.id = None;
+    warehouse.created_at = None;
+
+    match Warehouse::find_by_code(&warehouse.code, &state.db).await {
+        Ok(Some(_)) => return product_error(Status::Conflict, "A warehouse with this code already exists"),
+        Ok(None) => {},
+        Err(e) => {
+            println!("{:?}", e);
+            return product_error(Status::InternalServerError, "Unable to retrieve data");
+        }
+    }
+
+    save_warehouse(warehouse, Status::Created, state).await
+}
+
+/// Renames, moves or (de)activates a warehouse. Its code stays, stock and
+/// orders refer to it.
+#[put("/admin/warehouses/<code>", format = "application/json", data = "<warehouse>")]
+pub async fn update_warehouse(code: &str, warehouse: Json<Warehouse>, _caller: RequirePermission<ManageInventory>, state: &State<Arc<AppState>>) -> (Status, Json<serde_json::Value>) {
+    let existing = match Warehouse::find_by_code(code, &state.db).await {
+        Ok(Some(existing)) => existing,
+        Ok(None) => return product_error(Status::NotFound, "Warehouse not found"),
+        Err(e) => {
+            println!("{:?}", e);
+            return product_error(Status::InternalServerError, "Unable to retrieve data");
+        }
+    };
+
+    let mut warehouse = warehouse.into_inner();
+    warehouse.id = existing.id;
+    warehouse.code = existing.code;
+    warehouse.created_at = existing.created_at;
+
+    save_warehouse(warehouse, Status::Ok, state).await
+}
+
+/// Back in stock and low stock notifications, queued ones by default, for
+/// the mailer to send.
+#[get("/admin/notifications?<status>&<offset>&<limit>")]
+pub async fn list_notifications(status: Option<&str>, offset: Option<u32>, limit: Option<u32>, _caller: RequirePermission<ManageInventory>, state: &State<Arc<AppState>>) -> (Status, Json<serde_json::Value>) {
+    let status = match status.unwrap_or("queued") {
+        "queued" => NotificationStatus::Queued,
+        "sent" => NotificationStatus::Sent,
+        _ => return product_error(Status::UnprocessableEntity, "status must be queued or sent")
+    };
+    let offset = offset.unwrap_or(0).min(MAX_HISTORY_OFFSET);
+    let limit = limit.unwrap_or(DEFAULT_HISTORY_SIZE).clamp(1, MAX_HISTORY_SIZE);
+
+    match Notification::with_status(status, offset, limit, &state.db).await {
+        Ok(notifications) => (Status::Ok, Json(json!({
+            "success" : true,
+            "status" : status.as_str(),
+            "notifications" : notifications,
+            "pagination" : { "offset" : offset, "limit" : limit }
+        }))),
+        Err(e) => {
+            println!("{:?}", e);
+            product_error(Status::InternalServerError, "Unable to retrieve data")
+        }
+    }
+}
+
+#[post("/admin/notifications/<id>/sent")]
+pub async fn mark_notification_sent(id: &str, _caller: RequirePermission<ManageInventory>, state: &State<Arc<AppState>>) -> (Status, Json<serde_json::Value>) {
+    match Notification::mark_sent(id, &state.db).await {
+        Ok(Some(notification)) => (Status::Ok, Json(json!({"success" : true, "notification" : notification }))),
+        Ok(None) => product_error(Status::NotFound, "No queued notification with this id"),
+        Err(e) => {
+            println!("{:?}", e);
+            product_error(Status::InternalServerError, "Unable to update notification")
+        }
+    }
+}
+
+async fn save_product_group(group: ProductGroup, status: Status, state: &AppState) -> (Status, Json<serde_json::Value>) {
+    if let Err(errors) = group.validate() {
+        return (Status::UnprocessableEntity, Json(json!({"success" : false, "error" : "Invalid product group", "errors" : errors })));
+    }
+
+    match ProductGroup::find_by_slug(&group.slug, &state.db).await {
+        Ok(Some(existing)) if existing.id != group.id => {
+            return product_error(Status::Conflict, "A product group with this slug already exists");
+        },
+        Ok(_) => {},
+        Err(e) => {
+            println!("{:?}", e);
+            return product_error(Status::InternalServerError, "Unable to retrieve data");
+        }
+    }
+
+    match group.save(&state.db).await {
+        Ok(group) => (status, Json(json!({"success" : true, "group" : group }))),
+        Err(e) => {
+            println!("{:?}", e);
+            product_error(Status::InternalServerError, "Unable to save product group")
+        }
+    }
+}
+
+#[post("/admin/product-groups", format = "application/json", data = "<group>")]
+pub async fn create_product_group(group: Json<ProductGroup>, _caller: RequirePermission<ManageProducts>, state: &State<Arc<AppState>>) -> (Status, Json<serde_json::Value>) {
+    let mut group = group.into_inner();
+    group.id = None;
+
+    save_product_group(group, Status::Created, state).await
+}
+
+#[put("/admin/product-groups/<slug>", format = "application/json", data = "<group>")]
+pub async fn update_product_group(slug: &str, group: Json<ProductGroup>, _caller: RequirePermission<ManageProducts>, state: &State<Arc<AppState>>) -> (Status, Json<serde_json::Value>) {
+    let existing = match ProductGroup::find_by_slug(slug, &state.db).await {
+        Ok(Some(group)) => group,
+        Ok(None) => return product_error(Status::NotFound, "Product group not found"),
+        Err(e) => {
+            println!("{:?}", e);
+            return product_error(Status::InternalServerError, "Unable to retrieve data");
+        }
+    };
+
+    let mut group = group.into_inner();
+    group.id = existing.id;
+
+    save_product_group(group, Status::Ok, state).await
+}
+
+/// A coupon as sent by an admin, the products it is limited to referred to
+/// by slug.
+#[derive(Debug, Deserialize)]
+pub struct CouponBody {
+    #[serde(flatten)]
+    pub coupon: Coupon,
+    #[serde(default)]
+    pub product_slugs: Vec<String>
+}
+
+/// Validates and writes a coupon, mapping code clashes to 409.
+async fn save_coupon(body: CouponBody, existing: Option<Coupon>, state: &AppState) -> (Status, Json<serde_json::Value>) {
+    let CouponBody { mut coupon, product_slugs } = body;
+    coupon.code = Coupon::normalize_code(&coupon.code);
+
+    coupon.products = Vec::new();
+    for slug in &product_slugs {
+        match Product::find_by_slug(slug, &state.db).await {
+            Ok(Some(product)) => coupon.products.extend(product.id),
+            Ok(None) => return product_error(Status::UnprocessableEntity, &format!("Unknown product {}", slug)),
+            Err(e) => {
+                println!("{:?}", e);
+                return product_error(Status::InternalServerError, "Unable to retrieve data");
+            }
+        }
+    }
+
+    // Usage is only ever counted by orders
+    let status = match existing {
+        Some(existing) => {
+            coupon.id = existing.id;
+            coupon.used_count = existing.used_count;
+            coupon.created_at = existing.created_at;
+            Status::Ok
+        },
+        None => {
+            coupon.id = None;
+            coupon.used_count = 0;
+            coupon.created_at = None;
+            Status::Created
+        }
+    };
+
+    if let Err(errors) = coupon.validate() {
+        return (Status::UnprocessableEntity, Json(json!({"success" : false, "error" : "Invalid coupon", "errors" : errors })));
+    }
+    if [&coupon.amount_off, &coupon.min_cart_value].iter()
+        .any(|amount| amount.as_ref().is_some_and(|amount| amount.currency != state.currency)) {
+        return product_error(Status::UnprocessableEntity, &format!("Coupon amounts must be in {}", state.currency));
+    }
+
+    match coupon.save(&state.db).await {
+        Ok(coupon) => (status, Json(json!({"success" : true, "coupon" : coupon }))),
+        Err(e) if Coupon::is_code_conflict(&e) => product_error(Status::Conflict, "A coupon with this code already exists"),
+        Err(e) => {
+            println!("{:?}", e);
+            product_error(Status::InternalServerError, "Unable to save coupon")
+        }
+    }
+}
+
+#[get("/admin/coupons")]
+pub async fn list_coupons(_caller: RequirePermission<ManageCoupons>, state: &State<Arc<AppState>>) -> (Status, Json<serde_json::Value>) {
+    let coupons = Coupon::get_all(&state.db).await;
+    (Status::Ok, Json(json!({"success" : true, "coupons" : coupons })))
+}
+
+#[post("/admin/coupons", format = "application/json", data = "<body>")]
+pub async fn create_coupon(body: Json<CouponBody>, _caller: RequirePermission<ManageCoupons>, state: &State<Arc<AppState>>) -> (Status, Json<serde_json::Value>) {
+    save_coupon(body.into_inner(), None, state).await
+}
+
+/// Replaces a coupon. Set `active` to false to withdraw it, coupons already
+/// used on orders are kept for their history.
+#[put("/admin/coupons/<code>", format = "application/json", data = "<body>")]
+pub async fn update_coupon(code: &str, body: Json<CouponBody>, _caller: RequirePermission<ManageCoupons>, state: &State<Arc<AppState>>) -> (Status, Json<serde_json::Value>) {
+    let existing = match Coupon::find_by_code(code, &state.db).await {
+        Ok(Some(coupon)) => coupon,
+        Ok(None) => return product_error(Status::NotFound, "Coupon not found"),
+        Err(e) => {
+            println!("{:?}", e);
+            return product_error(Status::InternalServerError, "Unable to retrieve data");
+        }
+    };
+
+    save_coupon(body.into_inner(), Some(existing), state).await
+}
+
+/// Replaces the exchange rate table, prices shown in other currencies follow
+/// right away.
+#[put("/admin/exchange-rates", format = "application/json", data = "<rates>")]
+pub async fn replace_exchange_rates(rates: Json<Vec<ExchangeRate>>, _caller: RequirePermission<ManageProducts>, state: &State<Arc<AppState>>) -> (Status, Json<serde_json::Value>) {
+    let rates = rates.into_inner();
+    if let Err(errors) = ExchangeRate::validate_all(&rates, &state.currency) {
+        return (Status::UnprocessableEntity, Json(json!({"success" : false, "error" : "Invalid exchange rates", "errors" : errors })));
+    }
+
+    match ExchangeRate::replace_all(rates, &state.db).await {
+        Ok(rates) => {
+            let rates = ExchangeRates::new(&state.currency, rates);
+            let response = Json(json!({"success" : true, "base" : rates.base(), "rates" : rates.rates() }));
+            *state.rates.write().unwrap() = rates;
+            (Status::Ok, response)
+        },
+        Err(e) => {
+            println!("{:?}", e);
+            product_error(Status::InternalServerError, "Unable to save exchange rates")
+        }
+    }
+}
+
+async fn existing_order(id: &str, state: &AppState) -> Result<Order, (Status, Json<serde_json::Value>)> {
+    match Order::find_by_key(id, &state.db).await {
+        Ok(Some(order)) => Ok(order),
+        Ok(None) => Err((Status::NotFound, Json(json!({"success" : false, "error" : "Order not found" })))),
+        Err(e) => {
+            println!("{:?}", e);
+            Err((Status::InternalServerError, Json(json!({"success" : false, "error" : "Unable to retrieve data" }))))
+        }
+    }
+}
+
+#[get("/admin/orders/<id>")]
+pub async fn get_order_admin(id: &str, _caller: RequirePermission<ReadOrders>, state: &State<Arc<AppState>>) -> (Status, Json<serde_json::Value>) {
+    let order = match existing_order(id, state).await {
+        Ok(order) => order,
+        Err(e) => return e
+    };
+
+    match OrderEvent::for_order(order.id.as_ref().unwrap(), &state.db).await {
+        Ok(events) => (Status::Ok, Json(json!({"success" : true, "order" : order, "events" : events }))),
+        Err(e) => {
+            println!("{:?}", e);
+            (Status::InternalServerError, Json(json!({"success" : false, "error" : "Unable to retrieve data" })))
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StatusChange {
+    pub status: OrderStatus,
+    #[serde(default)]
+    pub note: Option<String>
+}
+
+#[post("/admin/orders/<id>/status", format = "application/json", data = "<change>")]
+pub async fn set_order_status(id: &str, change: Json<StatusChange>, caller: RequirePermission<ManageOrders>, state: &State<Arc<AppState>>) -> (Status, Json<serde_json::Value>) {
+    let order = match existing_order(id, state).await {
+        Ok(order) => order,
+        Err(e) => return e
+    };
+    let actor = match current_user(&caller.claims, &state.db).await {
+        Ok(user) => user.id.unwrap(),
+        Err(_) => return (Status::Unauthorized, Json(json!({"success" : false, "error" : "User not found" })))
+    };
+
+    // The status is claimed before the gateway is asked for the money back,
+    // so two staff members can't both refund the order
+    let StatusChange { status, note } = change.into_inner();
+    match order.transition(status, Some(&actor), note, &state.db).await {
+        Ok(Transition::Moved(moved)) => {
+            if order.status.requires_refund(status) && let Err(e) = refund_order(&moved, state).await {
+                return e;
+            }
+            (Status::Ok, Json(json!({"success" : true, "order" : moved })))
+        },
+        Ok(Transition::Illegal { from, to }) => (Status::Conflict, Json(json!({
+            "success" : false,
+            "error" : format!("A {} order can not be moved to {}", from.as_str(), to.as_str())
+        }))),
+        Ok(Transition::Stale) => (Status::Conflict, Json(json!({"success" : false, "error" : "The order was changed meanwhile, try again" }))),
+        Ok(Transition::OutOfStock) => (Status::Conflict, Json(json!({"success" : false, "error" : "Some items of this order are no longer available" }))),
+        Err(e) => {
+            println!("{:?}", e);
+            (Status::InternalServerError, Json(json!({"success" : false, "error" : "Unable to update order" })))
+        }
+    }
+}
+
+#[post("/admin/orders/<id>/payment/capture")]
+pub async fn capture_payment(id: &str, _caller: RequirePermission<ManageOrders>, state: &State<Arc<AppState>>) -> (Status, Json<serde_json::Value>) {
+    match existing_order(id, state).await {
+        Ok(order) => capture_order_payment(order, state).await,
+        Err(e) => e
+    }
+}
@@ -0,0 +1,293 @@
+use std::convert::Infallible;
+use std::sync::Arc;
+
+use rocket::{delete, get, post, put, Request, State};
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome};
+use rocket::serde::json::Json;
+use rocket::serde::Deserialize;
+use serde_json::json;
+use surrealdb::RecordId;
+use crate::utils::AppState;
+use crate::database::models::*;
+use crate::database::models::cart::MAX_LINE_QUANTITY;
+use crate::database::models::coupon::CouponRejection;
+use crate::routes::index::JwtError;
+use crate::utils::auth::{current_user, generate_opaque_token, hash_opaque_token, Claims};
+
+type CartResponse = (Status, Json<serde_json::Value>);
+
+/// Header carrying the opaque token of a guest cart.
+pub const CART_TOKEN_HEADER: &str = "X-Cart-Token";
+
+/// Token of the guest cart sent with the request, if any.
+pub struct GuestCartToken(pub Option<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for GuestCartToken {
+    type Error = Infallible;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(GuestCartToken(req.headers().get_one(CART_TOKEN_HEADER).map(str::to_string)))
+    }
+}
+
+/// Whose cart a request works on. Signed in users get their own cart, anyone
+/// else gets the guest cart of their cart token.
+pub enum CartOwner {
+    User(Claims),
+    Guest(Option<String>)
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for CartOwner {
+    type Error = JwtError;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        // A bad access token is an error, not a reason to fall back to a guest cart
+        if req.headers().contains("Authorization") {
+            return req.guard::<Claims>().await.map(CartOwner::User);
+        }
+
+        match req.guard::<GuestCartToken>().await {
+            Outcome::Success(GuestCartToken(token)) => Outcome::Success(CartOwner::Guest(token)),
+            _ => Outcome::Success(CartOwner::Guest(None))
+        }
+    }
+}
+
+/// A cart with the guest token it is reachable by.
+struct OwnedCart {
+    cart: Cart,
+    token: Option<String>
+}
+
+fn cart_error(status: Status, error: &str) -> CartResponse {
+    (status, Json(json!({"success" : false, "error" : error })))
+}
+
+fn too_many() -> CartResponse {
+    cart_error(Status::UnprocessableEntity, &format!("quantity must be at most {}", MAX_LINE_QUANTITY))
+}
+
+async fn owned_cart(owner: &CartOwner, state: &AppState) -> Result<OwnedCart, CartResponse> {
+    let cart = match owner {
+        CartOwner::User(jwt_claims) => {
+            let user = current_user(jwt_claims, &state.db).await
+                .map_err(|_| cart_error(Status::Unauthorized, "User not found"))?;
+            Cart::for_user(&user.id.unwrap(), &state.db).await
+                .map(|cart| OwnedCart { cart, token: None })
+        },
+        // Unknown or expired tokens start over with a new cart and token
+        CartOwner::Guest(Some(token)) => Cart::find_by_guest_hash(&hash_opaque_token(token), &state.db).await
+            .map(|cart| match cart {
+                Some(cart) => OwnedCart { cart, token: Some(token.clone()) },
+                None => OwnedCart { cart: Cart::guest(), token: None }
+            }),
+        CartOwner::Guest(None) => Ok(OwnedCart { cart: Cart::guest(), token: None })
+    };
+
+    cart.map_err(|e| {
+        println!("{:?}", e);
+        cart_error(Status::InternalServerError, "Unable to retrieve data")
+    })
+}
+
+async fn product_id(slug: &str, state: &AppState) -> Result<RecordId, CartResponse> {
+    match Product::find_by_slug(slug, &state.db).await {
+        Ok(Some(product)) => Ok(product.id.unwrap()),
+        Ok(None) => Err(cart_error(Status::NotFound, "Product not found")),
+        Err(e) => {
+            println!("{:?}", e);
+            Err(cart_error(Status::InternalServerError, "Unable to retrieve data"))
+        }
+    }
+}
+
+/// Responds with the cart priced from current product data. Guests also get
+/// back the token of their cart.
+async fn cart_response(owned: &OwnedCart, state: &AppState) -> CartResponse {
+    match owned.cart.priced(&state.currency, &state.db).await {
+        Ok(priced) if owned.cart.is_guest() => (Status::Ok, Json(json!({
+            "success" : true,
+            "cart" : priced,
+            "cart_token" : owned.token,
+            "expires_at" : owned.cart.expires_at
+        }))),
+        Ok(priced) => (Status::Ok, Json(json!({"success" : true, "cart" : priced }))),
+        Err(e) => {
+            println!("{:?}", e);
+            cart_error(Status::InternalServerError, "Unable to retrieve data")
+        }
+    }
+}
+
+async fn save_cart(owned: OwnedCart, state: &AppState) -> CartResponse {
+    let OwnedCart { mut cart, mut token } = owned;
+
+    if cart.is_guest() {
+        if cart.guest_token_hash.is_none() {
+            let new_token = generate_opaque_token();
+            cart.guest_token_hash = Some(hash_opaque_token(&new_token));
+            token = Some(new_token);
+        }
+        cart.extend_expiry(state.guest_cart_secs);
+    }
+
+    match cart.save(&state.db).await {
+        Ok(cart) => cart_response(&OwnedCart { cart, token }, state).await,
+        Err(e) => {
+            println!("{:?}", e);
+            cart_error(Status::InternalServerError, "Unable to save cart")
+        }
+    }
+}
+
+#[get("/cart")]
+pub async fn get_cart(owner: CartOwner, state: &State<Arc<AppState>>) -> CartResponse {
+    match owned_cart(&owner, state).await {
+        Ok(owned) => cart_response(&owned, state).await,
+        Err(e) => e
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AddCartItem {
+    pub slug: String,
+    pub quantity: u32
+}
+
+#[post("/cart/items", format = "application/json", data = "<item>")]
+pub async fn add_cart_item(item: Json<AddCartItem>, owner: CartOwner, state: &State<Arc<AppState>>) -> CartResponse {
+    if item.quantity == 0 {
+        return cart_error(Status::UnprocessableEntity, "quantity must be at least 1");
+    }
+
+    let mut owned = match owned_cart(&owner, state).await {
+        Ok(owned) => owned,
+        Err(e) => return e
+    };
+    let product = match product_id(&item.slug, state).await {
+        Ok(product) => product,
+        Err(e) => return e
+    };
+
+    let quantity = owned.cart.quantity_of(&product).saturating_add(item.quantity);
+    if quantity > MAX_LINE_QUANTITY {
+        return too_many();
+    }
+    owned.cart.set_quantity(&product, quantity);
+    save_cart(owned, state).await
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CartItemQuantity {
+    pub quantity: u32
+}
+
+#[put("/cart/items/<slug>", format = "application/json", data = "<item>")]
+pub async fn update_cart_item(slug: &str, item: Json<CartItemQuantity>, owner: CartOwner, state: &State<Arc<AppState>>) -> CartResponse {
+    if item.quantity > MAX_LINE_QUANTITY {
+        return too_many();
+    }
+
+    let mut owned = match owned_cart(&owner, state).await {
+        Ok(owned) => owned,
+        Err(e) => return e
+    };
+    let product = match product_id(slug, state).await {
+        Ok(product) => product,
+        Err(e) => return e
+    };
+
+    if owned.cart.quantity_of(&product) == 0 {
+        return cart_error(Status::NotFound, "Product is not in the cart");
+    }
+
+    owned.cart.set_quantity(&product, item.quantity);
+    save_cart(owned, state).await
+}
+
+#[delete("/cart/items/<slug>")]
+pub async fn remove_cart_item(slug: &str, owner: CartOwner, state: &State<Arc<AppState>>) -> CartResponse {
+    let mut owned = match owned_cart(&owner, state).await {
+        Ok(owned) => owned,
+        Err(e) => return e
+    };
+    let product = match product_id(slug, state).await {
+        Ok(product) => product,
+        Err(e) => return e
+    };
+
+    owned.cart.set_quantity(&product, 0);
+    save_cart(owned, state).await
+}
+
+#[delete("/cart")]
+pub async fn clear_cart(owner: CartOwner, state: &State<Arc<AppState>>) -> CartResponse {
+    let mut owned = match owned_cart(&owner, state).await {
+        Ok(owned) => owned,
+        Err(e) => return e
+    };
+
+    owned.cart.clear();
+    save_cart(owned, state).await
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ApplyCoupon {
+    pub code: String
+}
+
+/// Puts a coupon on the cart if it applies to it right now. The discount is
+/// worked out again whenever the cart is shown or ordered.
+#[post("/cart/coupon", format = "application/json", data = "<coupon>")]
+pub async fn apply_coupon(coupon: Json<ApplyCoupon>, owner: CartOwner, state: &State<Arc<AppState>>) -> CartResponse {
+    let mut owned = match owned_cart(&owner, state).await {
+        Ok(owned) => owned,
+        Err(e) => return e
+    };
+
+    let checked = match owned.cart.products(&state.db).await {
+        Ok(items) => Coupon::check(&coupon.code, &items, owned.cart.user.as_ref(), &state.db).await,
+        Err(e) => Err(e)
+    };
+    match checked {
+        Ok(Ok((coupon, _))) => owned.cart.coupon_code = Some(coupon.code),
+        Ok(Err(CouponRejection::Unknown)) => return cart_error(Status::NotFound, "Unknown coupon"),
+        Ok(Err(rejection)) => return cart_error(Status::UnprocessableEntity, &rejection.message()),
+        Err(e) => {
+            println!("{:?}", e);
+            return cart_error(Status::InternalServerError, "Unable to retrieve data");
+        }
+    }
+
+    save_cart(owned, state).await
+}
+
+#[delete("/cart/coupon")]
+pub async fn remove_coupon(owner: CartOwner, state: &State<Arc<AppState>>) -> CartResponse {
+    let mut owned = match owned_cart(&owner, state).await {
+        Ok(owned) => owned,
+        Err(e) => return e
+    };
+
+    owned.cart.coupon_code = None;
+    save_cart(owned, state).await
+}
+
+/// Merges the guest cart of `token` into the cart of `user` after they sign
+/// in. A failed merge must not fail the sign in, so it is only logged.
+pub async fn merge_guest_cart(token: &GuestCartToken, user: &User, state: &AppState) -> bool {
+    let (Some(token), Some(user_id)) = (&token.0, &user.id) else {
+        return false;
+    };
+
+    match Cart::merge_guest(&hash_opaque_token(token), user_id, &state.db).await {
+        Ok(merged) => merged.is_some(),
+        Err(e) => {
+            println!("Guest cart merge failed : {:?}", e);
+            false
+        }
+    }
+}
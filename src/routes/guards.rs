@@ -0,0 +1,74 @@
+use std::marker::PhantomData;
+
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome};
+use rocket::Request;
+
+use crate::database::models::role::{self, permissions};
+use crate::utils::auth::Claims;
+use super::index::JwtError;
+
+#[derive(Debug)]
+pub enum AuthzError {
+    Unauthenticated(JwtError),
+    Forbidden
+}
+
+/// A permission that can be required by `RequirePermission`.
+pub trait Permission {
+    const NAME: &'static str;
+}
+
+pub struct ManageProducts;
+pub struct ManageInventory;
+pub struct ReadOrders;
+pub struct ManageOrders;
+pub struct ReadUsers;
+pub struct ManageUsers;
+pub struct ManageCoupons;
+
+impl Permission for ManageProducts { const NAME: &'static str = permissions::PRODUCTS_MANAGE; }
+impl Permission for ManageInventory { const NAME: &'static str = permissions::INVENTORY_MANAGE; }
+impl Permission for ReadOrders { const NAME: &'static str = permissions::ORDERS_READ; }
+impl Permission for ManageOrders { const NAME: &'static str = permissions::ORDERS_MANAGE; }
+impl Permission for ReadUsers { const NAME: &'static str = permissions::USERS_READ; }
+impl Permission for ManageUsers { const NAME: &'static str = permissions::USERS_MANAGE; }
+impl Permission for ManageCoupons { const NAME: &'static str = permissions::COUPONS_MANAGE; }
+
+async fn authorize(req: &Request<'_>, allowed: impl Fn(&Claims) -> bool) -> Outcome<Claims, AuthzError> {
+    match req.guard::<Claims>().await {
+        Outcome::Success(claims) if allowed(&claims) => Outcome::Success(claims),
+        Outcome::Success(_) => Outcome::Error((Status::Forbidden, AuthzError::Forbidden)),
+        Outcome::Error((status, e)) => Outcome::Error((status, AuthzError::Unauthenticated(e))),
+        Outcome::Forward(status) => Outcome::Forward(status)
+    }
+}
+
+/// Caller holding the admin role.
+pub struct AdminUser(pub Claims);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AdminUser {
+    type Error = AuthzError;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        authorize(req, |claims| claims.has_role(role::ADMIN)).await
+            .map(AdminUser)
+    }
+}
+
+/// Caller whose roles grant the permission `P`.
+pub struct RequirePermission<P: Permission> {
+    pub claims: Claims,
+    permission: PhantomData<P>
+}
+
+#[rocket::async_trait]
+impl<'r, P: Permission> FromRequest<'r> for RequirePermission<P> {
+    type Error = AuthzError;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        authorize(req, |claims| claims.has_permission(P::NAME)).await
+            .map(|claims| RequirePermission { claims, permission: PhantomData })
+    }
+}
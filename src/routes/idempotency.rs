@@ -0,0 +1,94 @@
+use std::convert::Infallible;
+
+use ring::digest;
+use rocket::Request;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome};
+use rocket::serde::json::Json;
+use rocket::serde::Serialize;
+use serde_json::json;
+use crate::utils::AppState;
+use crate::database::models::idempotency_key::{IdempotencyClaim, IdempotencyKey};
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+const MAX_KEY_LEN: usize = 255;
+
+/// The `Idempotency-Key` of a request together with what identifies the
+/// request itself. Guards can't see the body, so routes that take one add it
+/// with `with_body`.
+pub struct Idempotency {
+    key: Option<String>,
+    request: String
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Idempotency {
+    type Error = Infallible;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(Idempotency {
+            key: req.headers().get_one(IDEMPOTENCY_KEY_HEADER).map(str::to_string),
+            request: format!("{} {}", req.method(), req.uri())
+        })
+    }
+}
+
+impl Idempotency {
+    pub fn with_body(mut self, body: &impl Serialize) -> Self {
+        self.request.push('\n');
+        self.request.push_str(&serde_json::to_string(body).unwrap_or_default());
+        self
+    }
+
+    fn request_hash(&self) -> String {
+        digest::digest(&digest::SHA256, self.request.as_bytes()).as_ref().iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+}
+
+/// Runs `handler` once per idempotency key of `scope`. Repeats get the stored
+/// response back, a key reused for another request gets 422. Requests without
+/// a key just run.
+pub async fn idempotent<F>(idempotency: Idempotency, scope: &str, state: &AppState, handler: F) -> (Status, Json<serde_json::Value>)
+where
+    F: Future<Output = (Status, Json<serde_json::Value>)>
+{
+    let Some(key) = idempotency.key.as_deref() else {
+        return handler.await;
+    };
+    if key.is_empty() || key.len() > MAX_KEY_LEN {
+        return (Status::BadRequest, Json(json!({"success" : false, "error" : "Invalid Idempotency-Key" })));
+    }
+
+    let record = match IdempotencyKey::claim(scope, key, &idempotency.request_hash(), &state.db).await {
+        Ok(IdempotencyClaim::New(record)) => record,
+        Ok(IdempotencyClaim::Replay(status, body)) => {
+            return (Status::from_code(status).unwrap_or(Status::Ok), Json(body));
+        },
+        Ok(IdempotencyClaim::InProgress) => {
+            return (Status::Conflict, Json(json!({"success" : false, "error" : "A request with this Idempotency-Key is still being processed" })));
+        },
+        Ok(IdempotencyClaim::Mismatch) => {
+            return (Status::UnprocessableEntity, Json(json!({"success" : false, "error" : "Idempotency-Key was already used for a different request" })));
+        },
+        Err(e) => {
+            println!("{:?}", e);
+            return (Status::InternalServerError, Json(json!({"success" : false, "error" : "Unable to retrieve data" })));
+        }
+    };
+
+    let (status, body) = handler.await;
+
+    // Server errors may go away, so the key is freed for a retry instead of replaying them
+    let stored = if status.code >= 500 {
+        record.release(&state.db).await.map(|_| ())
+    } else {
+        record.complete(status.code, body.0.clone(), &state.db).await.map(|_| ())
+    };
+    if let Err(e) = stored {
+        println!("Idempotency key not stored : {:?}", e);
+    }
+
+    (status, body)
+}
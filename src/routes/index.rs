@@ -5,27 +5,90 @@ use rocket::{get, post, Request, State};
 use rocket::serde::json::Json;
 use rocket::serde::{Deserialize, Serialize};
 use serde_json::json;
+use crate::money::Money;
 use crate::utils::AppState;
 use crate::database::models::*;
 use crate::database::utils::password_utils::{hash_password, verify_password};
-use crate::utils::auth::{generate_jwt, validate_jwt, Claims, JwtStatus};
+use crate::routes::cart::{merge_guest_cart, GuestCartToken};
+use crate::routes::products::{localized, DisplayCurrency};
+use crate::utils::auth::{check_session, generate_jwt, validate_jwt, Claims, ClientInfo, JwtStatus, SessionStatus};
 
 #[get("/")]
 pub fn index() -> Json<serde_json::Value> {
     Json(json!({"Author":"Sugat Bagde"}))
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VariantSummary {
+    pub sku: String,
+    pub slug: String,
+    pub color: String,
+    pub size: String,
+    pub price: Money,
+    pub available_qty: u32,
+    #[serde(default)]
+    pub extras: Option<serde_json::Value>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct GroupedProduct {
     pub title: String,
     pub slug: String,
     pub desc: String,
     pub img: String,
+    pub images: Vec<String>,
     pub category: String,
     pub color: Vec<String>,
     pub size: Vec<String>,
-    pub price: f32,
-    pub available_qty: u32,
+    pub price: Money,       // lowest variant price
+    pub available_qty: u32, // across all variants
+    pub variants: Vec<VariantSummary>,
+}
+
+impl GroupedProduct {
+    /// Summarises a group from its variants, which must not be empty.
+    pub fn from_variants(group: &ProductGroup, mut variants: Vec<Product>) -> GroupedProduct {
+        variants.sort_by(|a, b| a.slug.cmp(&b.slug));
+
+        let mut grouped = GroupedProduct {
+            title: group.title.clone(),
+            slug: variants[0].slug.clone(),
+            desc: group.desc.clone(),
+            img: group.images.first().cloned().unwrap_or_else(|| variants[0].img.clone()),
+            images: group.images.clone(),
+            category: group.category.clone(),
+            color: Vec::new(),
+            size: Vec::new(),
+            price: variants[0].price.clone(),
+            available_qty: 0,
+            variants: Vec::new(),
+        };
+
+        for variant in variants {
+            if !grouped.color.contains(&variant.color) {
+                grouped.color.push(variant.color.clone());
+            }
+            if !grouped.size.contains(&variant.size) {
+                grouped.size.push(variant.size.clone());
+            }
+            if variant.price.amount < grouped.price.amount {
+                grouped.price = variant.price.clone();
+            }
+            grouped.available_qty += variant.available_qty;
+
+            grouped.variants.push(VariantSummary {
+                sku: variant.sku,
+                slug: variant.slug,
+                color: variant.color,
+                size: variant.size,
+                price: variant.price,
+                available_qty: variant.available_qty,
+                extras: variant.extras,
+            });
+        }
+
+        grouped
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -34,36 +97,33 @@ pub struct WrappedProducts {
 }
 
 #[get("/get_products")]
-pub async fn get_products(state: &State<Arc<AppState>>) -> Json<WrappedProducts> {
+pub async fn get_products(display: DisplayCurrency, state: &State<Arc<AppState>>) -> Json<WrappedProducts> {
+    let groups: Vec<ProductGroup> = ProductGroup::get_all(&state.db).await;
     let products: Vec<Product> = Product::get_all(&state.db).await;
 
-    let mut grouped_products: HashMap<String, GroupedProduct> = HashMap::new();
+    // This endpoint has no way to report errors, an unsupported currency falls back to the store's
+    let rates = state.rates.read().unwrap().clone();
+    let currency = display.resolve(&rates).unwrap_or_else(|_| rates.base().to_string());
 
-    for product in products {
-        if product.stock_qty == 0 {
+    let mut variants_by_group: HashMap<String, Vec<Product>> = HashMap::new();
+    for product in products.into_iter().map(|product| localized(product, &currency, &rates)) {
+        if product.available_qty == 0 {
             continue;
         }
-
-        let entry = grouped_products.entry(product.title.clone()).or_insert_with(|| GroupedProduct {
-            title: product.title.clone(),
-            slug: product.slug.clone(),
-            desc: product.desc.clone(),
-            img: product.img.clone(),
-            category: product.category.clone(),
-            color: vec![product.color.clone()],
-            size: vec![product.size.clone()],
-            price: product.price,
-            available_qty: product.stock_qty,
-        });
-
-        if !entry.color.contains(&product.color) {
-            entry.color.push(product.color);
-        }
-        if !entry.size.contains(&product.size) {
-            entry.size.push(product.size);
+        if let Some(group) = &product.product_group {
+            variants_by_group.entry(group.to_string()).or_default().push(product);
         }
     }
 
+    // Keyed by title as it always was, so existing clients keep working
+    let mut grouped_products: HashMap<String, GroupedProduct> = HashMap::new();
+    for group in groups {
+        let Some(variants) = group.id.as_ref().and_then(|id| variants_by_group.remove(&id.to_string())) else {
+            continue;
+        };
+        grouped_products.insert(group.title.clone(), GroupedProduct::from_variants(&group, variants));
+    }
+
     Json(WrappedProducts { products: grouped_products })
 }
 
@@ -75,12 +135,13 @@ pub struct UserCredentials {
 }
 
 #[post("/signup", format = "application/json", data = "<credentials>")]
-pub async fn sign_up(credentials: Json<UserCredentials>, state: &State<Arc<AppState>>) -> Json<serde_json::Value> {
+pub async fn sign_up(credentials: Json<UserCredentials>, guest_cart: GuestCartToken, state: &State<Arc<AppState>>) -> Json<serde_json::Value> {
     let user = User {
         name : credentials.name.clone(),
         email : credentials.email.clone(),
         password_hash : hash_password( credentials.password.as_str()).expect("Error"),
         is_admin : false,
+        roles : vec![role::CUSTOMER.to_string()],
         id : None
     };
 
@@ -88,7 +149,8 @@ pub async fn sign_up(credentials: Json<UserCredentials>, state: &State<Arc<AppSt
 
     match user.save(&state.db).await {
         Ok(user) => {
-            Json(json!({"success" : true , "message" : "Success", "email" : user.email }))
+            let cart_merged = merge_guest_cart(&guest_cart, &user, state).await;
+            Json(json!({"success" : true , "message" : "Success", "email" : user.email, "cart_merged" : cart_merged }))
         },
         Err(e) => {
             println!("{:?}",e);
@@ -105,14 +167,22 @@ pub struct LoginCredentials {
 }
 
 #[post("/login", format = "application/json", data = "<credentials>")]
-pub async fn login(credentials : Json<LoginCredentials>, state: &State<Arc<AppState>>) -> Json<serde_json::Value> {
+pub async fn login(credentials : Json<LoginCredentials>, client: ClientInfo, guest_cart: GuestCartToken, state: &State<Arc<AppState>>) -> Json<serde_json::Value> {
     let user_res = User::find_by_email(credentials.email.as_str(), &state.db).await;
 
     match user_res {
         Ok(user) => {
             if verify_password(&user.password_hash, credentials.password.as_str()).expect("Something went wrong User hash verify") {
-                let token = generate_jwt(&user, &state.jwt_key_pair, &state.db).await;
-                Json(json!({"success" : true, "message": "Yeh! Logged in Successfully!", "token" : token }))
+                let tokens = generate_jwt(&user, &client, None, &state.jwt_keys, &state.token_lifetimes, &state.db).await;
+                let cart_merged = merge_guest_cart(&guest_cart, &user, state).await;
+                Json(json!({
+                    "success" : true,
+                    "message": "Yeh! Logged in Successfully!",
+                    "token" : tokens.access_token,
+                    "refresh_token" : tokens.refresh_token,
+                    "expires_in" : tokens.expires_in,
+                    "cart_merged" : cart_merged
+                }))
             }
             else {
                 Json(json!({"success" : false, "error" : "Invalid Credentials" }))
@@ -128,14 +198,30 @@ pub async fn login(credentials : Json<LoginCredentials>, state: &State<Arc<AppSt
 }
 
 
+use std::convert::Infallible;
+
 use rocket::http::Status;
 use rocket::request::{Outcome, FromRequest};
 
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ClientInfo {
+    type Error = Infallible;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(ClientInfo {
+            user_agent: req.headers().get_one("User-Agent").map(str::to_string),
+            ip: req.client_ip().map(|ip| ip.to_string())
+        })
+    }
+}
+
 #[derive(Debug)]
 pub enum JwtError {
     Missing,
     Invalid,
-    Expired
+    Expired,
+    Revoked,
+    Unavailable
 }
 
 #[rocket::async_trait]
@@ -150,12 +236,24 @@ impl<'r> FromRequest<'r> for Claims {
 
             match outcome {
                 rocket::outcome::Outcome::Success(state) => {
-                    match validate_jwt(token, &state.jwt_key_pair) {
-                        JwtStatus::Valid(e) => Some(e),
-                        _ => None
+                    let claims = match validate_jwt(token, &state.jwt_keys) {
+                        JwtStatus::Valid(e) => e,
+                        _ => return Err((Status::Forbidden, JwtError::Invalid))
+                    };
+
+                    // A good signature is not enough, the session must still be live
+                    match check_session(claims.jti, &state.session_cache, &state.db).await {
+                        Ok(SessionStatus::Active) => Ok(claims),
+                        Ok(SessionStatus::Expired) => Err((Status::Forbidden, JwtError::Expired)),
+                        Ok(SessionStatus::Revoked) => Err((Status::Forbidden, JwtError::Revoked)),
+                        Ok(SessionStatus::Missing) => Err((Status::Forbidden, JwtError::Invalid)),
+                        Err(e) => {
+                            println!("Session lookup failed : {:?}", e);
+                            Err((Status::ServiceUnavailable, JwtError::Unavailable))
+                        }
                     }
                 },
-                _ => None
+                _ => Err((Status::InternalServerError, JwtError::Unavailable))
             }
         };
 
@@ -166,8 +264,8 @@ impl<'r> FromRequest<'r> for Claims {
                 let token = &str[7..];
 
                 match is_valid(token).await {
-                    Some(e) => Outcome::Success(e),
-                    None => Outcome::Error((Status::Forbidden, JwtError::Invalid)),
+                    Ok(e) => Outcome::Success(e),
+                    Err(e) => Outcome::Error(e),
                 }
             },
             
@@ -176,6 +274,11 @@ impl<'r> FromRequest<'r> for Claims {
     }
 }
 
+#[get("/.well-known/jwks.json")]
+pub fn jwks(state: &State<Arc<AppState>>) -> Json<serde_json::Value> {
+    Json(state.jwt_keys.jwks())
+}
+
 #[get("/verify-user")]
 pub fn verify_user(jwt_claims: Claims, _state: &State<Arc<AppState>>) -> Json<serde_json::Value> {
     Json(json!({"success" : true, "message": "Token Valid", "id" : jwt_claims.jti }))
@@ -0,0 +1,189 @@
+use std::sync::Arc;
+
+use rocket::{get, post, State};
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::serde::{Deserialize, Serialize};
+use serde_json::json;
+use surrealdb::RecordId;
+use crate::utils::AppState;
+use crate::database::models::*;
+use crate::database::models::order::{PlaceOrder, Transition};
+use crate::utils::auth::{current_user, Claims};
+use super::addresses::owned_address;
+use super::idempotency::{idempotent, Idempotency};
+use super::payments::refund_order;
+
+pub type OrderResponse = (Status, Json<serde_json::Value>);
+
+const DEFAULT_PAGE_SIZE: u32 = 10;
+const MAX_PAGE_SIZE: u32 = 50;
+const MAX_OFFSET: u32 = 10_000;
+
+pub fn order_error(status: Status, error: &str) -> OrderResponse {
+    (status, Json(json!({"success" : false, "error" : error })))
+}
+
+pub async fn order_user(jwt_claims: &Claims, state: &AppState) -> Result<RecordId, OrderResponse> {
+    match current_user(jwt_claims, &state.db).await {
+        Ok(user) => Ok(user.id.unwrap()),
+        Err(_) => Err(order_error(Status::Unauthorized, "User not found"))
+    }
+}
+
+/// The order `id` if it belongs to `user`. Other users' orders are reported
+/// as missing, so their ids can not be probed.
+pub async fn owned_order(id: &str, user: &RecordId, state: &AppState) -> Result<Order, OrderResponse> {
+    match Order::find_by_key(id, &state.db).await {
+        Ok(Some(order)) if &order.user == user => Ok(order),
+        Ok(_) => Err(order_error(Status::NotFound, "Order not found")),
+        Err(e) => {
+            println!("{:?}", e);
+            Err(order_error(Status::InternalServerError, "Unable to retrieve data"))
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct Checkout {
+    #[serde(default)]
+    pub address: Option<String>     // id of the delivery address, the default shipping one if left out
+}
+
+/// Retried with the same `Idempotency-Key`, the order is placed only once;
+/// the key can't be reused with another address. The body is optional.
+#[post("/orders", data = "<checkout>")]
+pub async fn place_order(checkout: Option<Json<Checkout>>, jwt_claims: Claims, idempotency: Idempotency, state: &State<Arc<AppState>>) -> OrderResponse {
+    let checkout = checkout.map(|checkout| checkout.into_inner()).unwrap_or_default();
+    let idempotency = idempotency.with_body(&checkout);
+    idempotent(idempotency, jwt_claims.subject(), state, place_cart_order(checkout, &jwt_claims, state)).await
+}
+
+async fn place_cart_order(checkout: Checkout, jwt_claims: &Claims, state: &AppState) -> OrderResponse {
+    let user_id = match order_user(jwt_claims, state).await {
+        Ok(user_id) => user_id,
+        Err(e) => return e
+    };
+
+    let address = match checkout.address {
+        Some(id) => match owned_address(&id, &user_id, state).await {
+            Ok(address) => address,
+            Err(e) => return e
+        },
+        None => match Address::default_shipping(&user_id, &state.db).await {
+            Ok(Some(address)) => address,
+            Ok(None) => return order_error(Status::UnprocessableEntity, "A delivery address is required"),
+            Err(e) => {
+                println!("{:?}", e);
+                return order_error(Status::InternalServerError, "Unable to retrieve data");
+            }
+        }
+    };
+
+    let cart = match Cart::for_user(&user_id, &state.db).await {
+        Ok(cart) => cart,
+        Err(e) => {
+            println!("{:?}", e);
+            return order_error(Status::InternalServerError, "Unable to retrieve data");
+        }
+    };
+
+    match Order::place(&user_id, &cart, &address, state.stock.allocation, state.stock.hold_secs, &state.db).await {
+        Ok(PlaceOrder::Placed(order)) => (Status::Created, Json(json!({"success" : true, "order" : order }))),
+        Ok(PlaceOrder::EmptyCart) => order_error(Status::UnprocessableEntity, "Cart is empty"),
+        Ok(PlaceOrder::OutOfStock(shortages)) => (Status::Conflict, Json(json!({
+            "success" : false,
+            "error" : "Some items are out of stock",
+            "out_of_stock" : shortages
+        }))),
+        Ok(PlaceOrder::CouponRejected(rejection)) => order_error(Status::UnprocessableEntity, &rejection.message()),
+        Err(e) => {
+            println!("{:?}", e);
+            order_error(Status::InternalServerError, "Unable to place order")
+        }
+    }
+}
+
+#[get("/orders?<offset>&<limit>")]
+pub async fn list_orders(offset: Option<u32>, limit: Option<u32>, jwt_claims: Claims, state: &State<Arc<AppState>>) -> OrderResponse {
+    let user_id = match order_user(&jwt_claims, state).await {
+        Ok(user_id) => user_id,
+        Err(e) => return e
+    };
+
+    let offset = offset.unwrap_or(0).min(MAX_OFFSET);
+    let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+
+    // One extra row tells whether there is a next page
+    let mut orders = match Order::for_user(&user_id, offset, limit + 1, &state.db).await {
+        Ok(orders) => orders,
+        Err(e) => {
+            println!("{:?}", e);
+            return order_error(Status::InternalServerError, "Unable to retrieve data");
+        }
+    };
+
+    let has_more = orders.len() > limit as usize;
+    orders.truncate(limit as usize);
+    let next_offset = if has_more { Some(offset.saturating_add(limit)) } else { None };
+
+    (Status::Ok, Json(json!({
+        "success" : true,
+        "orders" : orders,
+        "pagination" : { "offset" : offset, "limit" : limit, "next_offset" : next_offset }
+    })))
+}
+
+#[get("/orders/<id>")]
+pub async fn get_order(id: &str, jwt_claims: Claims, state: &State<Arc<AppState>>) -> OrderResponse {
+    let user_id = match order_user(&jwt_claims, state).await {
+        Ok(user_id) => user_id,
+        Err(e) => return e
+    };
+    let order = match owned_order(id, &user_id, state).await {
+        Ok(order) => order,
+        Err(e) => return e
+    };
+
+    match OrderEvent::for_order(order.id.as_ref().unwrap(), &state.db).await {
+        Ok(events) => (Status::Ok, Json(json!({"success" : true, "order" : order, "events" : events }))),
+        Err(e) => {
+            println!("{:?}", e);
+            order_error(Status::InternalServerError, "Unable to retrieve data")
+        }
+    }
+}
+
+/// Customers may cancel until the order ships. The held or taken stock goes
+/// back on sale and a paid order is refunded.
+#[post("/orders/<id>/cancel")]
+pub async fn cancel_order(id: &str, jwt_claims: Claims, state: &State<Arc<AppState>>) -> OrderResponse {
+    let user_id = match order_user(&jwt_claims, state).await {
+        Ok(user_id) => user_id,
+        Err(e) => return e
+    };
+    let order = match owned_order(id, &user_id, state).await {
+        Ok(order) => order,
+        Err(e) => return e
+    };
+
+    if !matches!(order.status, OrderStatus::PendingPayment | OrderStatus::Paid | OrderStatus::Packed) {
+        return order_error(Status::Conflict, "Orders can only be cancelled before they ship");
+    }
+
+    match order.transition(OrderStatus::Cancelled, Some(&user_id), Some("Cancelled by customer".to_string()), &state.db).await {
+        Ok(Transition::Moved(moved)) => {
+            if order.status.requires_refund(OrderStatus::Cancelled) && let Err(e) = refund_order(&moved, state).await {
+                return e;
+            }
+            (Status::Ok, Json(json!({"success" : true, "order" : moved })))
+        },
+        // Only paying can run out of stock
+        Ok(Transition::Illegal { .. } | Transition::OutOfStock) => order_error(Status::Conflict, "Orders can only be cancelled before they ship"),
+        Ok(Transition::Stale) => order_error(Status::Conflict, "The order was changed meanwhile, try again"),
+        Err(e) => {
+            println!("{:?}", e);
+            order_error(Status::InternalServerError, "Unable to cancel order")
+        }
+    }
+}
@@ -0,0 +1,250 @@
+use std::sync::Arc;
+
+use rocket::{post, Request, State};
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome};
+use rocket::serde::json::Json;
+use serde_json::json;
+use surrealdb::Error::Api;
+use surrealdb::error::Api::Query;
+use crate::utils::AppState;
+use crate::database::models::*;
+use crate::database::models::order::Transition;
+use crate::payments::{PaymentError, PaymentStatus};
+use crate::utils::auth::Claims;
+use super::idempotency::{idempotent, Idempotency};
+use super::orders::{order_error, order_user, owned_order, OrderResponse};
+
+/// Moves a payment to `status` and carries the change over to its order: a
+/// capture pays the order, a refund refunds it. The payment and the order
+/// are written in one transaction. A capture the order can't take, say
+/// because it was cancelled meanwhile, is refunded right away, and so is one
+/// for items sold since their hold ran out, whose order is cancelled.
+async fn settle_payment(payment: Payment, status: PaymentStatus, state: &AppState) -> Result<(Payment, Option<Order>), surrealdb::Error> {
+    // Webhooks repeat what a capture call already told us
+    if payment.status == status {
+        return Ok((payment, None));
+    }
+
+    let (next, note) = match status {
+        PaymentStatus::Captured => (OrderStatus::Paid, "Payment captured"),
+        PaymentStatus::Refunded => (OrderStatus::Refunded, "Payment refunded"),
+        PaymentStatus::Pending | PaymentStatus::Failed => return Ok((payment.set_status(status, &state.db).await?, None))
+    };
+
+    let mut order = Order::find_by_id(&payment.order, &state.db).await?;
+    if let Some(current) = &order && current.status.can_transition_to(next) {
+        match current.settle_payment(next, payment.id.as_ref().unwrap(), status, Some(note.to_string()), &state.db).await? {
+            Transition::Moved(order) => {
+                let mut payment = payment;
+                payment.status = status;
+                return Ok((payment, Some(*order)));
+            },
+            // Nothing was written, the gateway retries the webhook
+            Transition::Stale => return Err(Api(Query("Order changed while settling its payment".to_string()))),
+            // The order can't be filled, it is cancelled and the capture refunded below
+            Transition::OutOfStock => match current.transition(OrderStatus::Cancelled, None, Some("Out of stock when paid".to_string()), &state.db).await? {
+                Transition::Moved(cancelled) => order = Some(*cancelled),
+                _ => return Err(Api(Query("Order changed while settling its payment".to_string())))
+            },
+            Transition::Illegal { .. } => {}
+        }
+    }
+
+    // e.g. the refund of an order that was cancelled, nothing left to move
+    if status != PaymentStatus::Captured {
+        return Ok((payment.set_status(status, &state.db).await?, order));
+    }
+
+    // Nothing ships for this money. Until the refund goes through the capture
+    // isn't recorded, so the gateway's retries try the refund again.
+    let status = match state.payments.refund(&payment.intent_id).await {
+        Ok(PaymentStatus::Refunded) => PaymentStatus::Refunded,
+        Ok(_) => PaymentStatus::Captured, // finished by the refund's webhook
+        Err(e) => return Err(Api(Query(format!("Refund of an unwanted capture failed : {:?}", e))))
+    };
+    Ok((payment.set_status(status, &state.db).await?, order))
+}
+
+/// Gives back the captured payment of an order that was just cancelled or
+/// refunded. Orders that were never paid have nothing to refund.
+pub async fn refund_order(order: &Order, state: &AppState) -> Result<(), OrderResponse> {
+    let payment = match Payment::latest_for_order(order.id.as_ref().unwrap(), &state.db).await {
+        Ok(Some(payment)) if payment.status == PaymentStatus::Captured => payment,
+        Ok(_) => return Ok(()),
+        Err(e) => {
+            println!("{:?}", e);
+            return Err(order_error(Status::InternalServerError, "Unable to retrieve data"));
+        }
+    };
+
+    let status = match state.payments.refund(&payment.intent_id).await {
+        Ok(status) => status,
+        Err(e) => {
+            println!("Refund of {} failed : {:?}", payment.intent_id, e);
+            return Err(order_error(Status::BadGateway, "The order was updated, but its payment could not be refunded"));
+        }
+    };
+
+    // A pending refund is finished by the gateway's webhook
+    if status == PaymentStatus::Refunded && let Err(e) = payment.set_status(status, &state.db).await {
+        println!("{:?}", e);
+        return Err(order_error(Status::InternalServerError, "Unable to save payment"));
+    }
+    Ok(())
+}
+
+/// Holds the order's stock afresh while the customer pays, so a checkout
+/// that took long doesn't charge for items that were sold meanwhile.
+async fn hold_stock(order: &Order, state: &AppState) -> Result<(), OrderResponse> {
+    match StockReservation::hold(order, state.stock.hold_secs, &state.db).await {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(order_error(Status::Conflict, "Some items of this order are no longer available")),
+        Err(e) => {
+            println!("{:?}", e);
+            Err(order_error(Status::InternalServerError, "Unable to hold stock"))
+        }
+    }
+}
+
+#[post("/orders/<id>/payment")]
+pub async fn create_payment(id: &str, jwt_claims: Claims, idempotency: Idempotency, state: &State<Arc<AppState>>) -> OrderResponse {
+    idempotent(idempotency, jwt_claims.subject(), state, start_payment(id, &jwt_claims, state)).await
+}
+
+async fn start_payment(id: &str, jwt_claims: &Claims, state: &AppState) -> OrderResponse {
+    let user_id = match order_user(jwt_claims, state).await {
+        Ok(user_id) => user_id,
+        Err(e) => return e
+    };
+    let order = match owned_order(id, &user_id, state).await {
+        Ok(order) => order,
+        Err(e) => return e
+    };
+
+    if order.status != OrderStatus::PendingPayment {
+        return order_error(Status::Conflict, "Order is not awaiting payment");
+    }
+    if let Err(e) = hold_stock(&order, state).await {
+        return e;
+    }
+
+    let intent = match state.payments.create_intent(&order).await {
+        Ok(intent) => intent,
+        Err(e) => {
+            println!("Payment intent failed : {:?}", e);
+            return order_error(Status::BadGateway, "Unable to start payment");
+        }
+    };
+
+    let payment = Payment {
+        id: None,
+        order: order.id.clone().unwrap(),
+        provider: state.payments.name().to_string(),
+        intent_id: intent.id.clone(),
+        amount: order.total.clone(),
+        status: intent.status,
+        created_at: None,
+        updated_at: None
+    };
+
+    match payment.save(&state.db).await {
+        Ok(payment) => (Status::Created, Json(json!({
+            "success" : true,
+            "payment" : payment,
+            "client_secret" : intent.client_secret
+        }))),
+        Err(e) => {
+            println!("{:?}", e);
+            order_error(Status::InternalServerError, "Unable to save payment")
+        }
+    }
+}
+
+/// Settles the pending payment of `order` at the gateway. Staff use this for
+/// gateways that don't capture on their own; with the mock gateway it plays
+/// out `MOCK_PAYMENT_OUTCOME`.
+pub async fn capture_order_payment(order: Order, state: &AppState) -> OrderResponse {
+    let payment = match Payment::latest_for_order(order.id.as_ref().unwrap(), &state.db).await {
+        Ok(Some(payment)) if payment.status == PaymentStatus::Pending => payment,
+        Ok(_) => return order_error(Status::Conflict, "There is no pending payment for this order"),
+        Err(e) => {
+            println!("{:?}", e);
+            return order_error(Status::InternalServerError, "Unable to retrieve data");
+        }
+    };
+    if order.status == OrderStatus::PendingPayment && let Err(e) = hold_stock(&order, state).await {
+        return e;
+    }
+
+    let status = match state.payments.capture(&payment.intent_id).await {
+        Ok(status) => status,
+        Err(e) => {
+            println!("Payment capture failed : {:?}", e);
+            return order_error(Status::BadGateway, "Unable to capture payment");
+        }
+    };
+
+    match settle_payment(payment, status, state).await {
+        Ok((payment, moved)) => (Status::Ok, Json(json!({
+            "success" : !matches!(payment.status, PaymentStatus::Failed | PaymentStatus::Refunded),
+            "payment" : payment,
+            "order" : moved.unwrap_or(order)
+        }))),
+        Err(e) => {
+            println!("{:?}", e);
+            order_error(Status::InternalServerError, "Unable to save payment")
+        }
+    }
+}
+
+/// Signature sent by the gateway with a webhook, in the provider's header.
+pub struct WebhookSignature(pub String);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for WebhookSignature {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let Outcome::Success(state) = req.guard::<&State<Arc<AppState>>>().await else {
+            return Outcome::Error((Status::InternalServerError, ()));
+        };
+
+        match req.headers().get_one(state.payments.signature_header()) {
+            Some(signature) => Outcome::Success(WebhookSignature(signature.to_string())),
+            None => Outcome::Error((Status::BadRequest, ()))
+        }
+    }
+}
+
+#[post("/payments/webhook", data = "<payload>")]
+pub async fn payment_webhook(payload: Vec<u8>, signature: WebhookSignature, state: &State<Arc<AppState>>) -> OrderResponse {
+    let event = match state.payments.verify_webhook(&payload, &signature.0) {
+        Ok(event) => event,
+        Err(PaymentError::InvalidSignature) => return order_error(Status::BadRequest, "Invalid signature"),
+        // Gateways send many kinds of events, the ones we don't need are acknowledged
+        Err(PaymentError::UnsupportedEvent(kind)) => return (Status::Ok, Json(json!({"success" : true, "ignored" : kind }))),
+        Err(e) => {
+            println!("{:?}", e);
+            return order_error(Status::BadRequest, "Invalid webhook");
+        }
+    };
+
+    let payment = match Payment::find_by_intent(state.payments.name(), &event.intent_id, &state.db).await {
+        Ok(Some(payment)) => payment,
+        Ok(None) => return (Status::Ok, Json(json!({"success" : true, "ignored" : event.intent_id }))),
+        Err(e) => {
+            println!("{:?}", e);
+            return order_error(Status::InternalServerError, "Unable to retrieve data");
+        }
+    };
+
+    match settle_payment(payment, event.status, state).await {
+        Ok((payment, _)) => (Status::Ok, Json(json!({"success" : true, "payment" : payment }))),
+        Err(e) => {
+            // A non-2xx answer makes the gateway retry later
+            println!("{:?}", e);
+            order_error(Status::InternalServerError, "Unable to save payment")
+        }
+    }
+}
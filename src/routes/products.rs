@@ -0,0 +1,370 @@
+use std::convert::Infallible;
+use std::sync::Arc;
+
+use rocket::{delete, get, post, FromForm, Request, State};
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome};
+use rocket::serde::json::Json;
+use rocket::serde::Serialize;
+use serde_json::json;
+use crate::money::Money;
+use crate::utils::AppState;
+use crate::utils::auth::{current_user, Claims};
+use crate::database::models::*;
+use crate::database::models::exchange_rate::ExchangeRates;
+use crate::database::models::product::{FilterOp, ProductQuery, ProductSort};
+use super::index::GroupedProduct;
+
+pub const ACCEPT_CURRENCY_HEADER: &str = "Accept-Currency";
+
+/// The currencies a client asked to see prices in, from the `currency` query
+/// parameter or else the `Accept-Currency` header, most wanted first.
+pub struct DisplayCurrency {
+    requested: Option<String>,
+    accepted: Vec<String>
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for DisplayCurrency {
+    type Error = Infallible;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(DisplayCurrency {
+            requested: req.query_value::<&str>("currency").and_then(Result::ok).map(|code| code.trim().to_uppercase()),
+            accepted: req.headers().get_one(ACCEPT_CURRENCY_HEADER).map(parse_accept_currency).unwrap_or_default()
+        })
+    }
+}
+
+/// `USD, EUR;q=0.5` lists the codes by preference, dropping `q=0` ones.
+pub fn parse_accept_currency(header: &str) -> Vec<String> {
+    let mut accepted: Vec<(String, f32)> = header.split(',')
+        .filter_map(|entry| {
+            let mut parts = entry.split(';');
+            let code = parts.next()?.trim().to_uppercase();
+            let weight = parts.filter_map(|param| param.trim().strip_prefix("q="))
+                .next()
+                .map_or(Some(1.0), |q| q.trim().parse().ok())?;
+            (!code.is_empty() && weight > 0.0).then_some((code, weight))
+        })
+        .collect();
+    accepted.sort_by(|a, b| b.1.total_cmp(&a.1));
+    accepted.into_iter().map(|(code, _)| code).collect()
+}
+
+impl DisplayCurrency {
+    /// The currency to show prices in. An unsupported `currency` parameter is
+    /// an error, unsupported header entries are skipped for the next one and
+    /// in the end the store currency.
+    pub fn resolve(&self, rates: &ExchangeRates) -> Result<String, String> {
+        if let Some(code) = &self.requested {
+            return if rates.supports(code) { Ok(code.clone()) } else { Err(format!("Prices can not be shown in {}", code)) };
+        }
+
+        Ok(self.accepted.iter()
+            .find(|code| rates.supports(code))
+            .cloned()
+            .unwrap_or_else(|| rates.base().to_string()))
+    }
+}
+
+/// `product` with its price in `currency`.
+pub fn localized(mut product: Product, currency: &str, rates: &ExchangeRates) -> Product {
+    if let Some(price) = product.price_in(currency, rates) {
+        product.price = price;
+    }
+    product
+}
+
+/// Resolves the display currency, 422 for one prices can't be shown in.
+fn display_currency(display: &DisplayCurrency, state: &AppState) -> Result<String, (Status, Json<serde_json::Value>)> {
+    display.resolve(&state.rates.read().unwrap())
+        .map_err(|error| (Status::UnprocessableEntity, Json(json!({"success" : false, "error" : error }))))
+}
+
+#[get("/currencies")]
+pub fn list_currencies(state: &State<Arc<AppState>>) -> Json<serde_json::Value> {
+    let rates = state.rates.read().unwrap();
+    Json(json!({"success" : true, "base" : rates.base(), "currencies" : rates.currencies(), "rates" : rates.rates() }))
+}
+
+/// Finds the group behind a slug, which may name either a variant or a group.
+async fn find_group(slug: &str, state: &AppState) -> Result<Option<(ProductGroup, Option<Product>)>, surrealdb::Error> {
+    if let Some(variant) = Product::find_by_slug(slug, &state.db).await? {
+        let group: Option<ProductGroup> = match variant.product_group.clone() {
+            Some(id) => state.db.select(id).await?,
+            None => None
+        };
+        return Ok(group.map(|group| (group, Some(variant))));
+    }
+
+    Ok(ProductGroup::find_by_slug(slug, &state.db).await?
+        .map(|group| (group, None)))
+}
+
+#[get("/products/<slug>")]
+pub async fn get_product(slug: &str, display: DisplayCurrency, state: &State<Arc<AppState>>) -> (Status, Json<serde_json::Value>) {
+    let currency = match display_currency(&display, state) {
+        Ok(currency) => currency,
+        Err(e) => return e
+    };
+
+    let (group, selected) = match find_group(slug, state).await {
+        Ok(Some(found)) => found,
+        Ok(None) => return (Status::NotFound, Json(json!({"success" : false, "error" : "Product not found" }))),
+        Err(e) => {
+            println!("{:?}", e);
+            return (Status::InternalServerError, Json(json!({"success" : false, "error" : "Unable to retrieve data" })));
+        }
+    };
+
+    // Out of stock variants are listed too, so the page can show them as sold out
+    let variants = match group.variants(&state.db).await {
+        Ok(variants) if !variants.is_empty() => variants,
+        Ok(_) => return (Status::NotFound, Json(json!({"success" : false, "error" : "Product not found" }))),
+        Err(e) => {
+            println!("{:?}", e);
+            return (Status::InternalServerError, Json(json!({"success" : false, "error" : "Unable to retrieve data" })));
+        }
+    };
+
+    let variants = {
+        let rates = state.rates.read().unwrap();
+        variants.into_iter().map(|variant| localized(variant, &currency, &rates)).collect()
+    };
+    let product = GroupedProduct::from_variants(&group, variants);
+    let selected = selected.map(|variant| variant.slug).unwrap_or_else(|| product.slug.clone());
+
+    (Status::Ok, Json(json!({"success" : true, "product" : product, "selected" : selected, "currency" : currency })))
+}
+
+const DEFAULT_PAGE_SIZE: u32 = 24;
+const MAX_PAGE_SIZE: u32 = 100;
+const MAX_OFFSET: u32 = 10_000;
+
+#[derive(Debug, FromForm)]
+pub struct ProductListParams {
+    pub category: Option<String>,
+    pub color: Option<String>,
+    pub size: Option<String>,
+    pub min_price: Option<String>,  // decimal, in the store currency whatever the display currency
+    pub max_price: Option<String>,
+    pub in_stock: Option<bool>,
+    pub sort: Option<String>,
+    pub offset: Option<u32>,
+    pub limit: Option<u32>
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProductListItem {
+    pub sku: String,
+    pub slug: String,
+    pub title: String,
+    pub img: String,
+    pub category: String,
+    pub color: String,
+    pub size: String,
+    pub price: Money,
+    pub available_qty: u32
+}
+
+impl From<Product> for ProductListItem {
+    fn from(product: Product) -> Self {
+        ProductListItem {
+            sku: product.sku,
+            slug: product.slug,
+            title: product.title,
+            img: product.img,
+            category: product.category,
+            color: product.color,
+            size: product.size,
+            price: product.price,
+            available_qty: product.available_qty
+        }
+    }
+}
+
+fn parse_sort(sort: &str) -> Option<ProductSort> {
+    match sort {
+        "price" | "price_asc" => Some(ProductSort::PriceAsc),
+        "price_desc" => Some(ProductSort::PriceDesc),
+        "newest" => Some(ProductSort::Newest),
+        "title" => Some(ProductSort::Title),
+        _ => None
+    }
+}
+
+#[get("/products?<params..>")]
+pub async fn list_products(params: ProductListParams, display: DisplayCurrency, state: &State<Arc<AppState>>) -> (Status, Json<serde_json::Value>) {
+    let currency = match display_currency(&display, state) {
+        Ok(currency) => currency,
+        Err(e) => return e
+    };
+
+    let mut query = ProductQuery::new();
+
+    if let Some(category) = params.category {
+        query = query.filter("category", FilterOp::Eq, category);
+    }
+    if let Some(color) = params.color {
+        query = query.filter("color", FilterOp::Eq, color);
+    }
+    if let Some(size) = params.size {
+        query = query.filter("size", FilterOp::Eq, size);
+    }
+    for (bound, op) in [(&params.min_price, FilterOp::Gte), (&params.max_price, FilterOp::Lte)] {
+        let Some(bound) = bound else { continue };
+        match Money::parse(bound, &state.currency) {
+            Some(price) => query = query.filter("price.amount", op, price.amount),
+            None => return (Status::UnprocessableEntity, Json(json!({"success" : false, "error" : "min_price and max_price must be decimal amounts" })))
+        }
+    }
+    if params.in_stock.unwrap_or(false) {
+        query = query.filter("available_qty", FilterOp::Gt, 0);
+    }
+
+    match params.sort.as_deref().map(parse_sort) {
+        Some(Some(sort)) => query = query.sort(sort),
+        Some(None) => return (Status::UnprocessableEntity, Json(json!({"success" : false, "error" : "sort must be one of price, price_desc, newest or title" }))),
+        None => {}
+    }
+
+    let offset = params.offset.unwrap_or(0).min(MAX_OFFSET);
+    let limit = params.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+
+    // One extra row tells whether there is a next page
+    let mut products = match Product::find(&state.db, query.page(offset, limit + 1)).await {
+        Ok(products) => products,
+        Err(e) => {
+            println!("{:?}", e);
+            return (Status::InternalServerError, Json(json!({"success" : false, "error" : "Unable to retrieve data" })));
+        }
+    };
+
+    let has_more = products.len() > limit as usize;
+    products.truncate(limit as usize);
+
+    let products: Vec<ProductListItem> = {
+        let rates = state.rates.read().unwrap();
+        products.into_iter().map(|product| ProductListItem::from(localized(product, &currency, &rates))).collect()
+    };
+    let next_offset = if has_more { Some(offset.saturating_add(limit)) } else { None };
+
+    (Status::Ok, Json(json!({
+        "success" : true,
+        "products" : products,
+        "currency" : currency,
+        "pagination" : { "offset" : offset, "limit" : limit, "next_offset" : next_offset }
+    })))
+}
+
+#[get("/search?<q>&<offset>&<limit>")]
+pub async fn search_products(q: &str, offset: Option<u32>, limit: Option<u32>, display: DisplayCurrency, state: &State<Arc<AppState>>) -> (Status, Json<serde_json::Value>) {
+    let currency = match display_currency(&display, state) {
+        Ok(currency) => currency,
+        Err(e) => return e
+    };
+
+    let terms = q.trim();
+    if terms.is_empty() {
+        return (Status::UnprocessableEntity, Json(json!({"success" : false, "error" : "Search query must not be empty" })));
+    }
+
+    let offset = offset.unwrap_or(0).min(MAX_OFFSET);
+    let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+
+    match Product::search(&state.db, terms, offset, limit).await {
+        Ok(mut results) => {
+            let rates = state.rates.read().unwrap();
+            for hit in results.hits.iter_mut() {
+                if let Some(price) = rates.price(&hit.price, &hit.price_overrides, &currency) {
+                    hit.price = price;
+                }
+            }
+
+            (Status::Ok, Json(json!({
+                "success" : true,
+                "query" : terms,
+                "currency" : currency,
+                "results" : results.hits,
+                "facets" : results.facets,
+                "pagination" : { "offset" : offset, "limit" : limit }
+            })))
+        },
+        Err(e) => {
+            println!("{:?}", e);
+            (Status::InternalServerError, Json(json!({"success" : false, "error" : "Unable to search products" })))
+        }
+    }
+}
+
+/// The signed in user and the variant behind `slug`, for the notify me routes.
+async fn subscriber_and_variant(slug: &str, jwt_claims: &Claims, state: &AppState) -> Result<(User, Product), (Status, Json<serde_json::Value>)> {
+    let user = match current_user(jwt_claims, &state.db).await {
+        Ok(user) => user,
+        Err(_) => return Err((Status::Unauthorized, Json(json!({"success" : false, "error" : "User not found" }))))
+    };
+
+    match Product::find_by_slug(slug, &state.db).await {
+        Ok(Some(product)) => Ok((user, product)),
+        Ok(None) => Err((Status::NotFound, Json(json!({"success" : false, "error" : "Product not found" })))),
+        Err(e) => {
+            println!("{:?}", e);
+            Err((Status::InternalServerError, Json(json!({"success" : false, "error" : "Unable to retrieve data" }))))
+        }
+    }
+}
+
+/// Asks to be told when a sold out variant is back in stock.
+#[post("/products/<slug>/notify-me")]
+pub async fn subscribe_to_restock(slug: &str, jwt_claims: Claims, state: &State<Arc<AppState>>) -> (Status, Json<serde_json::Value>) {
+    let (user, product) = match subscriber_and_variant(slug, &jwt_claims, state).await {
+        Ok(found) => found,
+        Err(e) => return e
+    };
+    let (user_id, product_id) = (user.id.unwrap(), product.id.unwrap());
+
+    match StockSubscription::find(&product_id, &user_id, &state.db).await {
+        Ok(Some(subscription)) => return (Status::Ok, Json(json!({"success" : true, "subscription" : subscription }))),
+        Ok(None) => {},
+        Err(e) => {
+            println!("{:?}", e);
+            return (Status::InternalServerError, Json(json!({"success" : false, "error" : "Unable to retrieve data" })));
+        }
+    }
+    if product.available_qty > 0 {
+        return (Status::Conflict, Json(json!({"success" : false, "error" : "Product is in stock" })));
+    }
+
+    let subscription = StockSubscription {
+        id: None,
+        product: product_id,
+        user: user_id,
+        email: user.email,
+        created_at: None
+    };
+    match subscription.save(&state.db).await {
+        Ok(subscription) => (Status::Created, Json(json!({"success" : true, "subscription" : subscription }))),
+        Err(e) => {
+            println!("{:?}", e);
+            (Status::InternalServerError, Json(json!({"success" : false, "error" : "Unable to save subscription" })))
+        }
+    }
+}
+
+#[delete("/products/<slug>/notify-me")]
+pub async fn unsubscribe_from_restock(slug: &str, jwt_claims: Claims, state: &State<Arc<AppState>>) -> (Status, Json<serde_json::Value>) {
+    let (user, product) = match subscriber_and_variant(slug, &jwt_claims, state).await {
+        Ok(found) => found,
+        Err(e) => return e
+    };
+
+    match StockSubscription::cancel(product.id.as_ref().unwrap(), user.id.as_ref().unwrap(), &state.db).await {
+        Ok(true) => (Status::Ok, Json(json!({"success" : true, "message" : "You will not be notified" }))),
+        Ok(false) => (Status::NotFound, Json(json!({"success" : false, "error" : "Not subscribed to this product" }))),
+        Err(e) => {
+            println!("{:?}", e);
+            (Status::InternalServerError, Json(json!({"success" : false, "error" : "Unable to cancel subscription" })))
+        }
+    }
+}
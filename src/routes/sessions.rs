@@ -0,0 +1,131 @@
+use std::sync::Arc;
+
+use rocket::{delete, get, post, State};
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::serde::Deserialize;
+use serde_json::json;
+use crate::utils::AppState;
+use crate::database::models::session_token::SessionToken;
+use crate::utils::auth::{current_user, refresh_session, Claims, ClientInfo, RefreshOutcome};
+
+#[derive(Debug, Deserialize)]
+pub struct RefreshRequest {
+    pub refresh_token: String
+}
+
+#[post("/token/refresh", format = "application/json", data = "<request>")]
+pub async fn refresh_token(request: Json<RefreshRequest>, client: ClientInfo, state: &State<Arc<AppState>>) -> (Status, Json<serde_json::Value>) {
+    match refresh_session(&request.refresh_token, &client, state).await {
+        Ok(RefreshOutcome::Refreshed(tokens)) => (Status::Ok, Json(json!({
+            "success" : true,
+            "token" : tokens.access_token,
+            "refresh_token" : tokens.refresh_token,
+            "expires_in" : tokens.expires_in
+        }))),
+        Ok(RefreshOutcome::Expired) => (Status::Unauthorized, Json(json!({"success" : false, "error" : "Refresh token expired" }))),
+        Ok(RefreshOutcome::Reused) => (Status::Unauthorized, Json(json!({"success" : false, "error" : "Refresh token reused, please login again" }))),
+        Ok(RefreshOutcome::Invalid) => (Status::Unauthorized, Json(json!({"success" : false, "error" : "Invalid refresh token" }))),
+        Err(e) => {
+            println!("{:?}", e);
+            (Status::InternalServerError, Json(json!({"success" : false, "error" : "Unable to refresh token" })))
+        }
+    }
+}
+
+#[post("/logout")]
+pub async fn logout(jwt_claims: Claims, state: &State<Arc<AppState>>) -> Json<serde_json::Value> {
+    let user = match current_user(&jwt_claims, &state.db).await {
+        Ok(user) => user,
+        Err(_) => return Json(json!({"success" : false, "error" : "Unable to retrieve data" }))
+    };
+
+    let jti = surrealdb::sql::Uuid::from(jwt_claims.jti);
+    match SessionToken::revoke(jti, &user.id.unwrap(), &state.db).await {
+        Ok(revoked) => {
+            for session in &revoked {
+                state.session_cache.invalidate(&session.jti.0);
+            }
+            Json(json!({"success" : true, "message" : "Logged out" }))
+        },
+        Err(e) => {
+            println!("{:?}", e);
+            Json(json!({"success" : false, "error" : "Unable to logout" }))
+        }
+    }
+}
+
+#[post("/logout-all")]
+pub async fn logout_all(jwt_claims: Claims, state: &State<Arc<AppState>>) -> Json<serde_json::Value> {
+    let user = match current_user(&jwt_claims, &state.db).await {
+        Ok(user) => user,
+        Err(_) => return Json(json!({"success" : false, "error" : "Unable to retrieve data" }))
+    };
+
+    match SessionToken::revoke_all_for_user(&user.id.unwrap(), &state.db).await {
+        Ok(revoked) => {
+            for session in &revoked {
+                state.session_cache.invalidate(&session.jti.0);
+            }
+            Json(json!({"success" : true, "message" : "Logged out everywhere", "revoked" : revoked.len() }))
+        },
+        Err(e) => {
+            println!("{:?}", e);
+            Json(json!({"success" : false, "error" : "Unable to logout" }))
+        }
+    }
+}
+
+#[get("/sessions")]
+pub async fn list_sessions(jwt_claims: Claims, state: &State<Arc<AppState>>) -> Json<serde_json::Value> {
+    let user = match current_user(&jwt_claims, &state.db).await {
+        Ok(user) => user,
+        Err(_) => return Json(json!({"success" : false, "error" : "Unable to retrieve data" }))
+    };
+
+    match SessionToken::find_active_for_user(&user.id.unwrap(), &state.db).await {
+        Ok(sessions) => {
+            let sessions: Vec<serde_json::Value> = sessions.iter()
+                .map(|session| json!({
+                    "id" : session.jti.0,
+                    "issued_at" : session.issued_at.0.to_rfc3339(),
+                    "expires_at" : session.refresh_expires_at.as_ref().unwrap_or(&session.expires_at).0.to_rfc3339(),
+                    "user_agent" : session.user_agent,
+                    "ip" : session.ip,
+                    "current" : session.jti.0 == jwt_claims.jti
+                }))
+                .collect();
+            Json(json!({"success" : true, "sessions" : sessions }))
+        },
+        Err(e) => {
+            println!("{:?}", e);
+            Json(json!({"success" : false, "error" : "Unable to retrieve data" }))
+        }
+    }
+}
+
+#[delete("/sessions/<id>")]
+pub async fn revoke_session(id: &str, jwt_claims: Claims, state: &State<Arc<AppState>>) -> Json<serde_json::Value> {
+    let Ok(jti) = uuid::Uuid::parse_str(id) else {
+        return Json(json!({"success" : false, "error" : "Invalid session id" }));
+    };
+
+    let user = match current_user(&jwt_claims, &state.db).await {
+        Ok(user) => user,
+        Err(_) => return Json(json!({"success" : false, "error" : "Unable to retrieve data" }))
+    };
+
+    match SessionToken::revoke(surrealdb::sql::Uuid::from(jti), &user.id.unwrap(), &state.db).await {
+        Ok(revoked) if revoked.is_empty() => Json(json!({"success" : false, "error" : "Session not found" })),
+        Ok(revoked) => {
+            for session in &revoked {
+                state.session_cache.invalidate(&session.jti.0);
+            }
+            Json(json!({"success" : true, "message" : "Session revoked" }))
+        },
+        Err(e) => {
+            println!("{:?}", e);
+            Json(json!({"success" : false, "error" : "Unable to revoke session" }))
+        }
+    }
+}
@@ -1,37 +1,74 @@
 use std::path::Path;
+use std::sync::RwLock;
 
-use auth::{generate_ed_dsa_keypair, get_pkcs8_der};
+use auth::load_key_ring;
 use surrealdb::engine::remote::ws::Client;
 use surrealdb::Surreal;
 
 use std::env;
 
 use crate::database::db::Credentials;
+use crate::database::models::exchange_rate::ExchangeRates;
+use crate::database::models::warehouse::AllocationStrategy;
+use crate::money::is_valid_currency;
+use crate::payments::{self, PaymentConfig, PaymentProvider, ProviderConfig};
 
 pub struct AppState {
     pub db: Surreal<Client>,
-    pub jwt_key_pair : auth::JwtKeyPair
+    pub jwt_keys : auth::JwtKeyRing,
+    pub session_cache : auth::SessionCache,
+    pub token_lifetimes : auth::TokenLifetimes,
+    pub guest_cart_secs : usize,  // how long an untouched guest cart is kept
+    pub payments : Box<dyn PaymentProvider>,
+    pub currency : String,  // prices are kept and charged in this currency
+    pub rates : RwLock<ExchangeRates>,  // for showing prices in other currencies
+    pub stock : StockConfig
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct StockConfig {
+    pub hold_secs : usize,              // how long checkout holds stock for an unpaid order
+    pub allocation : AllocationStrategy // how orders are spread over warehouses
+}
+
+#[derive(Debug, Clone)]
+pub struct JwtKeyConfig {
+    pub key_dir : String,               // holds one `<kid>.der` file per signing key
+    pub active_kid : Option<String>,    // key signing new tokens
+    pub legacy_key_path : String        // the single key used before rotation
 }
 
 impl AppState {
-    pub fn new(db: Surreal<Client>, jwt_key_path: &str) -> AppState {
+    pub fn new(db: Surreal<Client>, jwt_keys: &JwtKeyConfig, token_lifetimes: auth::TokenLifetimes, guest_cart_secs: usize, payment_config: &PaymentConfig, rates: ExchangeRates, stock: StockConfig) -> AppState {
         AppState{
             db,
-            jwt_key_pair : init_jwt_keys(jwt_key_path)
+            jwt_keys : init_jwt_keys(jwt_keys),
+            session_cache : auth::SessionCache::new(auth::SESSION_CACHE_TTL),
+            token_lifetimes,
+            guest_cart_secs,
+            payments : payments::provider_from_config(payment_config),
+            currency : payment_config.currency.clone(),
+            rates : RwLock::new(rates),
+            stock
         }
     }
 }
 
-fn init_jwt_keys(jwt_key_path: &str) -> auth::JwtKeyPair {
-    let key_path = Path::new(jwt_key_path);
-    let pkcs8_der = get_pkcs8_der(key_path);
-    generate_ed_dsa_keypair(&pkcs8_der)
+fn init_jwt_keys(jwt_keys: &JwtKeyConfig) -> auth::JwtKeyRing {
+    let key_dir = Path::new(&jwt_keys.key_dir);
+    let legacy_key_path = Path::new(&jwt_keys.legacy_key_path);
+    load_key_ring(key_dir, jwt_keys.active_kid.as_deref(), Some(legacy_key_path))
 }
 
 pub struct AppConfig{
     pub surreal_hostname : String,
     pub credentials : Credentials,
-    pub jwt_key_path : String
+    pub jwt_keys : JwtKeyConfig,
+    pub token_lifetimes : auth::TokenLifetimes,
+    pub guest_cart_secs : usize,
+    pub payment_config : PaymentConfig,
+    pub exchange_rates_file : Option<String>,
+    pub stock : StockConfig
 }
 
 pub fn extract_app_config_from_env() -> Result<AppConfig, String> {
@@ -48,7 +85,22 @@ pub fn extract_app_config_from_env() -> Result<AppConfig, String> {
         database
     };
 
-    let jwt_key_path = match env::var("JWT_KEY_PATH") {
+    let jwt_key_dir = match env::var("JWT_KEY_DIR") {
+        Ok(val) => val,
+        Err(_) => {
+            #[cfg(debug_assertions)]
+            {
+                "./security/jwt_keys".to_string()
+            }
+            #[cfg(not(debug_assertions))]
+            {
+                "/etc/hackerwear-backend/jwt_keys".to_string()
+            }
+        }
+    };
+
+    // Key from before rotation, still loaded so the tokens it signed stay valid
+    let jwt_legacy_key_path = match env::var("JWT_KEY_PATH") {
         Ok(val) => val,
         Err(_) => {
             #[cfg(debug_assertions)]
@@ -62,28 +114,145 @@ pub fn extract_app_config_from_env() -> Result<AppConfig, String> {
         }
     };
 
+    let jwt_keys = JwtKeyConfig {
+        key_dir: jwt_key_dir,
+        active_kid: env::var("JWT_ACTIVE_KID").ok(),
+        legacy_key_path: jwt_legacy_key_path
+    };
+
+    let token_lifetimes = auth::TokenLifetimes {
+        access_secs: parse_env_or("ACCESS_TOKEN_TTL_SECS", 15 * 60)?,       // 15 minutes
+        refresh_secs: parse_env_or("REFRESH_TOKEN_TTL_SECS", 30 * 24 * 60 * 60)? // 30 days
+    };
+    let guest_cart_secs = parse_env_or("GUEST_CART_TTL_SECS", 14 * 24 * 60 * 60)?;  // 14 days
+
+    let payment_config = extract_payment_config_from_env()?;
+    let exchange_rates_file = env::var("EXCHANGE_RATES_FILE").ok();
+    let stock = StockConfig {
+        hold_secs: parse_env_or("STOCK_HOLD_SECS", 15 * 60)?,  // 15 minutes
+        allocation: parse_env_or("ALLOCATION_STRATEGY", AllocationStrategy::Nearest)?
+    };
 
-    Ok(AppConfig { surreal_hostname: hostname, credentials: cred, jwt_key_path })
+    Ok(AppConfig { surreal_hostname: hostname, credentials: cred, jwt_keys, token_lifetimes, guest_cart_secs, payment_config, exchange_rates_file, stock })
+}
+
+/// Debug builds fall back to the mock gateway and a fixed webhook secret, so
+/// the app runs offline. Release builds have to name both.
+fn extract_payment_config_from_env() -> Result<PaymentConfig, String> {
+    let provider_name = match env::var("PAYMENT_PROVIDER") {
+        Ok(val) => val,
+        Err(_) => {
+            #[cfg(debug_assertions)]
+            {
+                "mock".to_string()
+            }
+            #[cfg(not(debug_assertions))]
+            {
+                return Err("Missing PAYMENT_PROVIDER".to_string());
+            }
+        }
+    };
+    let provider = match provider_name.as_str() {
+        "mock" => ProviderConfig::Mock(parse_env_or("MOCK_PAYMENT_OUTCOME", payments::mock::MockOutcome::Succeed)?),
+        other => return Err(format!("Unknown PAYMENT_PROVIDER {}", other))
+    };
+
+    let webhook_secret = match env::var("PAYMENT_WEBHOOK_SECRET") {
+        Ok(val) => val,
+        Err(_) => {
+            #[cfg(debug_assertions)]
+            {
+                "mock-webhook-secret".to_string()
+            }
+            #[cfg(not(debug_assertions))]
+            {
+                return Err("Missing PAYMENT_WEBHOOK_SECRET".to_string());
+            }
+        }
+    };
+
+    let currency = env::var("PAYMENT_CURRENCY").unwrap_or_else(|_| "INR".to_string());
+    if !is_valid_currency(&currency) {
+        return Err(format!("Invalid PAYMENT_CURRENCY {}", currency));
+    }
+
+    Ok(PaymentConfig { provider, webhook_secret, currency })
+}
+
+fn parse_env_or<T: std::str::FromStr>(key: &str, default: T) -> Result<T, String> {
+    match env::var(key) {
+        Ok(val) => val.parse().map_err(|_| format!("Invalid {}", key)),
+        Err(_) => Ok(default)
+    }
 }
 
 
 pub mod auth {
+    use std::collections::{ BTreeMap, HashMap };
+    use base64::Engine;
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
     use std::fs;
     use std::path::Path;
-    use std::time::{ SystemTime, UNIX_EPOCH };
+    use std::sync::Mutex;
+    use std::time::{ Duration, Instant, SystemTime, UNIX_EPOCH };
+    use chrono::Utc;
+    use ring::digest;
+    use ring::rand::{ SecureRandom, SystemRandom };
     use ring::signature::{ Ed25519KeyPair, KeyPair };
-    use jsonwebtoken::{ decode, encode, Algorithm, DecodingKey, EncodingKey, Validation };
+    use jsonwebtoken::{ decode, decode_header, encode, Algorithm, DecodingKey, EncodingKey, Validation };
     use serde::{ Deserialize, Serialize };
+    use serde_json::json;
     use surrealdb::engine::remote::ws::Client;
     use surrealdb::Surreal;
     use uuid::Uuid;
     
-    use crate::database::models::DatabaseIO;
+    use crate::database::models::{role, DatabaseIO, Role, User};
     use crate::database::models::session_token::SessionToken;
 
     pub struct JwtKeyPair {
         pub encoding_key: EncodingKey,
-        pub decoding_key: DecodingKey
+        pub decoding_key: DecodingKey,
+        pub public_key: Vec<u8>
+    }
+
+    /// Kid of the single key tokens were signed with before key rotation.
+    pub const LEGACY_KID: &str = "legacy";
+
+    /// Every key a token may have been signed with, by `kid`. Only the active
+    /// key signs new tokens, the others stay around so that tokens signed
+    /// before a rotation keep verifying until they expire.
+    pub struct JwtKeyRing {
+        active_kid: String,
+        keys: BTreeMap<String, JwtKeyPair>
+    }
+
+    impl JwtKeyRing {
+        pub fn active_kid(&self) -> &str {
+            &self.active_kid
+        }
+
+        pub fn active(&self) -> &JwtKeyPair {
+            &self.keys[&self.active_kid]
+        }
+
+        pub fn get(&self, kid: &str) -> Option<&JwtKeyPair> {
+            self.keys.get(kid)
+        }
+
+        /// Public half of every key as a JWK Set, for other services to verify our tokens.
+        pub fn jwks(&self) -> serde_json::Value {
+            let keys: Vec<serde_json::Value> = self.keys.iter()
+                .map(|(kid, keypair)| json!({
+                    "kty" : "OKP",
+                    "crv" : "Ed25519",
+                    "alg" : "EdDSA",
+                    "use" : "sig",
+                    "kid" : kid,
+                    "x" : URL_SAFE_NO_PAD.encode(&keypair.public_key)
+                }))
+                .collect();
+            json!({ "keys" : keys })
+        }
     }
 
     #[derive(Debug, Serialize, Deserialize)]
@@ -94,7 +263,56 @@ pub mod auth {
         exp: usize,  // expiry
         iat: usize,  // issued at
         // Make this private
-        pub jti: Uuid    // uuid of token
+        pub jti: Uuid,   // uuid of token
+        #[serde(default)]
+        roles: Vec<String>, // roles of the user at issue time
+        #[serde(default)]
+        perms: Vec<String>  // permissions granted by those roles
+    }
+
+    impl Claims {
+        pub fn subject(&self) -> &str {
+            &self.sub
+        }
+
+        pub fn roles(&self) -> &[String] {
+            &self.roles
+        }
+
+        pub fn has_role(&self, role: &str) -> bool {
+            self.roles.iter().any(|r| r == role)
+        }
+
+        pub fn has_permission(&self, permission: &str) -> bool {
+            self.perms.iter().any(|p| p == permission || p == role::permissions::ALL)
+        }
+    }
+
+    #[derive(Debug, Clone, Copy)]
+    pub struct TokenLifetimes {
+        pub access_secs: usize,
+        pub refresh_secs: usize
+    }
+
+    #[derive(Debug, Serialize)]
+    pub struct TokenPair {
+        pub access_token: String,
+        pub refresh_token: String,
+        pub expires_in: usize
+    }
+
+    pub enum RefreshOutcome {
+        Refreshed(TokenPair),
+        Invalid,
+        Expired,
+        Reused
+    }
+
+    /// Details of the client a session was issued to.
+    #[derive(Debug, Clone, Default)]
+    pub struct ClientInfo {
+        pub user_agent: Option<String>,
+        pub ip: Option<String>
     }
 
     pub enum JwtStatus{
@@ -103,10 +321,19 @@ pub mod auth {
         Invalid
     }
 
-    pub async fn generate_jwt(user: &super::super::database::models::User, keypair : &JwtKeyPair, db: &Surreal<Client>) -> String {
+    /// Issues a short-lived access token together with an opaque refresh token.
+    /// `family` is carried over on refresh so that a replayed refresh token can
+    /// take down every session that descends from the same login.
+    pub async fn generate_jwt(user: &User, client: &ClientInfo, family: Option<Uuid>, keys : &JwtKeyRing, lifetimes: &TokenLifetimes, db: &Surreal<Client>) -> TokenPair {
         let iat = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as usize;
-        let exp = iat + 10 * 24 * 60 * 60; // 10 days of validity
+        let exp = iat + lifetimes.access_secs;
         let jti = Uuid::new_v4();
+        let family = family.unwrap_or_else(Uuid::new_v4);
+        let refresh_token = generate_opaque_token();
+
+        let roles = user.role_names();
+        let perms = Role::permissions_for(&roles, db).await
+            .expect("Unable to load role permissions");
 
         let claims = Claims {
             iss : "hackerwear-api-server".to_string(),
@@ -114,20 +341,90 @@ pub mod auth {
             aud : "hackerwear-web".to_string(),
             iat,
             exp,
-            jti : jti.clone(),
+            jti,
+            roles,
+            perms
         };
 
-        let session_token = SessionToken::new(surrealdb::sql::Uuid::from(jti), user, iat, exp);
+        let session_token = SessionToken::new(surrealdb::sql::Uuid::from(jti), user, client, iat, exp)
+            .with_refresh(surrealdb::sql::Uuid::from(family), hash_opaque_token(&refresh_token), iat + lifetimes.refresh_secs);
         session_token.save(db).await
             .expect("Unable to save session token");
 
-        let token = encode(&jsonwebtoken::Header::new(Algorithm::EdDSA), &claims, &(keypair.encoding_key))
+        let mut header = jsonwebtoken::Header::new(Algorithm::EdDSA);
+        header.kid = Some(keys.active_kid().to_string());
+
+        let access_token = encode(&header, &claims, &(keys.active().encoding_key))
             .unwrap();
 
-        token
+        TokenPair { access_token, refresh_token, expires_in: lifetimes.access_secs }
     }
 
-    pub fn validate_jwt(token : &str, keypair : &JwtKeyPair) -> JwtStatus {
+    /// Exchanges a refresh token for a new token pair, rotating it out.
+    /// Presenting a refresh token that was already rotated revokes the whole family.
+    pub async fn refresh_session(refresh_token: &str, client: &ClientInfo, state: &super::AppState) -> Result<RefreshOutcome, surrealdb::Error> {
+        let session = match SessionToken::find_by_refresh_hash(&hash_opaque_token(refresh_token), &state.db).await? {
+            Some(session) => session,
+            None => return Ok(RefreshOutcome::Invalid)
+        };
+
+        if session.revoked {
+            return Ok(RefreshOutcome::Invalid);
+        }
+
+        if session.refresh_expires_at.as_ref().is_none_or(|expires_at| expires_at.0 <= Utc::now()) {
+            return Ok(RefreshOutcome::Expired);
+        }
+
+        // Losing the race to rotate counts as reuse too, two clients hold the same token
+        if session.rotated || !session.mark_rotated(&state.db).await? {
+            if let Some(family) = session.family {
+                for revoked in SessionToken::revoke_family(family, &state.db).await? {
+                    state.session_cache.invalidate(&revoked.jti.0);
+                }
+            }
+            return Ok(RefreshOutcome::Reused);
+        }
+
+        let user: Option<User> = state.db.select(session.user.clone()).await?;
+        let Some(user) = user else {
+            return Ok(RefreshOutcome::Invalid);
+        };
+
+        let family = session.family.map(|family| family.0);
+        let tokens = generate_jwt(&user, client, family, &state.jwt_keys, &state.token_lifetimes, &state.db).await;
+        Ok(RefreshOutcome::Refreshed(tokens))
+    }
+
+    /// Random token for refresh tokens and guest carts.
+    pub fn generate_opaque_token() -> String {
+        let mut bytes = [0u8; 32];
+        SystemRandom::new().fill(&mut bytes)
+            .expect("Failed to generate opaque token");
+        to_hex(&bytes)
+    }
+
+    /// Only the hash of an opaque token is stored, so a database leak does not
+    /// hand out working refresh tokens or guest carts.
+    pub fn hash_opaque_token(token: &str) -> String {
+        to_hex(digest::digest(&digest::SHA256, token.as_bytes()).as_ref())
+    }
+
+    fn to_hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    pub fn validate_jwt(token : &str, keys : &JwtKeyRing) -> JwtStatus {
+        // Tokens from before key rotation carry no kid, they were signed by the legacy key
+        let keypair = match decode_header(token).map(|header| header.kid) {
+            Ok(Some(kid)) => match keys.get(&kid) {
+                Some(keypair) => keypair,
+                None => return JwtStatus::Invalid
+            },
+            Ok(None) => keys.get(LEGACY_KID).unwrap_or(keys.active()),
+            Err(_) => return JwtStatus::Invalid
+        };
+
         let mut validation = Validation::new(Algorithm::EdDSA);
         validation.set_audience(&["hackerwear-web"]);
         match decode::<Claims>(token, &(keypair.decoding_key), &validation) { 
@@ -148,24 +445,97 @@ pub mod auth {
         }
     }
 
+    /// Resolves the `User` a token was issued to.
+    pub async fn current_user(claims: &Claims, db: &Surreal<Client>) -> Result<User, surrealdb::Error> {
+        User::find_by_email(claims.subject(), db).await
+    }
+
+    /// How long the result of a session lookup is trusted before the
+    /// `sessiontoken` table is consulted again.
+    pub const SESSION_CACHE_TTL: Duration = Duration::from_secs(60);
+
+    // Upper bound on cached jtis before stale entries get swept out
+    const SESSION_CACHE_CAPACITY: usize = 10_000;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum SessionStatus {
+        Active,
+        Revoked,
+        Expired,
+        Missing
+    }
+
+    struct CachedSession {
+        status: SessionStatus,
+        checked_at: Instant
+    }
+
+    /// In-process cache of recently checked jtis, so that every authenticated
+    /// request does not cost a database round trip.
+    pub struct SessionCache {
+        ttl: Duration,
+        entries: Mutex<HashMap<Uuid, CachedSession>>
+    }
+
+    impl SessionCache {
+        pub fn new(ttl: Duration) -> Self {
+            SessionCache { ttl, entries: Mutex::new(HashMap::new()) }
+        }
+
+        pub fn get(&self, jti: &Uuid) -> Option<SessionStatus> {
+            let entries = self.entries.lock().unwrap();
+            entries.get(jti)
+                .filter(|entry| entry.checked_at.elapsed() < self.ttl)
+                .map(|entry| entry.status)
+        }
+
+        pub fn insert(&self, jti: Uuid, status: SessionStatus) {
+            let mut entries = self.entries.lock().unwrap();
+            if entries.len() >= SESSION_CACHE_CAPACITY {
+                let ttl = self.ttl;
+                entries.retain(|_, entry| entry.checked_at.elapsed() < ttl);
+            }
+            entries.insert(jti, CachedSession { status, checked_at: Instant::now() });
+        }
+
+        pub fn invalidate(&self, jti: &Uuid) {
+            self.entries.lock().unwrap().remove(jti);
+        }
+    }
+
+    /// Looks up the session behind a jti, going to the database only when the
+    /// cache has no fresh answer.
+    pub async fn check_session(jti: Uuid, cache: &SessionCache, db: &Surreal<Client>) -> Result<SessionStatus, surrealdb::Error> {
+        if let Some(status) = cache.get(&jti) {
+            return Ok(status);
+        }
+
+        let status = match SessionToken::find_by_jti(surrealdb::sql::Uuid::from(jti), db).await? {
+            None => SessionStatus::Missing,
+            Some(session) if session.revoked => SessionStatus::Revoked,
+            Some(session) if session.expires_at.0 <= Utc::now() => SessionStatus::Expired,
+            Some(_) => SessionStatus::Active
+        };
+
+        cache.insert(jti, status);
+        Ok(status)
+    }
+
     pub fn get_pkcs8_der(key_path: &Path) -> Vec<u8> {
 
         if key_path.exists() {
             // Load existing key
-            let der_bytes = fs::read(key_path)
-                .expect("Failed to read existing JWT private key file");
-
-            der_bytes
+            fs::read(key_path)
+                .expect("Failed to read existing JWT private key file")
         }
         else {
             let rng = ring::rand::SystemRandom::new();
             let pkcs8 = Ed25519KeyPair::generate_pkcs8(&rng)
                 .expect("Failed to generate Ed25519 keypair");
 
-            if let Some(parent) = key_path.parent() {
-                if !parent.exists() {
-                    fs::create_dir_all(parent).expect("Failed to create directory for key");
-                }
+            if let Some(parent) = key_path.parent()
+                && !parent.exists() {
+                fs::create_dir_all(parent).expect("Failed to create directory for key");
             }
 
             // Save to disk
@@ -180,7 +550,60 @@ pub mod auth {
         let encoding_key = EncodingKey::from_ed_der(der_bytes);
 
         let pair = Ed25519KeyPair::from_pkcs8(der_bytes).unwrap();
-        let decoding_key = DecodingKey::from_ed_der(pair.public_key().as_ref());
-        JwtKeyPair { encoding_key, decoding_key }
+        let public_key = pair.public_key().as_ref().to_vec();
+        let decoding_key = DecodingKey::from_ed_der(&public_key);
+        JwtKeyPair { encoding_key, decoding_key, public_key }
+    }
+
+    /// Loads every `<kid>.der` key in `key_dir`, plus the key at
+    /// `legacy_key_path` under the `legacy` kid when that file exists. The
+    /// active key is `active_kid` when given, otherwise the most recently
+    /// written key file, then the legacy key; it is generated if missing.
+    pub fn load_key_ring(key_dir: &Path, active_kid: Option<&str>, legacy_key_path: Option<&Path>) -> JwtKeyRing {
+        if !key_dir.exists() {
+            fs::create_dir_all(key_dir).expect("Failed to create directory for keys");
+        }
+
+        let mut keys = BTreeMap::new();
+        let mut newest: Option<(SystemTime, u64, String)> = None;
+        for entry in fs::read_dir(key_dir).expect("Failed to read JWT key directory") {
+            let path = entry.expect("Failed to read JWT key directory").path();
+            if path.extension().is_some_and(|ext| ext == "der") {
+                let kid = path.file_stem().unwrap().to_string_lossy().to_string();
+                keys.insert(kid.clone(), generate_ed_dsa_keypair(&get_pkcs8_der(&path)));
+
+                // Files written in the same instant fall back to the number in the kid, so key-10 beats key-9
+                let written = fs::metadata(&path).and_then(|meta| meta.modified()).unwrap_or(UNIX_EPOCH);
+                let number = kid.rsplit(|c: char| !c.is_ascii_digit()).next().and_then(|n| n.parse().ok()).unwrap_or(0);
+                let candidate = (written, number, kid);
+                if newest.as_ref().is_none_or(|current| candidate > *current) {
+                    newest = Some(candidate);
+                }
+            }
+        }
+
+        if let Some(legacy_key_path) = legacy_key_path
+            && legacy_key_path.exists() && !keys.contains_key(LEGACY_KID) {
+            keys.insert(LEGACY_KID.to_string(), generate_ed_dsa_keypair(&get_pkcs8_der(legacy_key_path)));
+        }
+
+        let active_kid = match active_kid {
+            Some(kid) => kid.to_string(),
+            None => match newest {
+                Some((_, _, kid)) => kid,
+                None if keys.contains_key(LEGACY_KID) => LEGACY_KID.to_string(),
+                None => {
+                    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+                    format!("key-{}", now)
+                }
+            }
+        };
+
+        if !keys.contains_key(&active_kid) {
+            let key_path = key_dir.join(format!("{}.der", active_kid));
+            keys.insert(active_kid.clone(), generate_ed_dsa_keypair(&get_pkcs8_der(&key_path)));
+        }
+
+        JwtKeyRing { active_kid, keys }
     }
 }
\ No newline at end of file